@@ -0,0 +1,668 @@
+//! Gen1 CoIoT: CoAP packets that Gen1 devices multicast on UDP 5683.
+//!
+//! Devices send their sensor values as `/cit/s` every few seconds and right
+//! after a change; sleeping battery sensors send them when they wake up. The
+//! values are only ids, described by the `/cit/d` document each device serves
+//! over unicast CoAP. Decoded values are turned into the same document as the
+//! HTTP `/status` endpoint so that they go through the existing Gen1 metrics.
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+use crate::shelly::Gen1Family;
+
+pub const COIOT_PORT: u16 = 5683;
+const MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 1, 187);
+
+/// Minimum time between two `/cit/d` requests to the same device.
+const DESCRIPTION_RETRY: Duration = Duration::from_secs(60);
+
+/// Devices tracked at most; anyone on the network can send packets from
+/// any number of addresses.
+const MAX_DEVICES: usize = 256;
+
+/// CoAP option carrying `<model>#<device id>#<CoIoT version>`.
+const OPTION_DEVICE_ID: u16 = 3332;
+const OPTION_URI_PATH: u16 = 11;
+const CODE_CONTENT: u8 = 0x45;
+
+/// The parts of a CoAP message that CoIoT uses.
+#[derive(Debug, PartialEq)]
+struct CoapMessage {
+    code: u8,
+    uri_path: Vec<String>,
+    device: Option<String>,
+    payload: Vec<u8>,
+}
+
+impl CoapMessage {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let (header, rest) = bytes.split_first_chunk::<4>()?;
+        if header[0] >> 6 != 1 {
+            return None;
+        }
+        let token_length = usize::from(header[0] & 0x0f);
+        let mut rest = rest.get(token_length..)?;
+
+        let mut message = Self {
+            code: header[1],
+            uri_path: Vec::new(),
+            device: None,
+            payload: Vec::new(),
+        };
+
+        let mut number = 0u16;
+        while let Some((&byte, tail)) = rest.split_first() {
+            if byte == 0xff {
+                message.payload = tail.to_vec();
+                break;
+            }
+            rest = tail;
+            let delta = option_nibble(byte >> 4, &mut rest)?;
+            let length = usize::from(option_nibble(byte & 0x0f, &mut rest)?);
+            number = number.checked_add(delta)?;
+
+            let value = rest.get(..length)?;
+            rest = &rest[length..];
+            let value = String::from_utf8_lossy(value).into_owned();
+            match number {
+                OPTION_URI_PATH => message.uri_path.push(value),
+                OPTION_DEVICE_ID => message.device = Some(value),
+                _ => {}
+            }
+        }
+
+        Some(message)
+    }
+
+    /// A non-confirmable GET for `/cit/d`.
+    fn description_request(message_id: u16) -> Vec<u8> {
+        let mut request = vec![0x50, 0x01];
+        request.extend(message_id.to_be_bytes());
+        request.extend([0xb3, b'c', b'i', b't', 0x01, b'd']);
+        request
+    }
+}
+
+/// Decode an option delta or length, which may continue in the next bytes.
+fn option_nibble(nibble: u8, rest: &mut &[u8]) -> Option<u16> {
+    match nibble {
+        13 => {
+            let (&byte, tail) = rest.split_first()?;
+            *rest = tail;
+            Some(u16::from(byte) + 13)
+        }
+        14 => {
+            let (bytes, tail) = rest.split_first_chunk::<2>()?;
+            *rest = tail;
+            u16::from_be_bytes(*bytes).checked_add(269)
+        }
+        15 => None,
+        n => Some(u16::from(n)),
+    }
+}
+
+/// The `/cit/d` document: the blocks (channels) of a device and its sensors.
+#[derive(Debug, Deserialize)]
+pub struct CoiotDescription {
+    #[serde(rename = "blk")]
+    blocks: Vec<CoiotBlock>,
+    #[serde(rename = "sen")]
+    sensors: Vec<CoiotSensor>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CoiotBlock {
+    #[serde(rename = "I")]
+    id: u32,
+    /// e.g. `relay_0`, `light_1`, `sensor_0` or `device`
+    #[serde(rename = "D")]
+    description: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CoiotSensor {
+    #[serde(rename = "I")]
+    id: u32,
+    /// Sensor type: `S` state, `P` power, `E` energy, `T` temperature, ...
+    #[serde(rename = "T")]
+    kind: String,
+    #[serde(rename = "D")]
+    description: String,
+    #[serde(rename = "U")]
+    unit: Option<String>,
+    /// Value range, optionally followed by the value meaning "unavailable"
+    #[serde(rename = "R", default)]
+    range: Value,
+    /// Block the sensor belongs to
+    #[serde(rename = "L")]
+    block: Value,
+}
+
+impl CoiotSensor {
+    /// Whether `value` is the marker for a missing reading, e.g. `999` for
+    /// an add-on temperature probe that is not connected.
+    fn is_unavailable(&self, value: f64) -> bool {
+        self.range
+            .get(1)
+            .and_then(|marker| marker.as_str()?.parse::<f64>().ok())
+            == Some(value)
+    }
+}
+
+impl CoiotDescription {
+    /// Translate the `G` values of a `/cit/s` packet into a Gen1 `/status`
+    /// document. The model decides whether temperatures on a `sensor` block
+    /// come from a battery sensor or from a temperature add-on.
+    pub fn status(&self, model: &str, values: &[(u32, u32, Value)]) -> Value {
+        let blocks: HashMap<u32, &str> = self
+            .blocks
+            .iter()
+            .map(|block| (block.id, block.description.as_str()))
+            .collect();
+        let sensors: HashMap<u32, &CoiotSensor> = self
+            .sensors
+            .iter()
+            .map(|sensor| (sensor.id, sensor))
+            .collect();
+        // Only relays and lights take the temperature add-on
+        let add_on = matches!(
+            Gen1Family::from_model(model),
+            Some(Gen1Family::Relay | Gen1Family::Light)
+        );
+
+        let mut status = Map::new();
+        let mut channels: BTreeMap<(&str, usize), Map<String, Value>> = BTreeMap::new();
+        let mut ext_temperature = Map::new();
+
+        for (_, id, value) in values {
+            let (Some(sensor), Some(number)) = (sensors.get(id), value.as_f64()) else {
+                continue;
+            };
+            if sensor.is_unavailable(number) {
+                continue;
+            }
+            let block = sensor
+                .block
+                .as_u64()
+                .and_then(|id| blocks.get(&(id as u32)))
+                .copied()
+                .unwrap_or("device");
+            let (kind, index) = match block.rsplit_once('_') {
+                Some((kind, index)) => (kind, index.parse().unwrap_or(0)),
+                None => (block, 0),
+            };
+            let celsius = sensor.unit.as_deref() == Some("C");
+
+            match (sensor.kind.as_str(), sensor.description.as_str(), kind) {
+                ("S", "output", "relay" | "light") => {
+                    channels
+                        .entry((kind, index))
+                        .or_default()
+                        .insert("ison".to_string(), json!(number == 1.0));
+                }
+                ("P", "power", "relay" | "light") => {
+                    channels
+                        .entry(("meter", index))
+                        .or_default()
+                        .insert("power".to_string(), json!(number));
+                }
+                ("E", "energy", "relay" | "light") => {
+                    channels
+                        .entry(("meter", index))
+                        .or_default()
+                        .insert("total".to_string(), json!(number));
+                }
+                ("T", "deviceTemp", _) if celsius => {
+                    status.insert("temperature".to_string(), json!(number));
+                }
+                ("T", "extTemp", _) if celsius && add_on => {
+                    // Probes are numbered by sensor id: 31xx is probe 0, 32xx probe 1, ...
+                    let index = (sensor.id / 100).saturating_sub(31).to_string();
+                    ext_temperature.insert(index, json!({"tC": number}));
+                }
+                ("T", "extTemp" | "temp", _) if celsius => {
+                    status.insert(
+                        "tmp".to_string(),
+                        json!({"value": number, "units": "C", "tC": number, "is_valid": true}),
+                    );
+                }
+                ("H", "humidity", _) => {
+                    status.insert(
+                        "hum".to_string(),
+                        json!({"value": number, "is_valid": true}),
+                    );
+                }
+                ("L", "luminosity", _) => {
+                    status.insert(
+                        "lux".to_string(),
+                        json!({"value": number, "is_valid": true}),
+                    );
+                }
+                ("B", "battery", _) => {
+                    status.insert("bat".to_string(), json!({"value": number}));
+                }
+                _ => {}
+            }
+        }
+
+        for (section, kind) in [
+            ("relays", "relay"),
+            ("lights", "light"),
+            ("meters", "meter"),
+        ] {
+            let entries: Vec<Value> = channels
+                .iter()
+                .filter(|((k, _), _)| *k == kind)
+                .map(|(_, entry)| Value::Object(entry.clone()))
+                .collect();
+            if !entries.is_empty() {
+                status.insert(section.to_string(), Value::Array(entries));
+            }
+        }
+        if !ext_temperature.is_empty() {
+            status.insert(
+                "ext_temperature".to_string(),
+                Value::Object(ext_temperature),
+            );
+        }
+
+        Value::Object(status)
+    }
+}
+
+#[derive(Debug, Default)]
+struct CoiotDevice {
+    model: String,
+    description: Option<CoiotDescription>,
+    description_requested: Option<Instant>,
+    /// Values of the latest `/cit/s`, kept until they can be described
+    values: Vec<(u32, u32, Value)>,
+    /// Decoded status not yet taken by a poll
+    status: Option<Value>,
+}
+
+/// Statuses received over CoIoT, keyed by the address of the device.
+#[derive(Debug, Default)]
+pub struct CoiotListener {
+    devices: Mutex<HashMap<IpAddr, CoiotDevice>>,
+}
+
+/// Bind the CoIoT port and join the multicast group.
+pub async fn bind() -> Result<UdpSocket> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, COIOT_PORT)).await?;
+    socket.join_multicast_v4(MULTICAST_GROUP, Ipv4Addr::UNSPECIFIED)?;
+    Ok(socket)
+}
+
+impl CoiotListener {
+    /// Addresses and models of the devices heard from so far that answered
+    /// with a valid `/cit/d` description.
+    pub fn devices(&self) -> Vec<(IpAddr, String)> {
+        self.devices
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, device)| device.description.is_some())
+            .map(|(ip, device)| (*ip, device.model.clone()))
+            .collect()
+    }
+
+    /// The newest status of the device at `ip`, if one arrived since the
+    /// previous call.
+    pub fn take_status(&self, ip: IpAddr) -> Option<Value> {
+        self.devices.lock().unwrap().get_mut(&ip)?.status.take()
+    }
+
+    /// Receive packets until the socket fails.
+    pub async fn run(self: Arc<Self>, socket: UdpSocket) {
+        info!("Listening for CoIoT packets on {:?}", socket.local_addr());
+        let mut buffer = vec![0u8; 4096];
+        let mut message_id = 0u16;
+
+        loop {
+            let (length, source) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("CoIoT listener stopped: {}", e);
+                    return;
+                }
+            };
+            let Some(message) = CoapMessage::parse(&buffer[..length]) else {
+                debug!("Ignoring malformed CoAP packet from {}", source);
+                continue;
+            };
+
+            if self.receive(source, message) {
+                message_id = message_id.wrapping_add(1);
+                let request = CoapMessage::description_request(message_id);
+                if let Err(e) = socket.send_to(&request, source).await {
+                    debug!("Failed to request CoIoT description from {}: {}", source, e);
+                }
+            }
+        }
+    }
+
+    /// Handle one packet; returns whether the device's description should
+    /// be requested.
+    fn receive(&self, source: SocketAddr, message: CoapMessage) -> bool {
+        let mut devices = self.devices.lock().unwrap();
+
+        if message.uri_path == ["cit", "s"] {
+            #[derive(Deserialize)]
+            struct Values {
+                #[serde(rename = "G")]
+                values: Vec<(u32, u32, Value)>,
+            }
+
+            let Ok(Values { values }) = serde_json::from_slice(&message.payload) else {
+                debug!("Ignoring malformed CoIoT status from {}", source);
+                return false;
+            };
+
+            if !devices.contains_key(&source.ip()) && devices.len() >= MAX_DEVICES {
+                debug!(
+                    "Ignoring CoIoT device {}, already tracking {}",
+                    source, MAX_DEVICES
+                );
+                return false;
+            }
+            let device = devices.entry(source.ip()).or_default();
+            if let Some(model) = message.device.as_deref().and_then(|d| d.split('#').next()) {
+                device.model = model.to_string();
+            }
+            device.values = values;
+            return match &device.description {
+                Some(description) => {
+                    device.status = Some(description.status(&device.model, &device.values));
+                    false
+                }
+                None => {
+                    let due = device
+                        .description_requested
+                        .is_none_or(|at| at.elapsed() >= DESCRIPTION_RETRY);
+                    if due {
+                        device.description_requested = Some(Instant::now());
+                    }
+                    due
+                }
+            };
+        }
+
+        if message.code == CODE_CONTENT
+            && let Some(device) = devices.get_mut(&source.ip())
+        {
+            match serde_json::from_slice::<CoiotDescription>(&message.payload) {
+                Ok(description) => {
+                    debug!("Received CoIoT description from {}", source);
+                    if !device.values.is_empty() {
+                        device.status = Some(description.status(&device.model, &device.values));
+                    }
+                    device.description = Some(description);
+                }
+                Err(e) => debug!("Malformed CoIoT description from {}: {}", source, e),
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shelly::{
+        ShellyClient, ShellyError, ShellyGen1Status, ShellyGeneration, ShellyStatus,
+    };
+
+    const DESCRIPTION_1PM: &str = r#"{"blk":[{"I":1,"D":"relay_0"},{"I":2,"D":"sensor_0"},{"I":3,"D":"device"}],
+        "sen":[{"I":9103,"T":"EVC","D":"cfgChanged","R":"U16","L":3},
+               {"I":1101,"T":"S","D":"output","R":"0/1","L":1},
+               {"I":4101,"T":"P","D":"power","U":"W","R":["0/3500","-1"],"L":1},
+               {"I":4103,"T":"E","D":"energy","U":"Wmin","R":["U32","-1"],"L":1},
+               {"I":3101,"T":"T","D":"extTemp","U":"C","R":["-55/125","999"],"L":2},
+               {"I":3102,"T":"T","D":"extTemp","U":"F","R":["-67/257","999"],"L":2},
+               {"I":3201,"T":"T","D":"extTemp","U":"C","R":["-55/125","999"],"L":2},
+               {"I":3104,"T":"T","D":"deviceTemp","U":"C","R":"-40/300","L":3}]}"#;
+
+    const DESCRIPTION_HT: &str = r#"{"blk":[{"I":1,"D":"sensor_0"},{"I":2,"D":"device"}],
+        "sen":[{"I":9102,"T":"EV","D":"wakeupEvent","R":["battery/button/periodic/poweron/sensor/alarm","unknown"],"L":2},
+               {"I":3101,"T":"T","D":"extTemp","U":"C","R":["-55/125","999"],"L":1},
+               {"I":3102,"T":"T","D":"extTemp","U":"F","R":["-67/257","999"],"L":1},
+               {"I":3103,"T":"H","D":"humidity","R":["0/100","999"],"L":1},
+               {"I":3111,"T":"B","D":"battery","R":["0/100","-1"],"L":2}]}"#;
+
+    /// A `/cit/s` packet as multicast by a device.
+    fn status_packet(device: &str, payload: &str) -> Vec<u8> {
+        let mut packet = vec![0x50, 0x1e, 0x12, 0x34];
+        // Uri-Path "cit" / "s"
+        packet.extend([0xb3, b'c', b'i', b't', 0x01, b's']);
+        // Option 3332: delta 3321 from 11, extended with 2 bytes
+        packet.push(0xed);
+        packet.extend((3321u16 - 269).to_be_bytes());
+        packet.push(device.len() as u8 - 13);
+        packet.extend(device.as_bytes());
+        packet.push(0xff);
+        packet.extend(payload.as_bytes());
+        packet
+    }
+
+    #[test]
+    fn test_parse_coap_message() {
+        let message =
+            CoapMessage::parse(&status_packet("SHSW-PM#A4CF12F45678#2", r#"{"G":[]}"#)).unwrap();
+        assert_eq!(message.code, 0x1e);
+        assert_eq!(message.uri_path, ["cit", "s"]);
+        assert_eq!(message.device.as_deref(), Some("SHSW-PM#A4CF12F45678#2"));
+        assert_eq!(message.payload, br#"{"G":[]}"#);
+
+        let request = CoapMessage::parse(&CoapMessage::description_request(7)).unwrap();
+        assert_eq!(request.code, 0x01);
+        assert_eq!(request.uri_path, ["cit", "d"]);
+        assert!(request.payload.is_empty());
+
+        assert!(CoapMessage::parse(b"\x50").is_none());
+        assert!(CoapMessage::parse(b"GET / HTTP/1.1").is_none());
+    }
+
+    #[test]
+    fn test_describe_relay_status() {
+        let description: CoiotDescription = serde_json::from_str(DESCRIPTION_1PM).unwrap();
+        let values = [
+            (0, 9103, json!(3)),
+            (0, 1101, json!(1)),
+            (0, 4101, json!(41.7)),
+            (0, 4103, json!(123456)),
+            (0, 3101, json!(21.25)),
+            (0, 3102, json!(70.25)),
+            (0, 3201, json!(999)),
+            (0, 3104, json!(48.6)),
+        ];
+
+        let status: ShellyGen1Status =
+            serde_json::from_value(description.status("SHSW-PM", &values)).unwrap();
+        assert!(status.relays.unwrap().get(0).unwrap().ison);
+        let meters = status.meters.unwrap();
+        let meter = meters.get(0).unwrap();
+        assert_eq!(meter.power, 41.7);
+        assert_eq!(meter.total, Some(123456.0));
+        assert_eq!(status.temperature, Some(48.6));
+        // The second probe is not connected
+        let ext_temperature = status.ext_temperature.unwrap();
+        assert_eq!(ext_temperature.len(), 1);
+        assert_eq!(ext_temperature["0"].t_c, Some(21.25));
+        assert!(status.tmp.is_none());
+    }
+
+    #[test]
+    fn test_describe_second_probe_only() {
+        let description: CoiotDescription = serde_json::from_str(DESCRIPTION_1PM).unwrap();
+        let values = [
+            (0, 3101, json!(999)),
+            (0, 3102, json!(999)),
+            (0, 3201, json!(18.5)),
+        ];
+
+        let status: ShellyGen1Status =
+            serde_json::from_value(description.status("SHSW-PM", &values)).unwrap();
+        let ext_temperature = status.ext_temperature.unwrap();
+        assert_eq!(ext_temperature.len(), 1);
+        assert_eq!(ext_temperature["1"].t_c, Some(18.5));
+    }
+
+    #[test]
+    fn test_describe_sensor_status() {
+        let description: CoiotDescription = serde_json::from_str(DESCRIPTION_HT).unwrap();
+        let values = [
+            (0, 9102, json!(["periodic"])),
+            (0, 3101, json!(22.5)),
+            (0, 3102, json!(72.5)),
+            (0, 3103, json!(51.5)),
+            (0, 3111, json!(87)),
+        ];
+
+        let status: ShellyGen1Status =
+            serde_json::from_value(description.status("SHHT-1", &values)).unwrap();
+        assert_eq!(status.tmp.unwrap().celsius(), Some(22.5));
+        assert_eq!(status.hum.unwrap().valid_value(), Some(51.5));
+        assert_eq!(status.bat.unwrap().value, Some(87.0));
+        assert!(status.relays.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_listener_requests_description() {
+        let listener = Arc::new(CoiotListener::default());
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        tokio::spawn(listener.clone().run(socket));
+
+        // Play the device: send a status, then answer the description request
+        let device = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let packet = status_packet(
+            "SHHT-1#A4CF12F45678#2",
+            r#"{"G":[[0,3101,19.5],[0,3103,60],[0,3111,95]]}"#,
+        );
+        device.send_to(&packet, address).await.unwrap();
+
+        let mut buffer = [0u8; 512];
+        let (length, _) =
+            tokio::time::timeout(Duration::from_secs(5), device.recv_from(&mut buffer))
+                .await
+                .unwrap()
+                .unwrap();
+        let request = CoapMessage::parse(&buffer[..length]).unwrap();
+        assert_eq!(request.uri_path, ["cit", "d"]);
+
+        let mut response = vec![0x60, CODE_CONTENT, 0x00, 0x01, 0xff];
+        response.extend(DESCRIPTION_HT.as_bytes());
+        device.send_to(&response, address).await.unwrap();
+
+        let client = ShellyClient::coiot_only("http://127.0.0.1", listener.clone()).unwrap();
+        let mut status = None;
+        for _ in 0..50 {
+            if let Ok(ShellyStatus::Gen1(gen1)) = client.get_status().await {
+                status = Some(gen1);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let status = status.expect("no CoIoT status decoded");
+        assert_eq!(status.tmp.as_ref().unwrap().celsius(), Some(19.5));
+        assert_eq!(status.bat.as_ref().unwrap().value, Some(95.0));
+        assert_eq!(
+            listener.devices(),
+            [("127.0.0.1".parse().unwrap(), "SHHT-1".to_string())]
+        );
+
+        // Nothing new since the previous poll
+        assert!(matches!(
+            client.get_status().await,
+            Err(ShellyError::NotPushed)
+        ));
+    }
+
+    #[test]
+    fn test_device_limit() {
+        let listener = CoiotListener::default();
+        let packet = status_packet("SHHT-1#A4CF12F45678#2", r#"{"G":[[0,3101,19.5]]}"#);
+
+        for n in 0..=MAX_DEVICES as u32 {
+            let source = SocketAddr::new(IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + n)), 5683);
+            let request = listener.receive(source, CoapMessage::parse(&packet).unwrap());
+            assert_eq!(request, n < MAX_DEVICES as u32);
+        }
+        assert_eq!(listener.devices.lock().unwrap().len(), MAX_DEVICES);
+
+        // Only devices that described themselves are added for polling
+        assert!(listener.devices().is_empty());
+        let source = SocketAddr::new(IpAddr::V4(Ipv4Addr::from(0x0a00_0000)), 5683);
+        let mut response = vec![0x60, CODE_CONTENT, 0x00, 0x01, 0xff];
+        response.extend(DESCRIPTION_HT.as_bytes());
+        listener.receive(source, CoapMessage::parse(&response).unwrap());
+        assert_eq!(
+            listener.devices(),
+            [("10.0.0.0".parse().unwrap(), "SHHT-1".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_configured_host_keeps_polling() {
+        use std::time::Duration;
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let listener = Arc::new(CoiotListener::default());
+        let source: SocketAddr = "127.0.0.1:5683".parse().unwrap();
+        let push = |payload: &str| {
+            let packet = status_packet("SHSW-PM#A4CF12F45678#2", payload);
+            listener.receive(source, CoapMessage::parse(&packet).unwrap());
+        };
+        push(r#"{"G":[[0,1101,1],[0,4101,41.7]]}"#);
+        let mut response = vec![0x60, CODE_CONTENT, 0x00, 0x01, 0xff];
+        response.extend(DESCRIPTION_1PM.as_bytes());
+        listener.receive(source, CoapMessage::parse(&response).unwrap());
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/status"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"relays": [{"ison": false}], "wifi_sta": {"rssi": -58}, "uptime": 3600}"#,
+            ))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen1,
+        )
+        .unwrap();
+        assert!(client.enable_coiot(listener.clone()));
+
+        // A reachable device is polled for its full status
+        let Ok(ShellyStatus::Gen1(status)) = client.get_status().await else {
+            panic!("no status");
+        };
+        assert_eq!(status.uptime, Some(3600));
+        assert_eq!(status.wifi_sta.unwrap().rssi, Some(-58));
+        assert!(!status.relays.unwrap().get(0).unwrap().ison);
+
+        // Once it stops answering, the latest CoIoT packet stands in for it
+        push(r#"{"G":[[0,1101,1],[0,4101,41.7]]}"#);
+        let Ok(ShellyStatus::Gen1(status)) = client.get_status().await else {
+            panic!("no pushed status");
+        };
+        assert!(status.uptime.is_none());
+        assert!(status.relays.unwrap().get(0).unwrap().ison);
+        assert!(client.get_status().await.is_err());
+    }
+}
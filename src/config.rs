@@ -1,4 +1,6 @@
+use anyhow::{Result, anyhow};
 use clap::Parser;
+use std::collections::HashMap;
 use std::time::Duration;
 
 #[derive(Parser, Debug, Clone)]
@@ -47,6 +49,45 @@ pub struct Config {
     /// Discovery interval in seconds (when discovery is enabled)
     #[arg(long, env = "SHELLY_DISCOVERY_INTERVAL", default_value = "300")]
     pub discovery_interval: u64,
+
+    /// Comma-separated list of battery powered hosts that are only awake periodically
+    #[arg(long, env = "SHELLY_SLEEPY_HOSTS", value_delimiter = ',')]
+    pub sleepy_hosts: Option<Vec<String>>,
+
+    /// Seconds a sleepy device may stay silent before it is marked down
+    #[arg(long, env = "SHELLY_SLEEPY_MAX_SILENCE", default_value = "43200")]
+    pub sleepy_max_silence: u64,
+
+    /// Comma-separated unit factors for inputs in counter mode, as <host>/input:<id>=<factor>
+    /// (e.g. http://192.168.1.50/input:100=0.001 for a water meter emitting a pulse per liter)
+    #[arg(long, env = "SHELLY_PULSE_COUNTERS", value_delimiter = ',')]
+    pub pulse_counters: Option<Vec<String>>,
+
+    /// Comma-separated list of Gen2+ hosts to keep a WebSocket connection to, so that
+    /// status changes and input events are pushed instead of polled
+    #[arg(long, env = "SHELLY_WEBSOCKET_HOSTS", value_delimiter = ',')]
+    pub websocket_hosts: Option<Vec<String>>,
+
+    /// Comma-separated ids of Gen2+ devices allowed to connect to /ws with an outbound
+    /// WebSocket (e.g. shellyplusht-08b61fcb7f3c); the endpoint is only served when set
+    #[arg(long, env = "SHELLY_INBOUND_DEVICES", value_delimiter = ',')]
+    pub inbound_devices: Option<Vec<String>>,
+
+    /// Listen for CoIoT packets multicast by Gen1 devices on UDP 5683
+    #[arg(long, env = "SHELLY_COIOT", default_value = "false")]
+    pub coiot: bool,
+
+    /// MQTT broker (host or host:port) to ingest the status devices publish to
+    #[arg(long, env = "SHELLY_MQTT_BROKER")]
+    pub mqtt_broker: Option<String>,
+
+    /// MQTT broker username
+    #[arg(long, env = "SHELLY_MQTT_USERNAME")]
+    pub mqtt_username: Option<String>,
+
+    /// MQTT broker password
+    #[arg(long, env = "SHELLY_MQTT_PASSWORD")]
+    pub mqtt_password: Option<String>,
 }
 
 impl Config {
@@ -66,6 +107,44 @@ impl Config {
         Duration::from_secs(self.discovery_interval)
     }
 
+    pub fn sleepy_max_silence_duration(&self) -> Duration {
+        Duration::from_secs(self.sleepy_max_silence)
+    }
+
+    pub fn is_sleepy(&self, host: &str) -> bool {
+        self.sleepy_hosts
+            .as_ref()
+            .is_some_and(|hosts| hosts.iter().any(|h| h == host))
+    }
+
+    pub fn use_websocket(&self, host: &str) -> bool {
+        self.websocket_hosts
+            .as_ref()
+            .is_some_and(|hosts| hosts.iter().any(|h| h == host))
+    }
+
+    /// Parse `pulse_counters` into unit factors keyed by (host, input id).
+    pub fn pulse_factors(&self) -> Result<HashMap<(String, String), f64>> {
+        let mut factors = HashMap::new();
+
+        for entry in self.pulse_counters.iter().flatten() {
+            let invalid = || anyhow!("Invalid pulse counter '{}'", entry);
+
+            let (target, factor) = entry.rsplit_once('=').ok_or_else(invalid)?;
+            let (host, component) = target.rsplit_once('/').ok_or_else(invalid)?;
+            let id = component.strip_prefix("input:").ok_or_else(invalid)?;
+            let factor: f64 = factor.trim().parse().map_err(|_| invalid())?;
+            // Counters only ever go up by a finite amount
+            if !factor.is_finite() || factor < 0.0 {
+                return Err(invalid());
+            }
+
+            factors.insert((host.to_string(), id.to_string()), factor);
+        }
+
+        Ok(factors)
+    }
+
     pub fn auth(&self) -> Option<(String, String)> {
         self.password
             .as_ref()
@@ -81,6 +160,7 @@ impl Config {
                     // Extract IP or hostname from URL
                     host.trim_start_matches("http://")
                         .trim_start_matches("https://")
+                        .trim_start_matches("mqtt://")
                         .split(':')
                         .next()
                         .unwrap_or("unknown")
@@ -90,6 +170,7 @@ impl Config {
                 // Extract IP or hostname from URL
                 host.trim_start_matches("http://")
                     .trim_start_matches("https://")
+                    .trim_start_matches("mqtt://")
                     .split(':')
                     .next()
                     .unwrap_or("unknown")
@@ -121,6 +202,15 @@ mod tests {
             log_level: "info".to_string(),
             enable_discovery: false,
             discovery_interval: 300,
+            sleepy_hosts: None,
+            sleepy_max_silence: 43200,
+            pulse_counters: None,
+            websocket_hosts: None,
+            inbound_devices: None,
+            coiot: false,
+            mqtt_broker: None,
+            mqtt_username: None,
+            mqtt_password: None,
         };
 
         assert_eq!(config.metrics_bind_address(), "0.0.0.0:9925");
@@ -140,6 +230,15 @@ mod tests {
             log_level: "info".to_string(),
             enable_discovery: false,
             discovery_interval: 600,
+            sleepy_hosts: None,
+            sleepy_max_silence: 43200,
+            pulse_counters: None,
+            websocket_hosts: None,
+            inbound_devices: None,
+            coiot: false,
+            mqtt_broker: None,
+            mqtt_username: None,
+            mqtt_password: None,
         };
 
         assert_eq!(config.poll_interval_duration(), Duration::from_secs(45));
@@ -164,6 +263,15 @@ mod tests {
             log_level: "info".to_string(),
             enable_discovery: false,
             discovery_interval: 300,
+            sleepy_hosts: None,
+            sleepy_max_silence: 43200,
+            pulse_counters: None,
+            websocket_hosts: None,
+            inbound_devices: None,
+            coiot: false,
+            mqtt_broker: None,
+            mqtt_username: None,
+            mqtt_password: None,
         };
 
         assert!(config_without_password.auth().is_none());
@@ -180,6 +288,15 @@ mod tests {
             log_level: "info".to_string(),
             enable_discovery: false,
             discovery_interval: 300,
+            sleepy_hosts: None,
+            sleepy_max_silence: 43200,
+            pulse_counters: None,
+            websocket_hosts: None,
+            inbound_devices: None,
+            coiot: false,
+            mqtt_broker: None,
+            mqtt_username: None,
+            mqtt_password: None,
         };
 
         assert_eq!(
@@ -205,6 +322,15 @@ mod tests {
             log_level: "info".to_string(),
             enable_discovery: false,
             discovery_interval: 300,
+            sleepy_hosts: None,
+            sleepy_max_silence: 43200,
+            pulse_counters: None,
+            websocket_hosts: None,
+            inbound_devices: None,
+            coiot: false,
+            mqtt_broker: None,
+            mqtt_username: None,
+            mqtt_password: None,
         };
 
         let names = config_with_names.get_device_names();
@@ -228,6 +354,7 @@ mod tests {
             hosts: vec![
                 "http://192.168.1.100".to_string(),
                 "https://shelly.local".to_string(),
+                "mqtt://shellyplus1pm-a8032ab12345".to_string(),
             ],
             names: None,
             username: "admin".to_string(),
@@ -239,10 +366,19 @@ mod tests {
             log_level: "info".to_string(),
             enable_discovery: false,
             discovery_interval: 300,
+            sleepy_hosts: None,
+            sleepy_max_silence: 43200,
+            pulse_counters: None,
+            websocket_hosts: None,
+            inbound_devices: None,
+            coiot: false,
+            mqtt_broker: None,
+            mqtt_username: None,
+            mqtt_password: None,
         };
 
         let names = config_without_names.get_device_names();
-        assert_eq!(names.len(), 2);
+        assert_eq!(names.len(), 3);
         assert_eq!(
             names[0],
             (
@@ -257,6 +393,43 @@ mod tests {
                 "shelly.local".to_string()
             )
         );
+        assert_eq!(names[2].1, "shellyplus1pm-a8032ab12345");
+    }
+
+    #[test]
+    fn test_sleepy_hosts() {
+        let config = Config {
+            hosts: vec![
+                "http://192.168.1.100".to_string(),
+                "http://192.168.1.101".to_string(),
+            ],
+            names: None,
+            username: "admin".to_string(),
+            password: None,
+            port: 9925,
+            bind: "0.0.0.0".to_string(),
+            poll_interval: 30,
+            http_timeout: 10,
+            log_level: "info".to_string(),
+            enable_discovery: false,
+            discovery_interval: 300,
+            sleepy_hosts: Some(vec!["http://192.168.1.101".to_string()]),
+            sleepy_max_silence: 7200,
+            pulse_counters: None,
+            websocket_hosts: None,
+            inbound_devices: None,
+            coiot: false,
+            mqtt_broker: None,
+            mqtt_username: None,
+            mqtt_password: None,
+        };
+
+        assert!(!config.is_sleepy("http://192.168.1.100"));
+        assert!(config.is_sleepy("http://192.168.1.101"));
+        assert_eq!(
+            config.sleepy_max_silence_duration(),
+            Duration::from_secs(7200)
+        );
     }
 
     #[test]
@@ -277,6 +450,15 @@ mod tests {
             log_level: "info".to_string(),
             enable_discovery: false,
             discovery_interval: 300,
+            sleepy_hosts: None,
+            sleepy_max_silence: 43200,
+            pulse_counters: None,
+            websocket_hosts: None,
+            inbound_devices: None,
+            coiot: false,
+            mqtt_broker: None,
+            mqtt_username: None,
+            mqtt_password: None,
         };
 
         let names = config.get_device_names();
@@ -300,4 +482,83 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_pulse_factors() {
+        let mut config = Config {
+            hosts: vec!["http://192.168.1.50".to_string()],
+            names: None,
+            username: "admin".to_string(),
+            password: None,
+            port: 9925,
+            bind: "0.0.0.0".to_string(),
+            poll_interval: 30,
+            http_timeout: 10,
+            log_level: "info".to_string(),
+            enable_discovery: false,
+            discovery_interval: 300,
+            sleepy_hosts: None,
+            sleepy_max_silence: 43200,
+            pulse_counters: Some(vec![
+                "http://192.168.1.50/input:100=0.001".to_string(),
+                "http://192.168.1.51:8080/input:2=10".to_string(),
+            ]),
+            websocket_hosts: None,
+            inbound_devices: None,
+            coiot: false,
+            mqtt_broker: None,
+            mqtt_username: None,
+            mqtt_password: None,
+        };
+
+        let factors = config.pulse_factors().unwrap();
+        assert_eq!(
+            factors[&("http://192.168.1.50".to_string(), "100".to_string())],
+            0.001
+        );
+        assert_eq!(
+            factors[&("http://192.168.1.51:8080".to_string(), "2".to_string())],
+            10.0
+        );
+
+        config.pulse_counters = Some(vec!["http://192.168.1.50/switch:0=2".to_string()]);
+        assert!(config.pulse_factors().is_err());
+
+        for factor in ["-1", "NaN", "inf", "-inf"] {
+            config.pulse_counters = Some(vec![format!("http://192.168.1.50/input:100={factor}")]);
+            assert!(config.pulse_factors().is_err(), "{factor} accepted");
+        }
+    }
+
+    #[test]
+    fn test_websocket_hosts() {
+        let config = Config {
+            hosts: vec![
+                "http://192.168.1.100".to_string(),
+                "http://192.168.1.101".to_string(),
+            ],
+            names: None,
+            username: "admin".to_string(),
+            password: None,
+            port: 9925,
+            bind: "0.0.0.0".to_string(),
+            poll_interval: 30,
+            http_timeout: 10,
+            log_level: "info".to_string(),
+            enable_discovery: false,
+            discovery_interval: 300,
+            sleepy_hosts: None,
+            sleepy_max_silence: 43200,
+            pulse_counters: None,
+            websocket_hosts: Some(vec!["http://192.168.1.101".to_string()]),
+            inbound_devices: None,
+            coiot: false,
+            mqtt_broker: None,
+            mqtt_username: None,
+            mqtt_password: None,
+        };
+
+        assert!(!config.use_websocket("http://192.168.1.100"));
+        assert!(config.use_websocket("http://192.168.1.101"));
+    }
 }
@@ -0,0 +1,292 @@
+//! Endpoint for Gen2+ devices configured with an outbound WebSocket (the
+//! `ws` component), for devices the exporter cannot reach itself: battery
+//! powered sensors that are only awake for a moment, or sites behind NAT.
+
+use anyhow::{Result, bail};
+use axum::extract::State;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::response::Response;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+use crate::DeviceClients;
+use crate::metrics::Metrics;
+use crate::rpc::{Incoming, RpcChannel, RpcFrame};
+use crate::shelly::{DeviceInfo, ShellyClient, ShellyGeneration};
+use crate::websocket::WebSocketTransport;
+
+#[derive(Clone)]
+pub struct InboundState {
+    pub clients: DeviceClients,
+    pub metrics: Arc<Metrics>,
+    pub auth: Option<(String, String)>,
+    /// Ids of the devices allowed to connect
+    pub allowed: Arc<HashSet<String>>,
+}
+
+pub async fn inbound_handler(ws: WebSocketUpgrade, State(state): State<InboundState>) -> Response {
+    ws.on_upgrade(move |socket| async move {
+        if let Err(e) = serve(socket, &state).await {
+            warn!("Inbound WebSocket connection failed: {}", e);
+        }
+    })
+}
+
+/// Handle one device connection. The device is identified by the `src` of
+/// its first frame and registered as host `ws://<src>`, replacing the client
+/// of an earlier connection. Devices that are not allowed are disconnected.
+async fn serve(mut socket: WebSocket, state: &InboundState) -> Result<()> {
+    let transport = Arc::new(WebSocketTransport::inbound());
+    let mut channel = RpcChannel::new(state.auth.clone());
+    let mut device_id: Option<String> = None;
+
+    while let Some(message) = socket.recv().await {
+        let text = match message? {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+
+        let frame: RpcFrame = match serde_json::from_str(&text) {
+            Ok(frame) => frame,
+            Err(e) => {
+                debug!("Ignoring malformed inbound frame: {}", e);
+                continue;
+            }
+        };
+
+        let id = match &device_id {
+            Some(id) => id.clone(),
+            None => {
+                let Some(src) = frame.src.clone() else {
+                    debug!("Ignoring inbound frame without src");
+                    continue;
+                };
+                if !state.allowed.contains(&src) {
+                    bail!("device {} is not allowed to connect", src);
+                }
+                register(state, &src, transport.clone()).await?;
+                transport.set_connected(true);
+                for method in ["Shelly.GetDeviceInfo", "Shelly.GetStatus"] {
+                    socket.send(Message::text(channel.request(method))).await?;
+                }
+                device_id.insert(src).clone()
+            }
+        };
+
+        match channel.receive(frame) {
+            Ok(Incoming::Notification { method, params }) => transport.apply(&method, params),
+            Ok(Incoming::Response { method, result }) => match method.as_str() {
+                "Shelly.GetStatus" => transport.replace(result),
+                "Shelly.GetDeviceInfo" => match serde_json::from_value(result) {
+                    Ok(info) => update_device_info(state, &id, &info).await,
+                    Err(e) => debug!("Malformed device info from {}: {}", id, e),
+                },
+                _ => {}
+            },
+            Ok(Incoming::Retry(request)) => socket.send(Message::text(request)).await?,
+            Ok(Incoming::Ignored) => {}
+            // The device keeps pushing notifications even if it refuses our requests
+            Err(e) => debug!("Request to {} failed: {}", id, e),
+        }
+    }
+
+    transport.set_connected(false);
+    if let Some(id) = device_id {
+        info!("Inbound device {} disconnected", id);
+    }
+    Ok(())
+}
+
+async fn register(
+    state: &InboundState,
+    id: &str,
+    transport: Arc<WebSocketTransport>,
+) -> Result<()> {
+    let mut clients = state.clients.lock().await;
+    let host = inbound_host(id);
+
+    // Keep what an earlier connection learned about the device
+    let (generation, model) = match clients.get(&host) {
+        Some((client, ..)) if !client.is_inbound() => {
+            bail!("{} is already registered as a polled device", host)
+        }
+        Some((client, _, model)) => (client.generation, model.clone()),
+        None => (ShellyGeneration::Gen2, "Unknown".to_string()),
+    };
+
+    let client = ShellyClient::inbound(&host, generation, transport)?;
+    if clients
+        .insert(host.clone(), (client, id.to_string(), model))
+        .is_none()
+    {
+        info!("Added inbound device {} at {}", id, host);
+    }
+
+    Ok(())
+}
+
+/// Key and `host` label of an inbound device, kept apart from polled hosts.
+fn inbound_host(id: &str) -> String {
+    format!("ws://{id}")
+}
+
+async fn update_device_info(state: &InboundState, id: &str, info: &DeviceInfo) {
+    let host = inbound_host(id);
+    state.metrics.update_device_info(id, &host, info);
+
+    if let Some((client, _, model)) = state.clients.lock().await.get_mut(&host) {
+        client.generation = ShellyGeneration::from_rpc_gen(info.generation.into());
+        model.clone_from(&info.model);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shelly::{Component, ShellyStatus};
+    use axum::Router;
+    use axum::routing::any;
+    use futures_util::{SinkExt, StreamExt};
+    use serde_json::{Value, json};
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use tokio::sync::Mutex;
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    #[tokio::test]
+    async fn test_inbound_device() {
+        let state = InboundState {
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            metrics: Arc::new(Metrics::new().unwrap()),
+            auth: None,
+            allowed: Arc::new(HashSet::from(["shellyplusht-08b61fcb7f3c".to_string()])),
+        };
+        let app = Router::new()
+            .route("/ws", any(inbound_handler))
+            .with_state(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/ws", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        // Devices that are not allowed are turned away before registering
+        let (mut stranger, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let hello = json!({"src": "http://192.168.1.100", "method": "NotifyStatus", "params": {}});
+        stranger
+            .send(WsMessage::text(hello.to_string()))
+            .await
+            .unwrap();
+        assert!(!matches!(
+            stranger.next().await,
+            Some(Ok(WsMessage::Text(_)))
+        ));
+        assert!(state.clients.lock().await.is_empty());
+
+        // Play the device: announce ourselves, then answer the exporter's requests
+        let (mut device, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let notify = json!({"src": "shellyplusht-08b61fcb7f3c", "dst": "ws", "method": "NotifyFullStatus",
+            "params": {"ts": 1700000000.0, "temperature:0": {"id": 0, "tC": 21.5, "tF": 70.7}}});
+        device
+            .send(WsMessage::text(notify.to_string()))
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            let Some(Ok(WsMessage::Text(text))) = device.next().await else {
+                panic!("expected a request");
+            };
+            let request: Value = serde_json::from_str(&text).unwrap();
+            let result = match request["method"].as_str().unwrap() {
+                "Shelly.GetDeviceInfo" => json!({
+                    "name": "Bedroom H&T", "id": "shellyplusht-08b61fcb7f3c", "mac": "08B61FCB7F3C",
+                    "model": "SNSN-0013A", "gen": 2, "fw_id": "20231107-162609/1.0.8-ga4b3e93",
+                    "ver": "1.0.8", "app": "PlusHT", "auth_en": false, "auth_domain": null
+                }),
+                "Shelly.GetStatus" => json!({
+                    "temperature:0": {"id": 0, "tC": 22.0, "tF": 71.6},
+                    "humidity:0": {"id": 0, "rh": 48.2}
+                }),
+                other => panic!("unexpected request {other}"),
+            };
+            let response = json!({"id": request["id"], "src": "shellyplusht-08b61fcb7f3c",
+                "dst": request["src"], "result": result});
+            device
+                .send(WsMessage::text(response.to_string()))
+                .await
+                .unwrap();
+        }
+
+        let mut registered = None;
+        for _ in 0..50 {
+            if let Some((client, _, model)) = state
+                .clients
+                .lock()
+                .await
+                .get("ws://shellyplusht-08b61fcb7f3c")
+                && model == "SNSN-0013A"
+                && let Ok(ShellyStatus::Gen2(status)) = client.get_status().await
+                && status.components.contains_key("humidity:0")
+            {
+                registered = Some(status);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let status = registered.expect("inbound device not registered");
+        let Some(Component::Temperature(temperature)) = status.components.get("temperature:0")
+        else {
+            panic!("expected temperature:0");
+        };
+        assert_eq!(temperature.t_c, Some(22.0));
+
+        let output = state.metrics.gather().unwrap();
+        assert!(output.contains(r#"app="PlusHT""#));
+
+        // Once the device goes back to sleep its last status is used one more time
+        device.close(None).await.unwrap();
+        let clients = state.clients.clone();
+        let mut disconnected = false;
+        for _ in 0..50 {
+            let clients = clients.lock().await;
+            let (client, _, _) = &clients["ws://shellyplusht-08b61fcb7f3c"];
+            if client.get_status().await.is_err() {
+                disconnected = true;
+                break;
+            }
+            drop(clients);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(disconnected);
+    }
+
+    #[tokio::test]
+    async fn test_inbound_never_replaces_polled_device() {
+        let state = InboundState {
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            metrics: Arc::new(Metrics::new().unwrap()),
+            auth: None,
+            allowed: Arc::new(HashSet::new()),
+        };
+        let polled = ShellyClient::new(
+            "ws://shellyplus1-a8032ab12345".to_string(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen2,
+        )
+        .unwrap();
+        state.clients.lock().await.insert(
+            "ws://shellyplus1-a8032ab12345".to_string(),
+            (polled, "Garage".to_string(), "SNSW-001X16EU".to_string()),
+        );
+
+        let transport = Arc::new(WebSocketTransport::inbound());
+        assert!(
+            register(&state, "shellyplus1-a8032ab12345", transport)
+                .await
+                .is_err()
+        );
+        let clients = state.clients.lock().await;
+        assert!(!clients["ws://shellyplus1-a8032ab12345"].0.is_inbound());
+    }
+}
@@ -1,10 +1,20 @@
+mod auth;
+mod coiot;
 mod config;
+mod inbound;
 mod metrics;
+mod mqtt;
+mod rpc;
 mod shelly;
+mod websocket;
 
-use anyhow::Result;
-use axum::{Router, routing::get};
+use anyhow::{Result, bail};
+use axum::{
+    Router,
+    routing::{any, get},
+};
 use clap::Parser;
+use futures_util::future::join_all;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::{Mutex, RwLock};
@@ -12,12 +22,16 @@ use tokio::time::interval;
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+use crate::coiot::CoiotListener;
 use crate::config::Config;
+use crate::inbound::{InboundState, inbound_handler};
 use crate::metrics::Metrics;
-use crate::shelly::{ShellyClient, ShellyGeneration};
+use crate::mqtt::MqttListener;
+use crate::shelly::{ShellyClient, ShellyError, ShellyGeneration};
 
 type SharedMetrics = Arc<RwLock<String>>;
 type DeviceClients = Arc<Mutex<HashMap<String, (ShellyClient, String, String)>>>;
+type PendingDevices = Arc<Mutex<Vec<(String, String)>>>;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -39,31 +53,83 @@ async fn main() -> Result<()> {
     info!("Poll interval: {}s", config.poll_interval);
 
     // Initialize metrics
-    let metrics = Arc::new(Metrics::new()?);
+    let metrics = Arc::new(Metrics::new()?.with_pulse_factors(config.pulse_factors()?));
     let shared_metrics: SharedMetrics = Arc::new(RwLock::new(String::new()));
 
+    // Gen1 devices multicast their status over CoIoT
+    let coiot = if config.coiot {
+        let socket = coiot::bind().await?;
+        let listener = Arc::new(CoiotListener::default());
+        tokio::spawn(listener.clone().run(socket));
+        Some(listener)
+    } else {
+        None
+    };
+
+    // Devices that publish their status to an MQTT broker
+    let mqtt = match &config.mqtt_broker {
+        Some(broker) => {
+            let options = mqtt::connect_options(
+                broker,
+                config.mqtt_username.as_deref(),
+                config.mqtt_password.as_deref(),
+            )?;
+            Some(MqttListener::connect(options))
+        }
+        None => None,
+    };
+
     // Initialize device clients
     let device_clients: DeviceClients = Arc::new(Mutex::new(HashMap::new()));
 
+    // Sleepy devices that were asleep during setup and `mqtt://` hosts that
+    // didn't answer over the broker yet, retried in the background
+    let pending_sleepy: PendingDevices = Arc::new(Mutex::new(Vec::new()));
+
     // Setup initial devices
     for (host, name) in config.get_device_names() {
-        match setup_device_client(&host, &config).await {
+        match setup_device_client(
+            &host,
+            &name,
+            &config,
+            &metrics,
+            coiot.as_ref(),
+            mqtt.as_ref(),
+        )
+        .await
+        {
             Ok((client, model)) => {
                 info!("Added device: {} ({}) at {}", name, model, host);
                 let mut clients = device_clients.lock().await;
                 clients.insert(host, (client, name, model));
             }
+            Err(e) if config.is_sleepy(&host) || host.starts_with("mqtt://") => {
+                info!("Device at {} not reachable yet, will retry: {}", host, e);
+                pending_sleepy.lock().await.push((host, name));
+            }
             Err(e) => {
                 warn!("Failed to setup device at {}: {}", host, e);
             }
         }
     }
 
+    tokio::spawn(retry_pending(
+        pending_sleepy.clone(),
+        device_clients.clone(),
+        config.clone(),
+        metrics.clone(),
+        coiot.clone(),
+        mqtt.clone(),
+    ));
+
     // Start polling task
     let poll_metrics = metrics.clone();
     let poll_shared_metrics = shared_metrics.clone();
     let poll_interval = config.poll_interval_duration();
     let poll_clients = device_clients.clone();
+    let poll_config = config.clone();
+    let poll_coiot = coiot.clone();
+    let poll_mqtt = mqtt.clone();
 
     tokio::spawn(async move {
         let mut interval = interval(poll_interval);
@@ -72,12 +138,85 @@ async fn main() -> Result<()> {
         loop {
             interval.tick().await;
 
-            let clients = poll_clients.lock().await;
-            for (host, (client, device_name, model)) in clients.iter() {
-                let generation = match client.generation {
-                    ShellyGeneration::Gen1 => "gen1",
-                    ShellyGeneration::Gen2 => "gen2",
-                };
+            // Devices heard over CoIoT that are not polled yet, such as sleeping
+            // sensors, are exported from their CoIoT packets alone
+            if let Some(listener) = &poll_coiot {
+                let mut clients = poll_clients.lock().await;
+                for (ip, model) in listener.devices() {
+                    let host = format!("http://{ip}");
+                    if clients.contains_key(&host) {
+                        continue;
+                    }
+
+                    pending_sleepy
+                        .lock()
+                        .await
+                        .retain(|(pending, _)| *pending != host);
+                    let name = poll_config
+                        .get_device_names()
+                        .into_iter()
+                        .find(|(configured, _)| *configured == host)
+                        .map_or_else(|| ip.to_string(), |(_, name)| name);
+
+                    match ShellyClient::coiot_only(&host, listener.clone()) {
+                        Ok(client) => {
+                            info!("Added CoIoT device: {} ({}) at {}", name, model, host);
+                            clients.insert(host, (client, name, model));
+                        }
+                        Err(e) => warn!("Failed to add CoIoT device at {}: {}", host, e),
+                    }
+                }
+            }
+
+            // Devices heard over MQTT that are not polled yet are exported from
+            // what they publish alone, keyed by their device id or topic prefix
+            if let Some(listener) = &poll_mqtt {
+                let mut clients = poll_clients.lock().await;
+                for (id, generation, model) in listener.devices() {
+                    let host = format!("mqtt://{id}");
+                    if clients.contains_key(&host) {
+                        continue;
+                    }
+
+                    // Configured hosts that take requests are left to be retried,
+                    // rather than only exported from what they publish
+                    let configured = poll_config.hosts.contains(&host);
+                    if configured && !poll_config.is_sleepy(&host) {
+                        continue;
+                    }
+
+                    pending_sleepy
+                        .lock()
+                        .await
+                        .retain(|(pending, _)| *pending != host);
+                    let name = poll_config
+                        .get_device_names()
+                        .into_iter()
+                        .find(|(configured, _)| *configured == host)
+                        .map_or(id.clone(), |(_, name)| name);
+
+                    match ShellyClient::mqtt_only(&id, generation, listener.clone()) {
+                        Ok(client) => {
+                            info!("Added MQTT device: {} ({}) at {}", name, model, host);
+                            clients.insert(host, (client, name, model));
+                        }
+                        Err(e) => warn!("Failed to add MQTT device at {}: {}", host, e),
+                    }
+                }
+            }
+
+            // Poll a snapshot, so that devices connecting or being added don't
+            // wait for every device's request
+            let clients: Vec<_> = poll_clients
+                .lock()
+                .await
+                .iter()
+                .map(|(host, (client, name, model))| {
+                    (host.clone(), client.clone(), name.clone(), model.clone())
+                })
+                .collect();
+            for (host, client, device_name, model) in &clients {
+                let generation = client.generation.label();
 
                 match client.get_status().await {
                     Ok(status) => {
@@ -97,7 +236,32 @@ async fn main() -> Result<()> {
                             continue;
                         }
                     }
+                    // Push-only devices are only heard from when they wake up or connect
+                    Err(ShellyError::NotPushed) => {
+                        debug!("Nothing pushed by {} ({})", device_name, host);
+                        poll_metrics.mark_device_silent(
+                            device_name,
+                            host,
+                            model,
+                            generation,
+                            poll_config.sleepy_max_silence_duration(),
+                        );
+                    }
+                    Err(e) if poll_config.is_sleepy(host) => {
+                        debug!(
+                            "Sleepy device {} ({}) not reachable: {}",
+                            device_name, host, e
+                        );
+                        poll_metrics.mark_device_silent(
+                            device_name,
+                            host,
+                            model,
+                            generation,
+                            poll_config.sleepy_max_silence_duration(),
+                        );
+                    }
                     Err(e) => {
+                        poll_metrics.record_scrape_error(device_name, host, &e);
                         warn!(
                             "Failed to fetch status from {} ({}): {}",
                             device_name, host, e
@@ -107,8 +271,6 @@ async fn main() -> Result<()> {
                 }
             }
 
-            drop(clients);
-
             // Gather all metrics
             match poll_metrics.gather() {
                 Ok(metrics_text) => {
@@ -127,6 +289,9 @@ async fn main() -> Result<()> {
         let discovery_interval = config.discovery_interval_duration();
         let discovery_clients = device_clients.clone();
         let discovery_config = config.clone();
+        let discovery_metrics = metrics.clone();
+        let discovery_coiot = coiot.clone();
+        let discovery_mqtt = mqtt.clone();
 
         tokio::spawn(async move {
             let mut interval = interval(discovery_interval);
@@ -144,15 +309,24 @@ async fn main() -> Result<()> {
                             if let std::collections::hash_map::Entry::Vacant(e) =
                                 clients.entry(device_url.clone())
                             {
-                                match setup_device_client(&device_url, &discovery_config).await {
+                                let name = device_url
+                                    .trim_start_matches("http://")
+                                    .trim_start_matches("https://")
+                                    .split(':')
+                                    .next()
+                                    .unwrap_or("unknown")
+                                    .to_string();
+                                match setup_device_client(
+                                    &device_url,
+                                    &name,
+                                    &discovery_config,
+                                    &discovery_metrics,
+                                    discovery_coiot.as_ref(),
+                                    discovery_mqtt.as_ref(),
+                                )
+                                .await
+                                {
                                     Ok((client, model)) => {
-                                        let name = device_url
-                                            .trim_start_matches("http://")
-                                            .trim_start_matches("https://")
-                                            .split(':')
-                                            .next()
-                                            .unwrap_or("unknown")
-                                            .to_string();
                                         info!(
                                             "Added discovered device: {} ({}) at {}",
                                             name, model, device_url
@@ -178,12 +352,30 @@ async fn main() -> Result<()> {
     }
 
     // Initialize HTTP server
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/metrics", get(metrics_handler))
         .route("/health", get(health_handler))
         .route("/", get(root_handler))
         .with_state(shared_metrics);
 
+    if let Some(devices) = &config.inbound_devices {
+        info!(
+            "Accepting WebSocket connections on /ws from {} devices",
+            devices.len()
+        );
+        let inbound = InboundState {
+            clients: device_clients.clone(),
+            metrics: metrics.clone(),
+            auth: config.auth(),
+            allowed: Arc::new(devices.iter().cloned().collect()),
+        };
+        app = app.merge(
+            Router::new()
+                .route("/ws", any(inbound_handler))
+                .with_state(inbound),
+        );
+    }
+
     let addr = config.metrics_bind_address();
     info!("Starting metrics server on {}", &addr);
 
@@ -193,27 +385,119 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn setup_device_client(host: &str, config: &Config) -> Result<(ShellyClient, String)> {
+/// Retry the setup of devices that were not reachable yet, concurrently and
+/// apart from the poll loop so that sleeping devices never delay it.
+async fn retry_pending(
+    pending: PendingDevices,
+    clients: DeviceClients,
+    config: Config,
+    metrics: Arc<Metrics>,
+    coiot: Option<Arc<CoiotListener>>,
+    mqtt: Option<Arc<MqttListener>>,
+) {
+    let mut interval = interval(config.poll_interval_duration());
+    interval.tick().await; // First tick completes immediately
+
+    loop {
+        interval.tick().await;
+
+        let hosts = std::mem::take(&mut *pending.lock().await);
+        let attempts = hosts.into_iter().map(|(host, name)| async {
+            let result = setup_device_client(
+                &host,
+                &name,
+                &config,
+                &metrics,
+                coiot.as_ref(),
+                mqtt.as_ref(),
+            )
+            .await;
+            (host, name, result)
+        });
+
+        for (host, name, result) in join_all(attempts).await {
+            match result {
+                Ok((client, model)) => {
+                    info!("Added device: {} ({}) at {}", name, model, host);
+                    clients.lock().await.insert(host, (client, name, model));
+                }
+                Err(e) => {
+                    debug!("Device at {} still not reachable: {}", host, e);
+                    // Devices heard over CoIoT or MQTT meanwhile are no longer retried
+                    let registered = clients.lock().await.contains_key(&host);
+                    if !registered {
+                        pending.lock().await.push((host, name));
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn setup_device_client(
+    host: &str,
+    name: &str,
+    config: &Config,
+    metrics: &Metrics,
+    coiot: Option<&Arc<CoiotListener>>,
+    mqtt: Option<&Arc<MqttListener>>,
+) -> Result<(ShellyClient, String)> {
     let timeout = config.http_timeout_duration();
     let auth = config.auth();
 
-    // Detect device generation
-    let generation = ShellyClient::detect_generation(host, timeout, auth.clone()).await?;
+    let (mut client, device_info) = match (host.strip_prefix("mqtt://"), mqtt) {
+        // Only Gen2+ devices take requests over MQTT, and tell their generation
+        (Some(prefix), Some(listener)) => {
+            let (client, info) =
+                ShellyClient::over_mqtt(prefix, listener.clone(), timeout, auth).await?;
+            (client, Some(info))
+        }
+        (Some(_), None) => bail!("{} requires an MQTT broker (--mqtt-broker)", host),
+        (None, _) => {
+            // Detect device generation
+            let generation = ShellyClient::detect_generation(host, timeout, auth.clone()).await?;
+            let client = ShellyClient::new(host.to_string(), timeout, auth, generation)?;
+            (client, None)
+        }
+    };
+    let generation = client.generation;
 
-    // Create client
-    let client = ShellyClient::new(host.to_string(), timeout, auth, generation)?;
+    if config.use_websocket(host) {
+        if generation.is_rpc() {
+            client.enable_websocket(timeout);
+        } else {
+            warn!("{} is a Gen1 device, polling it over HTTP instead", host);
+        }
+    }
+
+    if let Some(listener) = coiot
+        && generation == ShellyGeneration::Gen1
+        && !client.enable_coiot(listener.clone())
+    {
+        warn!("{} is not an IP address, polling it over HTTP only", host);
+    }
 
     // Get device info for model
-    let model = if generation == ShellyGeneration::Gen2 {
-        match client.get_device_info().await {
-            Ok(info) => info.model,
-            Err(_) => "Unknown".to_string(),
+    let device_info = match device_info {
+        Some(info) => Ok(info),
+        None => client.get_device_info().await,
+    };
+    let model = match device_info {
+        Ok(info) => {
+            metrics.update_device_info(name, host, &info);
+            info.model
         }
-    } else {
-        // Gen1 devices don't have a unified device info endpoint
-        "Shelly Gen1".to_string()
+        Err(_) => "Unknown".to_string(),
     };
 
+    // Component names only change when reconfigured, so fetch them once
+    if generation.is_rpc() {
+        match client.get_component_names().await {
+            Ok(names) => metrics.update_component_names(name, host, &names),
+            Err(e) => debug!("Failed to fetch component names from {}: {}", host, e),
+        }
+    }
+
     Ok((client, model))
 }
 
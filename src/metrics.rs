@@ -1,20 +1,55 @@
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use prometheus::{
-    Encoder, GaugeVec, IntGaugeVec, Registry, TextEncoder, register_gauge_vec,
-    register_int_gauge_vec,
+    CounterVec, Encoder, GaugeVec, IntCounterVec, IntGaugeVec, Registry, TextEncoder,
+    register_counter_vec_with_registry, register_gauge_vec_with_registry,
+    register_int_counter_vec_with_registry, register_int_gauge_vec_with_registry,
 };
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
 use tracing::{debug, error};
 
-use crate::shelly::{ShellyGen1Status, ShellyGen2Status, ShellyStatus};
+use crate::shelly::{
+    AlarmStatus, BthomeConfig, BthomeDeviceStatus, BthomeSensorStatus, Component, CoverStatus,
+    DeviceInfo, DevicePowerStatus, Em1DataStatus, Em1Status, EmDataStatus, EmStatus, EmeterStatus,
+    Gen1Family, HumidityStatus, InputGen1Status, InputStatus, LightGen1Status, LightStatus,
+    Pm1Status, RollerStatus, ShellyError, ShellyGen1Status, ShellyGen2Status, ShellyStatus,
+    SwitchStatus, TemperatureStatus, ThermostatGen1Status, ThermostatStatus, bthome_object_name,
+    split_component_key,
+};
+
+/// Cover states as reported by Gen2 devices; Gen1 roller states are mapped onto these.
+const COVER_STATES: &[&str] = &[
+    "open",
+    "closed",
+    "opening",
+    "closing",
+    "stopped",
+    "calibrating",
+];
+
+/// States reported by the Shelly Gas sensor.
+const GAS_ALARM_STATES: &[&str] = &["none", "mild", "heavy", "test", "unknown"];
+const GAS_SENSOR_STATES: &[&str] = &["warmup", "normal", "fault", "unknown"];
+const GAS_SELF_TEST_STATES: &[&str] = &["not_completed", "completed", "running", "pending"];
 
 pub struct Metrics {
     registry: Registry,
 
     // Common metrics
     device_up: IntGaugeVec,
+    device_info: IntGaugeVec,
     device_uptime: IntGaugeVec,
     device_temperature: GaugeVec,
     wifi_rssi: IntGaugeVec,
+    component_info: IntGaugeVec,
+    device_last_seen: IntGaugeVec,
+    device_stale: IntGaugeVec,
+    scrape_errors_total: IntCounterVec,
+    status_parse_errors_total: IntCounterVec,
+    /// Time of the last successful update per (device, host)
+    last_seen: Mutex<HashMap<(String, String), DateTime<Utc>>>,
 
     // Power metrics
     switch_output: IntGaugeVec,
@@ -25,6 +60,101 @@ pub struct Metrics {
     switch_frequency_hz: GaugeVec,
     switch_energy_total_wh: GaugeVec,
 
+    // Cover metrics
+    cover_state: IntGaugeVec,
+    cover_position_percent: GaugeVec,
+    cover_target_position_percent: GaugeVec,
+    cover_calibrated: IntGaugeVec,
+    cover_power_watts: GaugeVec,
+    cover_voltage_volts: GaugeVec,
+    cover_current_amps: GaugeVec,
+    cover_power_factor: GaugeVec,
+    cover_energy_total_wh: GaugeVec,
+
+    // Light metrics
+    light_output: IntGaugeVec,
+    light_brightness_percent: GaugeVec,
+    light_color_temperature_kelvin: GaugeVec,
+    light_color_value: GaugeVec,
+    light_power_watts: GaugeVec,
+    light_voltage_volts: GaugeVec,
+    light_current_amps: GaugeVec,
+    light_energy_total_wh: GaugeVec,
+
+    // Input metrics
+    input_state: IntGaugeVec,
+    input_percent: GaugeVec,
+    input_events_total: IntCounterVec,
+    input_frequency_hz: GaugeVec,
+    input_count_total: CounterVec,
+    /// Last seen Gen1 `event_cnt` per (device, host, channel)
+    input_event_counts: Mutex<HashMap<(String, String, String), i64>>,
+    /// Last seen Gen2 `counts.total` per (device, host, channel)
+    input_count_totals: Mutex<HashMap<(String, String, String), f64>>,
+    /// Unit factor per (host, channel) applied to counted pulses
+    pulse_factors: HashMap<(String, String), f64>,
+
+    // Energy meter metrics
+    em_voltage_volts: GaugeVec,
+    em_current_amps: GaugeVec,
+    em_neutral_current_amps: GaugeVec,
+    em_active_power_watts: GaugeVec,
+    em_apparent_power_va: GaugeVec,
+    em_power_factor: GaugeVec,
+    em_frequency_hz: GaugeVec,
+    em_energy_total_wh: GaugeVec,
+    em_returned_energy_total_wh: GaugeVec,
+    em_current_imbalance_ratio: GaugeVec,
+
+    // Single-phase meter metrics
+    emeter_power_watts: GaugeVec,
+    emeter_apparent_power_va: GaugeVec,
+    emeter_reactive_power_var: GaugeVec,
+    emeter_voltage_volts: GaugeVec,
+    emeter_current_amps: GaugeVec,
+    emeter_power_factor: GaugeVec,
+    emeter_frequency_hz: GaugeVec,
+    emeter_energy_total_wh: GaugeVec,
+    emeter_returned_energy_total_wh: GaugeVec,
+
+    // Sensor metrics
+    sensor_temperature_celsius: GaugeVec,
+    sensor_humidity_percent: GaugeVec,
+    sensor_illuminance_lux: GaugeVec,
+    sensor_tilt_degrees: GaugeVec,
+    sensor_open: IntGaugeVec,
+    sensor_motion: IntGaugeVec,
+    sensor_vibration: IntGaugeVec,
+    flood_alarm: IntGaugeVec,
+    smoke_alarm: IntGaugeVec,
+    gas_concentration_ppm: GaugeVec,
+    gas_alarm_state: IntGaugeVec,
+    gas_sensor_state: IntGaugeVec,
+    gas_self_test_state: IntGaugeVec,
+    battery_percent: GaugeVec,
+    battery_voltage_volts: GaugeVec,
+    external_power_present: IntGaugeVec,
+    voltmeter_voltage_volts: GaugeVec,
+
+    // External sensor metrics
+    external_temperature_celsius: GaugeVec,
+    external_humidity_percent: GaugeVec,
+    external_switch_state: IntGaugeVec,
+
+    // BTHome (Shelly BLU) metrics
+    bthome_battery_percent: GaugeVec,
+    bthome_rssi_dbm: IntGaugeVec,
+    bthome_packet_id: IntGaugeVec,
+    bthome_last_updated: IntGaugeVec,
+    bthome_sensor_value: GaugeVec,
+
+    // Thermostat metrics
+    thermostat_target_celsius: GaugeVec,
+    thermostat_current_celsius: GaugeVec,
+    thermostat_valve_position_percent: GaugeVec,
+    thermostat_enabled: IntGaugeVec,
+    thermostat_window_open: IntGaugeVec,
+
     // System metrics
     system_ram_free_bytes: IntGaugeVec,
     system_ram_total_bytes: IntGaugeVec,
@@ -39,124 +169,680 @@ impl Metrics {
     pub fn new() -> Result<Self> {
         let registry = Registry::new();
 
-        let device_up = register_int_gauge_vec!(
+        let device_up = register_int_gauge_vec_with_registry!(
             "shelly_device_up",
             "Whether the device is reachable (1) or not (0)",
-            &["device", "host", "model", "generation"]
+            &["device", "host", "model", "generation"],
+            registry
         )?;
-        registry.register(Box::new(device_up.clone()))?;
 
-        let device_uptime = register_int_gauge_vec!(
+        let device_info = register_int_gauge_vec_with_registry!(
+            "shelly_device_info",
+            "Device identity (always 1)",
+            &[
+                "device", "host", "mac", "id", "fw_id", "ver", "app", "auth_en"
+            ],
+            registry
+        )?;
+
+        let device_uptime = register_int_gauge_vec_with_registry!(
             "shelly_device_uptime_seconds",
             "Device uptime in seconds",
-            &["device", "host"]
+            &["device", "host"],
+            registry
         )?;
-        registry.register(Box::new(device_uptime.clone()))?;
 
-        let device_temperature = register_gauge_vec!(
+        let device_temperature = register_gauge_vec_with_registry!(
             "shelly_device_temperature_celsius",
             "Device temperature in celsius",
-            &["device", "host"]
+            &["device", "host"],
+            registry
         )?;
-        registry.register(Box::new(device_temperature.clone()))?;
 
-        let wifi_rssi = register_int_gauge_vec!(
+        let wifi_rssi = register_int_gauge_vec_with_registry!(
             "shelly_wifi_rssi_dbm",
             "WiFi signal strength in dBm",
-            &["device", "host", "ssid"]
+            &["device", "host", "ssid"],
+            registry
+        )?;
+
+        let component_info = register_int_gauge_vec_with_registry!(
+            "shelly_component_info",
+            "Configured name of a Gen2 component (always 1)",
+            &["device", "host", "component", "channel", "name"],
+            registry
         )?;
-        registry.register(Box::new(wifi_rssi.clone()))?;
 
-        let switch_output = register_int_gauge_vec!(
+        let device_last_seen = register_int_gauge_vec_with_registry!(
+            "shelly_device_last_seen_timestamp_seconds",
+            "Unix timestamp of the last successful status update",
+            &["device", "host"],
+            registry
+        )?;
+
+        let device_stale = register_int_gauge_vec_with_registry!(
+            "shelly_device_stale",
+            "Whether the exported readings are retained from an earlier update (1) or fresh (0)",
+            &["device", "host"],
+            registry
+        )?;
+
+        let scrape_errors_total = register_int_counter_vec_with_registry!(
+            "shelly_scrape_errors_total",
+            "Failed status requests by reason",
+            &["device", "host", "reason"],
+            registry
+        )?;
+
+        let status_parse_errors_total = register_int_counter_vec_with_registry!(
+            "shelly_status_parse_errors_total",
+            "Status entries skipped because they could not be parsed",
+            &["device", "host", "component"],
+            registry
+        )?;
+
+        let switch_output = register_int_gauge_vec_with_registry!(
             "shelly_switch_output",
             "Switch output state (0=off, 1=on)",
-            &["device", "host", "channel"]
+            &["device", "host", "channel"],
+            registry
         )?;
-        registry.register(Box::new(switch_output.clone()))?;
 
-        let switch_power_watts = register_gauge_vec!(
+        let switch_power_watts = register_gauge_vec_with_registry!(
             "shelly_switch_power_watts",
             "Instantaneous power consumption in watts",
-            &["device", "host", "channel"]
+            &["device", "host", "channel"],
+            registry
         )?;
-        registry.register(Box::new(switch_power_watts.clone()))?;
 
-        let switch_voltage_volts = register_gauge_vec!(
+        let switch_voltage_volts = register_gauge_vec_with_registry!(
             "shelly_switch_voltage_volts",
             "Voltage in volts",
-            &["device", "host", "channel"]
+            &["device", "host", "channel"],
+            registry
         )?;
-        registry.register(Box::new(switch_voltage_volts.clone()))?;
 
-        let switch_current_amps = register_gauge_vec!(
+        let switch_current_amps = register_gauge_vec_with_registry!(
             "shelly_switch_current_amps",
             "Current in amperes",
-            &["device", "host", "channel"]
+            &["device", "host", "channel"],
+            registry
         )?;
-        registry.register(Box::new(switch_current_amps.clone()))?;
 
-        let switch_power_factor = register_gauge_vec!(
+        let switch_power_factor = register_gauge_vec_with_registry!(
             "shelly_switch_power_factor",
             "Power factor",
-            &["device", "host", "channel"]
+            &["device", "host", "channel"],
+            registry
         )?;
-        registry.register(Box::new(switch_power_factor.clone()))?;
 
-        let switch_frequency_hz = register_gauge_vec!(
+        let switch_frequency_hz = register_gauge_vec_with_registry!(
             "shelly_switch_frequency_hz",
             "AC frequency in Hz",
-            &["device", "host", "channel"]
+            &["device", "host", "channel"],
+            registry
         )?;
-        registry.register(Box::new(switch_frequency_hz.clone()))?;
 
-        let switch_energy_total_wh = register_gauge_vec!(
+        let switch_energy_total_wh = register_gauge_vec_with_registry!(
             "shelly_switch_energy_total_wh",
             "Total energy consumed in watt-hours",
-            &["device", "host", "channel"]
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let cover_state = register_int_gauge_vec_with_registry!(
+            "shelly_cover_state",
+            "Cover state (1 for the current state, 0 otherwise)",
+            &["device", "host", "channel", "state"],
+            registry
+        )?;
+
+        let cover_position_percent = register_gauge_vec_with_registry!(
+            "shelly_cover_position_percent",
+            "Current cover position in percent (0=closed, 100=open)",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let cover_target_position_percent = register_gauge_vec_with_registry!(
+            "shelly_cover_target_position_percent",
+            "Target cover position in percent",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let cover_calibrated = register_int_gauge_vec_with_registry!(
+            "shelly_cover_calibrated",
+            "Whether the cover is calibrated for position control (1) or not (0)",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let cover_power_watts = register_gauge_vec_with_registry!(
+            "shelly_cover_power_watts",
+            "Instantaneous cover motor power in watts",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let cover_voltage_volts = register_gauge_vec_with_registry!(
+            "shelly_cover_voltage_volts",
+            "Cover voltage in volts",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let cover_current_amps = register_gauge_vec_with_registry!(
+            "shelly_cover_current_amps",
+            "Cover current in amperes",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let cover_power_factor = register_gauge_vec_with_registry!(
+            "shelly_cover_power_factor",
+            "Cover power factor",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let cover_energy_total_wh = register_gauge_vec_with_registry!(
+            "shelly_cover_energy_total_wh",
+            "Total energy consumed by the cover in watt-hours",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let light_output = register_int_gauge_vec_with_registry!(
+            "shelly_light_output",
+            "Light output state (0=off, 1=on)",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let light_brightness_percent = register_gauge_vec_with_registry!(
+            "shelly_light_brightness_percent",
+            "Light brightness in percent",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let light_color_temperature_kelvin = register_gauge_vec_with_registry!(
+            "shelly_light_color_temperature_kelvin",
+            "Light color temperature in kelvin",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let light_color_value = register_gauge_vec_with_registry!(
+            "shelly_light_color_value",
+            "Light color channel value (0-255)",
+            &["device", "host", "channel", "color"],
+            registry
+        )?;
+
+        let light_power_watts = register_gauge_vec_with_registry!(
+            "shelly_light_power_watts",
+            "Instantaneous light power in watts",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let light_voltage_volts = register_gauge_vec_with_registry!(
+            "shelly_light_voltage_volts",
+            "Light voltage in volts",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let light_current_amps = register_gauge_vec_with_registry!(
+            "shelly_light_current_amps",
+            "Light current in amperes",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let light_energy_total_wh = register_gauge_vec_with_registry!(
+            "shelly_light_energy_total_wh",
+            "Total energy consumed by the light in watt-hours",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let input_state = register_int_gauge_vec_with_registry!(
+            "shelly_input_state",
+            "Input state (0=off, 1=on)",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let input_percent = register_gauge_vec_with_registry!(
+            "shelly_input_percent",
+            "Analog input value in percent",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let input_events_total = register_int_counter_vec_with_registry!(
+            "shelly_input_events_total",
+            "Total number of input events by event type",
+            &["device", "host", "channel", "event"],
+            registry
+        )?;
+
+        let input_frequency_hz = register_gauge_vec_with_registry!(
+            "shelly_input_frequency_hz",
+            "Pulse frequency of an input in counter mode",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let input_count_total = register_counter_vec_with_registry!(
+            "shelly_input_count_total",
+            "Pulses counted by an input in counter mode, multiplied by the configured unit factor",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let em_voltage_volts = register_gauge_vec_with_registry!(
+            "shelly_em_voltage_volts",
+            "Energy meter voltage in volts",
+            &["device", "host", "channel", "phase"],
+            registry
+        )?;
+
+        let em_current_amps = register_gauge_vec_with_registry!(
+            "shelly_em_current_amps",
+            "Energy meter current in amperes",
+            &["device", "host", "channel", "phase"],
+            registry
+        )?;
+
+        let em_neutral_current_amps = register_gauge_vec_with_registry!(
+            "shelly_em_neutral_current_amps",
+            "Energy meter neutral current in amperes",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let em_active_power_watts = register_gauge_vec_with_registry!(
+            "shelly_em_active_power_watts",
+            "Energy meter active power in watts",
+            &["device", "host", "channel", "phase"],
+            registry
+        )?;
+
+        let em_apparent_power_va = register_gauge_vec_with_registry!(
+            "shelly_em_apparent_power_va",
+            "Energy meter apparent power in volt-amperes",
+            &["device", "host", "channel", "phase"],
+            registry
+        )?;
+
+        let em_power_factor = register_gauge_vec_with_registry!(
+            "shelly_em_power_factor",
+            "Energy meter power factor",
+            &["device", "host", "channel", "phase"],
+            registry
+        )?;
+
+        let em_frequency_hz = register_gauge_vec_with_registry!(
+            "shelly_em_frequency_hz",
+            "Energy meter AC frequency in Hz",
+            &["device", "host", "channel", "phase"],
+            registry
+        )?;
+
+        let em_energy_total_wh = register_gauge_vec_with_registry!(
+            "shelly_em_energy_total_wh",
+            "Total active energy consumed in watt-hours",
+            &["device", "host", "channel", "phase"],
+            registry
+        )?;
+
+        let em_returned_energy_total_wh = register_gauge_vec_with_registry!(
+            "shelly_em_returned_energy_total_wh",
+            "Total active energy returned to the grid in watt-hours",
+            &["device", "host", "channel", "phase"],
+            registry
+        )?;
+
+        let em_current_imbalance_ratio = register_gauge_vec_with_registry!(
+            "shelly_em_current_imbalance_ratio",
+            "Maximum deviation of a phase current from the average, relative to the average",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let emeter_power_watts = register_gauge_vec_with_registry!(
+            "shelly_emeter_power_watts",
+            "Energy meter active power in watts",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let emeter_apparent_power_va = register_gauge_vec_with_registry!(
+            "shelly_emeter_apparent_power_va",
+            "Energy meter apparent power in volt-amperes",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let emeter_reactive_power_var = register_gauge_vec_with_registry!(
+            "shelly_emeter_reactive_power_var",
+            "Energy meter reactive power in volt-amperes reactive",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let emeter_voltage_volts = register_gauge_vec_with_registry!(
+            "shelly_emeter_voltage_volts",
+            "Energy meter voltage in volts",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let emeter_current_amps = register_gauge_vec_with_registry!(
+            "shelly_emeter_current_amps",
+            "Energy meter current in amperes",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let emeter_power_factor = register_gauge_vec_with_registry!(
+            "shelly_emeter_power_factor",
+            "Energy meter power factor",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let emeter_frequency_hz = register_gauge_vec_with_registry!(
+            "shelly_emeter_frequency_hz",
+            "Energy meter AC frequency in Hz",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let emeter_energy_total_wh = register_gauge_vec_with_registry!(
+            "shelly_emeter_energy_total_wh",
+            "Total active energy consumed in watt-hours",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let emeter_returned_energy_total_wh = register_gauge_vec_with_registry!(
+            "shelly_emeter_returned_energy_total_wh",
+            "Total active energy returned to the grid in watt-hours",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let sensor_temperature_celsius = register_gauge_vec_with_registry!(
+            "shelly_sensor_temperature_celsius",
+            "Sensor temperature in celsius",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let sensor_humidity_percent = register_gauge_vec_with_registry!(
+            "shelly_sensor_humidity_percent",
+            "Relative humidity in percent",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let sensor_illuminance_lux = register_gauge_vec_with_registry!(
+            "shelly_sensor_illuminance_lux",
+            "Illuminance in lux",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let sensor_tilt_degrees = register_gauge_vec_with_registry!(
+            "shelly_sensor_tilt_degrees",
+            "Tilt angle in degrees",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let sensor_open = register_int_gauge_vec_with_registry!(
+            "shelly_sensor_open",
+            "Whether the door/window is open (1) or closed (0)",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let sensor_motion = register_int_gauge_vec_with_registry!(
+            "shelly_sensor_motion",
+            "Whether motion is detected (1) or not (0)",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let sensor_vibration = register_int_gauge_vec_with_registry!(
+            "shelly_sensor_vibration",
+            "Whether vibration is detected (1) or not (0)",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let flood_alarm = register_int_gauge_vec_with_registry!(
+            "shelly_flood_alarm",
+            "Whether flood is detected (1) or not (0)",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let smoke_alarm = register_int_gauge_vec_with_registry!(
+            "shelly_smoke_alarm",
+            "Whether smoke is detected (1) or not (0)",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let gas_concentration_ppm = register_gauge_vec_with_registry!(
+            "shelly_gas_concentration_ppm",
+            "Gas concentration measured by the gas sensor in ppm",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let gas_alarm_state = register_int_gauge_vec_with_registry!(
+            "shelly_gas_alarm_state",
+            "Gas alarm state (1 for the current state, 0 otherwise)",
+            &["device", "host", "channel", "state"],
+            registry
         )?;
-        registry.register(Box::new(switch_energy_total_wh.clone()))?;
 
-        let system_ram_free_bytes = register_int_gauge_vec!(
+        let gas_sensor_state = register_int_gauge_vec_with_registry!(
+            "shelly_gas_sensor_state",
+            "Gas sensor operating state (1 for the current state, 0 otherwise)",
+            &["device", "host", "channel", "state"],
+            registry
+        )?;
+
+        let gas_self_test_state = register_int_gauge_vec_with_registry!(
+            "shelly_gas_self_test_state",
+            "Gas sensor self-test state (1 for the current state, 0 otherwise)",
+            &["device", "host", "channel", "state"],
+            registry
+        )?;
+
+        let battery_percent = register_gauge_vec_with_registry!(
+            "shelly_battery_percent",
+            "Battery charge in percent",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let battery_voltage_volts = register_gauge_vec_with_registry!(
+            "shelly_battery_voltage_volts",
+            "Battery voltage in volts",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let external_power_present = register_int_gauge_vec_with_registry!(
+            "shelly_external_power_present",
+            "Whether external power is connected (1) or not (0)",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let voltmeter_voltage_volts = register_gauge_vec_with_registry!(
+            "shelly_voltmeter_voltage_volts",
+            "Voltage measured by an add-on voltmeter",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let external_temperature_celsius = register_gauge_vec_with_registry!(
+            "shelly_external_temperature_celsius",
+            "External add-on temperature probe reading in celsius",
+            &["device", "host", "sensor", "sensor_id"],
+            registry
+        )?;
+
+        let external_humidity_percent = register_gauge_vec_with_registry!(
+            "shelly_external_humidity_percent",
+            "External add-on humidity sensor reading in percent",
+            &["device", "host", "sensor", "sensor_id"],
+            registry
+        )?;
+
+        let external_switch_state = register_int_gauge_vec_with_registry!(
+            "shelly_external_switch_state",
+            "External add-on switch input state (0=off, 1=on)",
+            &["device", "host", "sensor"],
+            registry
+        )?;
+
+        let bthome_battery_percent = register_gauge_vec_with_registry!(
+            "shelly_bthome_battery_percent",
+            "Battery level of a BLU device paired with a gateway",
+            &["device", "host", "address", "name"],
+            registry
+        )?;
+
+        let bthome_rssi_dbm = register_int_gauge_vec_with_registry!(
+            "shelly_bthome_rssi_dbm",
+            "Signal strength of a BLU device as received by the gateway",
+            &["device", "host", "address", "name"],
+            registry
+        )?;
+
+        let bthome_packet_id = register_int_gauge_vec_with_registry!(
+            "shelly_bthome_packet_id",
+            "Id of the last packet received from a BLU device",
+            &["device", "host", "address", "name"],
+            registry
+        )?;
+
+        let bthome_last_updated = register_int_gauge_vec_with_registry!(
+            "shelly_bthome_last_updated_timestamp_seconds",
+            "Unix time of the last packet received from a BLU device",
+            &["device", "host", "address", "name"],
+            registry
+        )?;
+
+        let bthome_sensor_value = register_gauge_vec_with_registry!(
+            "shelly_bthome_sensor_value",
+            "Last value of a BTHome object reported by a BLU device",
+            &[
+                "device",
+                "host",
+                "address",
+                "name",
+                "object_id",
+                "index",
+                "type"
+            ],
+            registry
+        )?;
+
+        let thermostat_target_celsius = register_gauge_vec_with_registry!(
+            "shelly_thermostat_target_celsius",
+            "Thermostat target temperature in celsius",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let thermostat_current_celsius = register_gauge_vec_with_registry!(
+            "shelly_thermostat_current_celsius",
+            "Temperature measured by the thermostat in celsius",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let thermostat_valve_position_percent = register_gauge_vec_with_registry!(
+            "shelly_thermostat_valve_position_percent",
+            "Valve position of a TRV in percent",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let thermostat_enabled = register_int_gauge_vec_with_registry!(
+            "shelly_thermostat_enabled",
+            "Whether the thermostat is regulating (1) or not (0)",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let thermostat_window_open = register_int_gauge_vec_with_registry!(
+            "shelly_thermostat_window_open",
+            "Whether an open window was detected (1) or not (0)",
+            &["device", "host", "channel"],
+            registry
+        )?;
+
+        let system_ram_free_bytes = register_int_gauge_vec_with_registry!(
             "shelly_system_ram_free_bytes",
             "Free RAM in bytes",
-            &["device", "host"]
+            &["device", "host"],
+            registry
         )?;
-        registry.register(Box::new(system_ram_free_bytes.clone()))?;
 
-        let system_ram_total_bytes = register_int_gauge_vec!(
+        let system_ram_total_bytes = register_int_gauge_vec_with_registry!(
             "shelly_system_ram_total_bytes",
             "Total RAM in bytes",
-            &["device", "host"]
+            &["device", "host"],
+            registry
         )?;
-        registry.register(Box::new(system_ram_total_bytes.clone()))?;
 
-        let system_fs_free_bytes = register_int_gauge_vec!(
+        let system_fs_free_bytes = register_int_gauge_vec_with_registry!(
             "shelly_system_fs_free_bytes",
             "Free filesystem space in bytes",
-            &["device", "host"]
+            &["device", "host"],
+            registry
         )?;
-        registry.register(Box::new(system_fs_free_bytes.clone()))?;
 
-        let system_fs_total_bytes = register_int_gauge_vec!(
+        let system_fs_total_bytes = register_int_gauge_vec_with_registry!(
             "shelly_system_fs_total_bytes",
             "Total filesystem space in bytes",
-            &["device", "host"]
+            &["device", "host"],
+            registry
         )?;
-        registry.register(Box::new(system_fs_total_bytes.clone()))?;
 
-        let device_update_available = register_int_gauge_vec!(
+        let device_update_available = register_int_gauge_vec_with_registry!(
             "shelly_device_update_available",
             "Whether a firmware update is available (1) or not (0)",
-            &["device", "host", "current_version", "new_version"]
+            &["device", "host", "current_version", "new_version"],
+            registry
         )?;
-        registry.register(Box::new(device_update_available.clone()))?;
 
         Ok(Self {
             registry,
             device_up,
+            device_info,
             device_uptime,
             device_temperature,
             wifi_rssi,
+            component_info,
+            device_last_seen,
+            device_stale,
+            scrape_errors_total,
+            status_parse_errors_total,
+            last_seen: Mutex::new(HashMap::new()),
             switch_output,
             switch_power_watts,
             switch_voltage_volts,
@@ -164,6 +850,80 @@ impl Metrics {
             switch_power_factor,
             switch_frequency_hz,
             switch_energy_total_wh,
+            cover_state,
+            cover_position_percent,
+            cover_target_position_percent,
+            cover_calibrated,
+            cover_power_watts,
+            cover_voltage_volts,
+            cover_current_amps,
+            cover_power_factor,
+            cover_energy_total_wh,
+            light_output,
+            light_brightness_percent,
+            light_color_temperature_kelvin,
+            light_color_value,
+            light_power_watts,
+            light_voltage_volts,
+            light_current_amps,
+            light_energy_total_wh,
+            input_state,
+            input_percent,
+            input_events_total,
+            input_frequency_hz,
+            input_count_total,
+            input_event_counts: Mutex::new(HashMap::new()),
+            input_count_totals: Mutex::new(HashMap::new()),
+            pulse_factors: HashMap::new(),
+            em_voltage_volts,
+            em_current_amps,
+            em_neutral_current_amps,
+            em_active_power_watts,
+            em_apparent_power_va,
+            em_power_factor,
+            em_frequency_hz,
+            em_energy_total_wh,
+            em_returned_energy_total_wh,
+            em_current_imbalance_ratio,
+            emeter_power_watts,
+            emeter_apparent_power_va,
+            emeter_reactive_power_var,
+            emeter_voltage_volts,
+            emeter_current_amps,
+            emeter_power_factor,
+            emeter_frequency_hz,
+            emeter_energy_total_wh,
+            emeter_returned_energy_total_wh,
+            sensor_temperature_celsius,
+            sensor_humidity_percent,
+            sensor_illuminance_lux,
+            sensor_tilt_degrees,
+            sensor_open,
+            sensor_motion,
+            sensor_vibration,
+            flood_alarm,
+            smoke_alarm,
+            gas_concentration_ppm,
+            gas_alarm_state,
+            gas_sensor_state,
+            gas_self_test_state,
+            battery_percent,
+            battery_voltage_volts,
+            external_power_present,
+            voltmeter_voltage_volts,
+            external_temperature_celsius,
+            external_humidity_percent,
+            external_switch_state,
+            bthome_battery_percent,
+            bthome_rssi_dbm,
+            bthome_packet_id,
+            bthome_last_updated,
+            bthome_sensor_value,
+            thermostat_target_celsius,
+            thermostat_current_celsius,
+            thermostat_valve_position_percent,
+            thermostat_enabled,
+            thermostat_window_open,
             system_ram_free_bytes,
             system_ram_total_bytes,
             system_fs_free_bytes,
@@ -172,6 +932,44 @@ impl Metrics {
         })
     }
 
+    /// Set the unit factors applied to inputs in counter mode, keyed by
+    /// (host, input id). Inputs without a factor count raw pulses.
+    pub fn with_pulse_factors(mut self, pulse_factors: HashMap<(String, String), f64>) -> Self {
+        self.pulse_factors = pulse_factors;
+        self
+    }
+
+    /// Export the identity of a device as `shelly_device_info`.
+    pub fn update_device_info(&self, device_name: &str, host: &str, info: &DeviceInfo) {
+        self.device_info
+            .with_label_values(&[
+                device_name,
+                host,
+                &info.mac,
+                &info.id,
+                &info.fw_id,
+                &info.ver,
+                &info.app,
+                if info.auth_en { "true" } else { "false" },
+            ])
+            .set(1);
+    }
+
+    /// Export the configured component names of a device as `shelly_component_info`.
+    pub fn update_component_names(
+        &self,
+        device_name: &str,
+        host: &str,
+        names: &BTreeMap<String, String>,
+    ) {
+        for (key, name) in names {
+            let (component, channel) = split_component_key(key);
+            self.component_info
+                .with_label_values(&[device_name, host, component, channel.unwrap_or("0"), name])
+                .set(1);
+        }
+    }
+
     pub fn update_device(
         &self,
         device_name: &str,
@@ -187,9 +985,31 @@ impl Metrics {
             .with_label_values(&[device_name, host, model, generation])
             .set(1);
 
+        let now = Utc::now();
+        self.last_seen
+            .lock()
+            .unwrap()
+            .insert((device_name.to_string(), host.to_string()), now);
+        self.device_last_seen
+            .with_label_values(&[device_name, host])
+            .set(now.timestamp());
+        self.device_stale
+            .with_label_values(&[device_name, host])
+            .set(0);
+
+        let parse_errors = match status {
+            ShellyStatus::Gen1(gen1_status) => &gen1_status.parse_errors,
+            ShellyStatus::Gen2(gen2_status) => &gen2_status.parse_errors,
+        };
+        for component in parse_errors {
+            self.status_parse_errors_total
+                .with_label_values(&[device_name, host, component])
+                .inc();
+        }
+
         match status {
             ShellyStatus::Gen1(gen1_status) => {
-                self.update_gen1_metrics(device_name, host, gen1_status)?
+                self.update_gen1_metrics(device_name, host, model, gen1_status)?
             }
             ShellyStatus::Gen2(gen2_status) => {
                 self.update_gen2_metrics(device_name, host, gen2_status)?
@@ -203,6 +1023,7 @@ impl Metrics {
         &self,
         device_name: &str,
         host: &str,
+        model: &str,
         status: &ShellyGen1Status,
     ) -> Result<()> {
         // Uptime
@@ -220,16 +1041,18 @@ impl Metrics {
         }
 
         // WiFi
-        if let Some(wifi) = &status.wifi_sta {
+        if let Some(wifi) = &status.wifi_sta
+            && let Some(rssi) = wifi.rssi
+        {
             let ssid = wifi.ssid.as_deref().unwrap_or("unknown");
             self.wifi_rssi
                 .with_label_values(&[device_name, host, ssid])
-                .set(wifi.rssi as i64);
+                .set(rssi as i64);
         }
 
         // Relays and meters
         if let Some(relays) = &status.relays {
-            for (idx, relay) in relays.iter().enumerate() {
+            for (idx, relay) in relays.iter() {
                 let channel = idx.to_string();
                 self.switch_output
                     .with_label_values(&[device_name, host, &channel])
@@ -237,15 +1060,66 @@ impl Metrics {
             }
         }
 
+        if let Some(rollers) = &status.rollers {
+            for (idx, roller) in rollers.iter() {
+                self.update_roller_metrics(device_name, host, &idx.to_string(), roller);
+            }
+        }
+
+        if let Some(lights) = &status.lights {
+            for (idx, light) in lights.iter() {
+                self.update_gen1_light_metrics(device_name, host, &idx.to_string(), light);
+            }
+        }
+
+        if let Some(inputs) = &status.inputs {
+            for (idx, input) in inputs.iter() {
+                self.update_gen1_input_metrics(device_name, host, &idx.to_string(), input);
+            }
+        }
+
+        if let Some(emeters) = &status.emeters {
+            for (idx, emeter) in emeters.iter() {
+                self.update_gen1_emeter_metrics(device_name, host, &idx.to_string(), emeter);
+            }
+        }
+
+        if let Some(thermostats) = &status.thermostats {
+            for (idx, thermostat) in thermostats.iter() {
+                self.update_gen1_thermostat_metrics(
+                    device_name,
+                    host,
+                    &idx.to_string(),
+                    thermostat,
+                );
+            }
+        }
+
+        self.update_gen1_sensor_metrics(device_name, host, model, status);
+
+        // Dimmers and RGBW controllers meter their light channels rather than a
+        // relay; fall back to the shape of the status when the model is unknown
+        let meters_lights = match Gen1Family::from_model(model) {
+            Some(family) => family == Gen1Family::Light,
+            None => status.lights.is_some() && status.relays.is_none(),
+        };
+        let (power_watts, energy_total_wh) = if meters_lights {
+            (&self.light_power_watts, &self.light_energy_total_wh)
+        } else {
+            (&self.switch_power_watts, &self.switch_energy_total_wh)
+        };
+
         if let Some(meters) = &status.meters {
-            for (idx, meter) in meters.iter().enumerate() {
+            for (idx, meter) in meters.iter() {
                 let channel = idx.to_string();
-                self.switch_power_watts
+                power_watts
                     .with_label_values(&[device_name, host, &channel])
                     .set(meter.power);
-                self.switch_energy_total_wh
-                    .with_label_values(&[device_name, host, &channel])
-                    .set(meter.total);
+                if let Some(total) = meter.total {
+                    energy_total_wh
+                        .with_label_values(&[device_name, host, &channel])
+                        .set(total);
+                }
             }
         }
 
@@ -269,13 +1143,14 @@ impl Metrics {
         }
 
         // Updates
-        if let Some(update) = &status.update {
-            if update.has_update {
-                let new_version = update.new_version.as_deref().unwrap_or("unknown");
-                self.device_update_available
-                    .with_label_values(&[device_name, host, &update.old_version, new_version])
-                    .set(1);
-            }
+        if let Some(update) = &status.update
+            && update.has_update == Some(true)
+        {
+            let old_version = update.old_version.as_deref().unwrap_or("unknown");
+            let new_version = update.new_version.as_deref().unwrap_or("unknown");
+            self.device_update_available
+                .with_label_values(&[device_name, host, old_version, new_version])
+                .set(1);
         }
 
         Ok(())
@@ -289,116 +1164,1190 @@ impl Metrics {
     ) -> Result<()> {
         // System metrics
         if let Some(sys) = &status.sys {
-            self.device_uptime
-                .with_label_values(&[device_name, host])
-                .set(sys.uptime);
-
-            self.system_ram_total_bytes
-                .with_label_values(&[device_name, host])
-                .set(sys.ram_size);
-            self.system_ram_free_bytes
-                .with_label_values(&[device_name, host])
-                .set(sys.ram_free);
-
-            self.system_fs_total_bytes
-                .with_label_values(&[device_name, host])
-                .set(sys.fs_size);
-            self.system_fs_free_bytes
-                .with_label_values(&[device_name, host])
-                .set(sys.fs_free);
+            let labels = [device_name, host];
+            let values = [
+                (&self.device_uptime, sys.uptime),
+                (&self.system_ram_total_bytes, sys.ram_size),
+                (&self.system_ram_free_bytes, sys.ram_free),
+                (&self.system_fs_total_bytes, sys.fs_size),
+                (&self.system_fs_free_bytes, sys.fs_free),
+            ];
+            for (gauge, value) in values {
+                if let Some(value) = value {
+                    gauge.with_label_values(&labels).set(value);
+                }
+            }
 
             // Check for updates
-            if let Some(updates) = &sys.available_updates {
-                if let Some(stable) = &updates.stable {
-                    self.device_update_available
-                        .with_label_values(&[device_name, host, "current", &stable.version])
-                        .set(1);
-                }
+            if let Some(updates) = &sys.available_updates
+                && let Some(stable) = &updates.stable
+            {
+                self.device_update_available
+                    .with_label_values(&[device_name, host, "current", &stable.version])
+                    .set(1);
             }
         }
 
         // WiFi
-        if let Some(wifi) = &status.wifi {
-            if let (Some(ssid), Some(rssi)) = (&wifi.ssid, wifi.rssi) {
-                self.wifi_rssi
-                    .with_label_values(&[device_name, host, ssid])
-                    .set(rssi as i64);
-            }
+        if let Some(wifi) = &status.wifi
+            && let (Some(ssid), Some(rssi)) = (&wifi.ssid, wifi.rssi)
+        {
+            self.wifi_rssi
+                .with_label_values(&[device_name, host, ssid])
+                .set(rssi as i64);
         }
 
-        // Process switches
-        let switches = vec![
-            ("0", &status.switch_0),
-            ("1", &status.switch_1),
-            ("2", &status.switch_2),
-            ("3", &status.switch_3),
-        ];
+        // Components
+        for (key, component) in &status.components {
+            let channel = split_component_key(key).1.unwrap_or("0");
 
-        for (channel, switch_opt) in switches {
-            if let Some(switch) = switch_opt {
-                self.switch_output
-                    .with_label_values(&[device_name, host, channel])
-                    .set(if switch.output { 1 } else { 0 });
-
-                // Temperature
-                if let Some(temp) = &switch.temperature {
-                    if let Some(t_c) = temp.t_c {
-                        self.device_temperature
-                            .with_label_values(&[device_name, host])
-                            .set(t_c);
+            match component {
+                Component::Switch(switch) => {
+                    self.update_switch_metrics(device_name, host, channel, switch)
+                }
+                Component::Cover(cover) => {
+                    self.update_cover_metrics(device_name, host, channel, cover)
+                }
+                Component::Light(light) => {
+                    self.update_light_metrics(device_name, host, channel, light)
+                }
+                Component::Input(input) => {
+                    self.update_input_metrics(device_name, host, channel, input)
+                }
+                Component::Em(em) => self.update_em_metrics(device_name, host, channel, em),
+                Component::EmData(emdata) => {
+                    self.update_emdata_metrics(device_name, host, channel, emdata)
+                }
+                Component::Em1(em1) => self.update_em1_metrics(device_name, host, channel, em1),
+                Component::Em1Data(em1data) => {
+                    self.update_em1data_metrics(device_name, host, channel, em1data)
+                }
+                Component::Pm1(pm1) => self.update_pm1_metrics(device_name, host, channel, pm1),
+                Component::Temperature(temperature) => {
+                    self.update_temperature_metrics(device_name, host, channel, temperature)
+                }
+                Component::Humidity(humidity) => {
+                    self.update_humidity_metrics(device_name, host, channel, humidity)
+                }
+                Component::Voltmeter(voltmeter) => {
+                    if let Some(voltage) = voltmeter.voltage {
+                        self.voltmeter_voltage_volts
+                            .with_label_values(&[device_name, host, channel])
+                            .set(voltage);
                     }
                 }
-
-                // Power metrics
-                if let Some(power) = switch.apower {
-                    self.switch_power_watts
-                        .with_label_values(&[device_name, host, channel])
-                        .set(power);
+                Component::Illuminance(illuminance) => {
+                    if let Some(lux) = illuminance.lux {
+                        self.sensor_illuminance_lux
+                            .with_label_values(&[device_name, host, channel])
+                            .set(lux);
+                    }
                 }
-
-                if let Some(voltage) = switch.voltage {
-                    self.switch_voltage_volts
-                        .with_label_values(&[device_name, host, channel])
-                        .set(voltage);
+                Component::DevicePower(power) => {
+                    self.update_device_power_metrics(device_name, host, channel, power)
                 }
-
-                if let Some(current) = switch.current {
-                    self.switch_current_amps
-                        .with_label_values(&[device_name, host, channel])
-                        .set(current);
+                Component::Smoke(smoke) => {
+                    self.update_alarm(&self.smoke_alarm, device_name, host, channel, smoke)
                 }
-
-                if let Some(pf) = switch.pf {
-                    self.switch_power_factor
-                        .with_label_values(&[device_name, host, channel])
-                        .set(pf);
+                Component::Flood(flood) => {
+                    self.update_alarm(&self.flood_alarm, device_name, host, channel, flood)
                 }
-
-                if let Some(freq) = switch.freq {
-                    self.switch_frequency_hz
-                        .with_label_values(&[device_name, host, channel])
-                        .set(freq);
+                Component::Thermostat(thermostat) => {
+                    self.update_thermostat_metrics(device_name, host, channel, thermostat)
                 }
+                Component::BthomeDevice(bthome) => match status.bthome_config.get(key) {
+                    Some(config) => {
+                        self.update_bthome_device_metrics(device_name, host, config, bthome)
+                    }
+                    None => debug!("Skipping {} without BTHome config", key),
+                },
+                Component::BthomeSensor(bthome) => match status.bthome_config.get(key) {
+                    Some(config) => {
+                        // Sensors carry the name of the BLU device they belong to, which is
+                        // informational only: the address identifies the device
+                        let name = status
+                            .bthome_config
+                            .iter()
+                            .find(|(k, c)| k.starts_with("bthomedevice:") && c.addr == config.addr)
+                            .and_then(|(_, c)| c.name.as_deref())
+                            .unwrap_or("");
+                        self.update_bthome_sensor_metrics(device_name, host, name, config, bthome)
+                    }
+                    None => debug!("Skipping {} without BTHome config", key),
+                },
+                Component::Unknown(_) => debug!("Skipping unsupported component {}", key),
+            }
+        }
 
-                if let Some(energy) = &switch.aenergy {
-                    self.switch_energy_total_wh
-                        .with_label_values(&[device_name, host, channel])
-                        .set(energy.total);
-                }
+        // Events pushed over a WebSocket connection, one per button press
+        for event in &status.events {
+            if let ("input", Some(channel)) = split_component_key(&event.component) {
+                self.input_events_total
+                    .with_label_values(&[device_name, host, channel, &event.event])
+                    .inc();
             }
         }
 
         Ok(())
     }
 
-    pub fn mark_device_down(&self, device_name: &str, host: &str, model: &str, generation: &str) {
-        error!("Marking device {} as down", device_name);
-        self.device_up
-            .with_label_values(&[device_name, host, model, generation])
-            .set(0);
-    }
-
-    pub fn gather(&self) -> Result<String> {
+    fn update_switch_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        channel: &str,
+        switch: &SwitchStatus,
+    ) {
+        self.switch_output
+            .with_label_values(&[device_name, host, channel])
+            .set(if switch.output { 1 } else { 0 });
+
+        // Temperature
+        if let Some(temp) = &switch.temperature
+            && let Some(t_c) = temp.t_c
+        {
+            self.device_temperature
+                .with_label_values(&[device_name, host])
+                .set(t_c);
+        }
+
+        // Power metrics
+        if let Some(power) = switch.apower {
+            self.switch_power_watts
+                .with_label_values(&[device_name, host, channel])
+                .set(power);
+        }
+
+        if let Some(voltage) = switch.voltage {
+            self.switch_voltage_volts
+                .with_label_values(&[device_name, host, channel])
+                .set(voltage);
+        }
+
+        if let Some(current) = switch.current {
+            self.switch_current_amps
+                .with_label_values(&[device_name, host, channel])
+                .set(current);
+        }
+
+        if let Some(pf) = switch.pf {
+            self.switch_power_factor
+                .with_label_values(&[device_name, host, channel])
+                .set(pf);
+        }
+
+        if let Some(freq) = switch.freq {
+            self.switch_frequency_hz
+                .with_label_values(&[device_name, host, channel])
+                .set(freq);
+        }
+
+        if let Some(energy) = &switch.aenergy {
+            self.switch_energy_total_wh
+                .with_label_values(&[device_name, host, channel])
+                .set(energy.total);
+        }
+    }
+
+    fn update_cover_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        channel: &str,
+        cover: &CoverStatus,
+    ) {
+        self.update_cover_state(device_name, host, channel, &cover.state);
+
+        if let Some(pos_control) = cover.pos_control {
+            self.cover_calibrated
+                .with_label_values(&[device_name, host, channel])
+                .set(if pos_control { 1 } else { 0 });
+        }
+
+        if let Some(position) = cover.current_pos {
+            self.cover_position_percent
+                .with_label_values(&[device_name, host, channel])
+                .set(position);
+        }
+
+        if let Some(target) = cover.target_pos {
+            self.cover_target_position_percent
+                .with_label_values(&[device_name, host, channel])
+                .set(target);
+        }
+
+        // Temperature
+        if let Some(temp) = &cover.temperature
+            && let Some(t_c) = temp.t_c
+        {
+            self.device_temperature
+                .with_label_values(&[device_name, host])
+                .set(t_c);
+        }
+
+        // Power metrics
+        if let Some(power) = cover.apower {
+            self.cover_power_watts
+                .with_label_values(&[device_name, host, channel])
+                .set(power);
+        }
+
+        if let Some(voltage) = cover.voltage {
+            self.cover_voltage_volts
+                .with_label_values(&[device_name, host, channel])
+                .set(voltage);
+        }
+
+        if let Some(current) = cover.current {
+            self.cover_current_amps
+                .with_label_values(&[device_name, host, channel])
+                .set(current);
+        }
+
+        if let Some(pf) = cover.pf {
+            self.cover_power_factor
+                .with_label_values(&[device_name, host, channel])
+                .set(pf);
+        }
+
+        if let Some(energy) = &cover.aenergy {
+            self.cover_energy_total_wh
+                .with_label_values(&[device_name, host, channel])
+                .set(energy.total);
+        }
+    }
+
+    fn update_roller_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        channel: &str,
+        roller: &RollerStatus,
+    ) {
+        // Gen1 reports the direction of travel as "open"/"close" and idle as "stop"
+        let state = match roller.state.as_str() {
+            _ if roller.calibrating == Some(true) => "calibrating",
+            "open" => "opening",
+            "close" => "closing",
+            "stop" => "stopped",
+            other => other,
+        };
+        self.update_cover_state(device_name, host, channel, state);
+
+        let calibrated = roller.positioning.unwrap_or(false);
+        self.cover_calibrated
+            .with_label_values(&[device_name, host, channel])
+            .set(if calibrated { 1 } else { 0 });
+
+        if calibrated && let Some(position) = roller.current_pos {
+            self.cover_position_percent
+                .with_label_values(&[device_name, host, channel])
+                .set(position);
+        }
+
+        if let Some(power) = roller.power {
+            self.cover_power_watts
+                .with_label_values(&[device_name, host, channel])
+                .set(power);
+        }
+    }
+
+    fn update_light_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        channel: &str,
+        light: &LightStatus,
+    ) {
+        self.light_output
+            .with_label_values(&[device_name, host, channel])
+            .set(if light.output { 1 } else { 0 });
+
+        if let Some(brightness) = light.brightness {
+            self.light_brightness_percent
+                .with_label_values(&[device_name, host, channel])
+                .set(brightness);
+        }
+
+        if let Some(ct) = light.ct {
+            self.light_color_temperature_kelvin
+                .with_label_values(&[device_name, host, channel])
+                .set(ct);
+        }
+
+        if let Some(rgb) = &light.rgb {
+            for (color, value) in ["red", "green", "blue"].iter().zip(rgb) {
+                self.light_color_value
+                    .with_label_values(&[device_name, host, channel, color])
+                    .set(*value);
+            }
+        }
+
+        if let Some(white) = light.white {
+            self.light_color_value
+                .with_label_values(&[device_name, host, channel, "white"])
+                .set(white);
+        }
+
+        // Temperature
+        if let Some(temp) = &light.temperature
+            && let Some(t_c) = temp.t_c
+        {
+            self.device_temperature
+                .with_label_values(&[device_name, host])
+                .set(t_c);
+        }
+
+        // Power metrics
+        if let Some(power) = light.apower {
+            self.light_power_watts
+                .with_label_values(&[device_name, host, channel])
+                .set(power);
+        }
+
+        if let Some(voltage) = light.voltage {
+            self.light_voltage_volts
+                .with_label_values(&[device_name, host, channel])
+                .set(voltage);
+        }
+
+        if let Some(current) = light.current {
+            self.light_current_amps
+                .with_label_values(&[device_name, host, channel])
+                .set(current);
+        }
+
+        if let Some(energy) = &light.aenergy {
+            self.light_energy_total_wh
+                .with_label_values(&[device_name, host, channel])
+                .set(energy.total);
+        }
+    }
+
+    fn update_gen1_light_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        channel: &str,
+        light: &LightGen1Status,
+    ) {
+        self.light_output
+            .with_label_values(&[device_name, host, channel])
+            .set(if light.ison { 1 } else { 0 });
+
+        // Color mode reports its brightness as gain
+        if let Some(brightness) = light.brightness.or(light.gain) {
+            self.light_brightness_percent
+                .with_label_values(&[device_name, host, channel])
+                .set(brightness);
+        }
+
+        if let Some(temp) = light.temp {
+            self.light_color_temperature_kelvin
+                .with_label_values(&[device_name, host, channel])
+                .set(temp);
+        }
+
+        let colors = [
+            ("red", light.red),
+            ("green", light.green),
+            ("blue", light.blue),
+            // On the Duo `white` is a percentage mix, not a channel value
+            ("white", light.white.filter(|_| light.temp.is_none())),
+        ];
+        for (color, value) in colors {
+            if let Some(value) = value {
+                self.light_color_value
+                    .with_label_values(&[device_name, host, channel, color])
+                    .set(value);
+            }
+        }
+    }
+
+    fn update_input_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        channel: &str,
+        input: &InputStatus,
+    ) {
+        if let Some(state) = input.state {
+            self.input_state
+                .with_label_values(&[device_name, host, channel])
+                .set(if state { 1 } else { 0 });
+        }
+
+        if let Some(percent) = input.percent {
+            self.input_percent
+                .with_label_values(&[device_name, host, channel])
+                .set(percent);
+        }
+
+        if let Some(freq) = input.freq {
+            self.input_frequency_hz
+                .with_label_values(&[device_name, host, channel])
+                .set(freq);
+        }
+
+        let Some(counts) = &input.counts else {
+            return;
+        };
+
+        let key = (
+            device_name.to_string(),
+            host.to_string(),
+            channel.to_string(),
+        );
+        let previous = self
+            .input_count_totals
+            .lock()
+            .unwrap()
+            .insert(key, counts.total);
+
+        // The first reading seeds the counter with the device total so it
+        // matches the meter. A lower total means the device counter was
+        // reset, and everything counted since then is new.
+        let pulses = match previous {
+            Some(previous) if counts.total >= previous => counts.total - previous,
+            _ => counts.total,
+        };
+
+        let factor = self
+            .pulse_factors
+            .get(&(host.to_string(), channel.to_string()))
+            .copied()
+            .unwrap_or(1.0);
+
+        self.input_count_total
+            .with_label_values(&[device_name, host, channel])
+            .inc_by(pulses * factor);
+    }
+
+    fn update_gen1_input_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        channel: &str,
+        input: &InputGen1Status,
+    ) {
+        self.input_state
+            .with_label_values(&[device_name, host, channel])
+            .set(if input.input != 0 { 1 } else { 0 });
+
+        let Some(event_cnt) = input.event_cnt else {
+            return;
+        };
+
+        let key = (
+            device_name.to_string(),
+            host.to_string(),
+            channel.to_string(),
+        );
+        let previous = self
+            .input_event_counts
+            .lock()
+            .unwrap()
+            .insert(key, event_cnt);
+
+        // The device only reports the type of the latest event, so every event
+        // since the previous poll is attributed to it. A lower count means the
+        // device restarted and the new count becomes the baseline.
+        if let Some(previous) = previous
+            && event_cnt > previous
+            && let Some(event) = input.event.as_deref().filter(|e| !e.is_empty())
+        {
+            self.input_events_total
+                .with_label_values(&[device_name, host, channel, event])
+                .inc_by((event_cnt - previous) as u64);
+        }
+    }
+
+    fn update_em_metrics(&self, device_name: &str, host: &str, channel: &str, em: &EmStatus) {
+        for (phase, readings) in em.phases() {
+            let labels = [device_name, host, channel, phase];
+
+            if let Some(voltage) = readings.voltage {
+                self.em_voltage_volts
+                    .with_label_values(&labels)
+                    .set(voltage);
+            }
+
+            if let Some(current) = readings.current {
+                self.em_current_amps.with_label_values(&labels).set(current);
+            }
+
+            if let Some(power) = readings.act_power {
+                self.em_active_power_watts
+                    .with_label_values(&labels)
+                    .set(power);
+            }
+
+            if let Some(power) = readings.aprt_power {
+                self.em_apparent_power_va
+                    .with_label_values(&labels)
+                    .set(power);
+            }
+
+            if let Some(pf) = readings.pf {
+                self.em_power_factor.with_label_values(&labels).set(pf);
+            }
+
+            if let Some(freq) = readings.freq {
+                self.em_frequency_hz.with_label_values(&labels).set(freq);
+            }
+        }
+
+        if let Some(current) = em.n_current {
+            self.em_neutral_current_amps
+                .with_label_values(&[device_name, host, channel])
+                .set(current);
+        }
+
+        if let Some(imbalance) = em.current_imbalance() {
+            self.em_current_imbalance_ratio
+                .with_label_values(&[device_name, host, channel])
+                .set(imbalance);
+        }
+    }
+
+    fn update_emdata_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        channel: &str,
+        emdata: &EmDataStatus,
+    ) {
+        for (phase, energy, returned) in emdata.phases() {
+            let labels = [device_name, host, channel, phase];
+
+            if let Some(energy) = energy {
+                self.em_energy_total_wh
+                    .with_label_values(&labels)
+                    .set(energy);
+            }
+
+            if let Some(returned) = returned {
+                self.em_returned_energy_total_wh
+                    .with_label_values(&labels)
+                    .set(returned);
+            }
+        }
+    }
+
+    fn update_em1_metrics(&self, device_name: &str, host: &str, channel: &str, em1: &Em1Status) {
+        let labels = [device_name, host, channel];
+
+        if let Some(power) = em1.act_power {
+            self.emeter_power_watts
+                .with_label_values(&labels)
+                .set(power);
+        }
+
+        if let Some(power) = em1.aprt_power {
+            self.emeter_apparent_power_va
+                .with_label_values(&labels)
+                .set(power);
+        }
+
+        if let Some(voltage) = em1.voltage {
+            self.emeter_voltage_volts
+                .with_label_values(&labels)
+                .set(voltage);
+        }
+
+        if let Some(current) = em1.current {
+            self.emeter_current_amps
+                .with_label_values(&labels)
+                .set(current);
+        }
+
+        if let Some(pf) = em1.pf {
+            self.emeter_power_factor.with_label_values(&labels).set(pf);
+        }
+
+        if let Some(freq) = em1.freq {
+            self.emeter_frequency_hz
+                .with_label_values(&labels)
+                .set(freq);
+        }
+    }
+
+    fn update_em1data_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        channel: &str,
+        em1data: &Em1DataStatus,
+    ) {
+        let labels = [device_name, host, channel];
+
+        if let Some(energy) = em1data.total_act_energy {
+            self.emeter_energy_total_wh
+                .with_label_values(&labels)
+                .set(energy);
+        }
+
+        if let Some(returned) = em1data.total_act_ret_energy {
+            self.emeter_returned_energy_total_wh
+                .with_label_values(&labels)
+                .set(returned);
+        }
+    }
+
+    fn update_pm1_metrics(&self, device_name: &str, host: &str, channel: &str, pm1: &Pm1Status) {
+        let labels = [device_name, host, channel];
+
+        if let Some(power) = pm1.apower {
+            self.emeter_power_watts
+                .with_label_values(&labels)
+                .set(power);
+        }
+
+        if let Some(voltage) = pm1.voltage {
+            self.emeter_voltage_volts
+                .with_label_values(&labels)
+                .set(voltage);
+        }
+
+        if let Some(current) = pm1.current {
+            self.emeter_current_amps
+                .with_label_values(&labels)
+                .set(current);
+        }
+
+        if let Some(pf) = pm1.pf {
+            self.emeter_power_factor.with_label_values(&labels).set(pf);
+        }
+
+        if let Some(freq) = pm1.freq {
+            self.emeter_frequency_hz
+                .with_label_values(&labels)
+                .set(freq);
+        }
+
+        if let Some(energy) = &pm1.aenergy {
+            self.emeter_energy_total_wh
+                .with_label_values(&labels)
+                .set(energy.total);
+        }
+
+        if let Some(returned) = &pm1.ret_aenergy {
+            self.emeter_returned_energy_total_wh
+                .with_label_values(&labels)
+                .set(returned.total);
+        }
+    }
+
+    fn update_gen1_emeter_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        channel: &str,
+        emeter: &EmeterStatus,
+    ) {
+        if !emeter.is_valid {
+            debug!(
+                "Skipping invalid emeter {} reading on {} ({})",
+                channel, device_name, host
+            );
+            return;
+        }
+
+        let labels = [device_name, host, channel];
+
+        self.emeter_power_watts
+            .with_label_values(&labels)
+            .set(emeter.power);
+
+        if let Some(reactive) = emeter.reactive {
+            self.emeter_reactive_power_var
+                .with_label_values(&labels)
+                .set(reactive);
+        }
+
+        if let Some(voltage) = emeter.voltage {
+            self.emeter_voltage_volts
+                .with_label_values(&labels)
+                .set(voltage);
+        }
+
+        if let Some(current) = emeter.current {
+            self.emeter_current_amps
+                .with_label_values(&labels)
+                .set(current);
+        }
+
+        if let Some(pf) = emeter.pf {
+            self.emeter_power_factor.with_label_values(&labels).set(pf);
+        }
+
+        if let Some(total) = emeter.total {
+            self.emeter_energy_total_wh
+                .with_label_values(&labels)
+                .set(total);
+        }
+
+        if let Some(returned) = emeter.total_returned {
+            self.emeter_returned_energy_total_wh
+                .with_label_values(&labels)
+                .set(returned);
+        }
+    }
+
+    fn update_temperature_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        channel: &str,
+        temperature: &TemperatureStatus,
+    ) {
+        if let Some(t_c) = temperature.t_c {
+            self.sensor_temperature_celsius
+                .with_label_values(&[device_name, host, channel])
+                .set(t_c);
+        }
+    }
+
+    fn update_humidity_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        channel: &str,
+        humidity: &HumidityStatus,
+    ) {
+        if let Some(rh) = humidity.rh {
+            self.sensor_humidity_percent
+                .with_label_values(&[device_name, host, channel])
+                .set(rh);
+        }
+    }
+
+    fn update_device_power_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        channel: &str,
+        power: &DevicePowerStatus,
+    ) {
+        let labels = [device_name, host, channel];
+
+        if let Some(battery) = &power.battery {
+            if let Some(percent) = battery.percent {
+                self.battery_percent.with_label_values(&labels).set(percent);
+            }
+
+            if let Some(voltage) = battery.voltage {
+                self.battery_voltage_volts
+                    .with_label_values(&labels)
+                    .set(voltage);
+            }
+        }
+
+        if let Some(external) = &power.external {
+            self.external_power_present
+                .with_label_values(&labels)
+                .set(if external.present { 1 } else { 0 });
+        }
+    }
+
+    fn update_alarm(
+        &self,
+        gauge: &IntGaugeVec,
+        device_name: &str,
+        host: &str,
+        channel: &str,
+        alarm: &AlarmStatus,
+    ) {
+        gauge
+            .with_label_values(&[device_name, host, channel])
+            .set(if alarm.alarm { 1 } else { 0 });
+    }
+
+    fn update_thermostat_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        channel: &str,
+        thermostat: &ThermostatStatus,
+    ) {
+        let labels = [device_name, host, channel];
+
+        if let Some(target) = thermostat.target_c {
+            self.thermostat_target_celsius
+                .with_label_values(&labels)
+                .set(target);
+        }
+
+        if let Some(current) = thermostat.current_c {
+            self.thermostat_current_celsius
+                .with_label_values(&labels)
+                .set(current);
+        }
+
+        if let Some(pos) = thermostat.pos {
+            self.thermostat_valve_position_percent
+                .with_label_values(&labels)
+                .set(pos);
+        }
+
+        if let Some(enable) = thermostat.enable {
+            self.thermostat_enabled
+                .with_label_values(&labels)
+                .set(if enable { 1 } else { 0 });
+        }
+
+        if let Some(window_open) = thermostat.window_open {
+            self.thermostat_window_open
+                .with_label_values(&labels)
+                .set(if window_open { 1 } else { 0 });
+        }
+
+        if let Some(battery) = thermostat.battery {
+            self.battery_percent.with_label_values(&labels).set(battery);
+        }
+    }
+
+    fn update_gen1_thermostat_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        channel: &str,
+        thermostat: &ThermostatGen1Status,
+    ) {
+        let labels = [device_name, host, channel];
+
+        if let Some(target) = &thermostat.target_t {
+            if let Some(t_c) = target.celsius() {
+                self.thermostat_target_celsius
+                    .with_label_values(&labels)
+                    .set(t_c);
+            }
+            if let Some(enabled) = target.enabled {
+                self.thermostat_enabled
+                    .with_label_values(&labels)
+                    .set(if enabled { 1 } else { 0 });
+            }
+        }
+
+        if let Some(t_c) = thermostat.tmp.as_ref().and_then(|tmp| tmp.celsius()) {
+            self.thermostat_current_celsius
+                .with_label_values(&labels)
+                .set(t_c);
+        }
+
+        // The position is -1 until the valve has been calibrated
+        if let Some(pos) = thermostat.pos.filter(|pos| *pos >= 0.0) {
+            self.thermostat_valve_position_percent
+                .with_label_values(&labels)
+                .set(pos);
+        }
+
+        if let Some(window_open) = thermostat.window_open {
+            self.thermostat_window_open
+                .with_label_values(&labels)
+                .set(if window_open { 1 } else { 0 });
+        }
+    }
+
+    fn update_bthome_device_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        config: &BthomeConfig,
+        bthome: &BthomeDeviceStatus,
+    ) {
+        let labels = [
+            device_name,
+            host,
+            config.addr.as_str(),
+            config.name.as_deref().unwrap_or(""),
+        ];
+
+        if let Some(battery) = bthome.battery {
+            self.bthome_battery_percent
+                .with_label_values(&labels)
+                .set(battery);
+        }
+
+        if let Some(rssi) = bthome.rssi {
+            self.bthome_rssi_dbm.with_label_values(&labels).set(rssi);
+        }
+
+        if let Some(packet_id) = bthome.packet_id {
+            self.bthome_packet_id
+                .with_label_values(&labels)
+                .set(packet_id);
+        }
+
+        if let Some(ts) = bthome.last_updated_ts {
+            self.bthome_last_updated.with_label_values(&labels).set(ts);
+        }
+    }
+
+    fn update_bthome_sensor_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        name: &str,
+        config: &BthomeConfig,
+        bthome: &BthomeSensorStatus,
+    ) {
+        let (Some(obj_id), Some(value)) = (config.obj_id, bthome.numeric_value()) else {
+            return;
+        };
+
+        let object_id = obj_id.to_string();
+        let index = config.idx.unwrap_or(0).to_string();
+        self.bthome_sensor_value
+            .with_label_values(&[
+                device_name,
+                host,
+                &config.addr,
+                name,
+                &object_id,
+                &index,
+                bthome_object_name(obj_id),
+            ])
+            .set(value);
+    }
+
+    fn update_gen1_sensor_metrics(
+        &self,
+        device_name: &str,
+        host: &str,
+        model: &str,
+        status: &ShellyGen1Status,
+    ) {
+        // Gen1 sensors report a single reading of each kind
+        let labels = [device_name, host, "0"];
+
+        // Relays and dimmers report their internal temperature as `tmp` too,
+        // which is already exported as the device temperature
+        let has_outputs = matches!(
+            Gen1Family::from_model(model),
+            Some(Gen1Family::Relay | Gen1Family::Light)
+        ) || status.relays.is_some()
+            || status.lights.is_some()
+            || status.meters.is_some();
+
+        if !has_outputs && let Some(t_c) = status.tmp.as_ref().and_then(|tmp| tmp.celsius()) {
+            self.sensor_temperature_celsius
+                .with_label_values(&labels)
+                .set(t_c);
+        }
+
+        if let Some(rh) = status.hum.as_ref().and_then(|hum| hum.valid_value()) {
+            self.sensor_humidity_percent
+                .with_label_values(&labels)
+                .set(rh);
+        }
+
+        if let Some(lux) = status.lux.as_ref().and_then(|lux| lux.valid_value()) {
+            self.sensor_illuminance_lux
+                .with_label_values(&labels)
+                .set(lux);
+        }
+
+        if let Some(tilt) = status.accel.as_ref().and_then(|accel| accel.tilt) {
+            // Tilt is -1 while the sensor is not calibrated
+            if tilt >= 0.0 {
+                self.sensor_tilt_degrees
+                    .with_label_values(&labels)
+                    .set(tilt);
+            }
+        }
+
+        if let Some(sensor) = &status.sensor
+            && sensor.is_valid != Some(false)
+        {
+            if let Some(state) = sensor.state.as_deref() {
+                self.sensor_open
+                    .with_label_values(&labels)
+                    .set(if state == "open" { 1 } else { 0 });
+            }
+
+            if let Some(motion) = sensor.motion {
+                self.sensor_motion
+                    .with_label_values(&labels)
+                    .set(if motion { 1 } else { 0 });
+            }
+
+            if let Some(vibration) = sensor.vibration {
+                self.sensor_vibration
+                    .with_label_values(&labels)
+                    .set(if vibration { 1 } else { 0 });
+            }
+        }
+
+        // Door/Window reports vibration as 0/1, or -1 when detection is disabled
+        if let Some(vibration) = status.accel.as_ref().and_then(|accel| accel.vibration)
+            && vibration >= 0
+        {
+            self.sensor_vibration
+                .with_label_values(&labels)
+                .set(if vibration > 0 { 1 } else { 0 });
+        }
+
+        if let Some(flood) = status.flood {
+            self.flood_alarm
+                .with_label_values(&labels)
+                .set(if flood { 1 } else { 0 });
+        }
+
+        if let Some(smoke) = status.smoke {
+            self.smoke_alarm
+                .with_label_values(&labels)
+                .set(if smoke { 1 } else { 0 });
+        }
+
+        if let Some(gas) = &status.gas_sensor {
+            let states = [
+                (&self.gas_alarm_state, GAS_ALARM_STATES, &gas.alarm_state),
+                (&self.gas_sensor_state, GAS_SENSOR_STATES, &gas.sensor_state),
+                (
+                    &self.gas_self_test_state,
+                    GAS_SELF_TEST_STATES,
+                    &gas.self_test_state,
+                ),
+            ];
+            for (gauge, known, state) in states {
+                if let Some(state) = state.as_deref() {
+                    self.set_state(gauge, known, &labels, state);
+                }
+            }
+        }
+
+        if let Some(concentration) = &status.concentration
+            && concentration.is_valid != Some(false)
+            && let Some(ppm) = concentration.ppm
+        {
+            self.gas_concentration_ppm
+                .with_label_values(&labels)
+                .set(ppm);
+        }
+
+        if let Some(bat) = &status.bat {
+            if let Some(percent) = bat.value {
+                self.battery_percent.with_label_values(&labels).set(percent);
+            }
+
+            if let Some(voltage) = bat.voltage {
+                self.battery_voltage_volts
+                    .with_label_values(&labels)
+                    .set(voltage);
+            }
+        }
+
+        if let Some(charger) = status.charger {
+            self.external_power_present
+                .with_label_values(&labels)
+                .set(if charger { 1 } else { 0 });
+        }
+
+        // Temperature add-on
+        for (sensor, probe) in status.ext_temperature.iter().flatten() {
+            if let Some(t_c) = probe.t_c {
+                let sensor_id = probe.hw_id.as_deref().unwrap_or("");
+                self.external_temperature_celsius
+                    .with_label_values(&[device_name, host, sensor, sensor_id])
+                    .set(t_c);
+            }
+        }
+
+        for (sensor, probe) in status.ext_humidity.iter().flatten() {
+            if let Some(hum) = probe.hum {
+                let sensor_id = probe.hw_id.as_deref().unwrap_or("");
+                self.external_humidity_percent
+                    .with_label_values(&[device_name, host, sensor, sensor_id])
+                    .set(hum);
+            }
+        }
+
+        for (sensor, switch) in status.ext_switch.iter().flatten() {
+            self.external_switch_state
+                .with_label_values(&[device_name, host, sensor])
+                .set(if switch.input != 0 { 1 } else { 0 });
+        }
+    }
+
+    fn update_cover_state(&self, device_name: &str, host: &str, channel: &str, state: &str) {
+        self.set_state(
+            &self.cover_state,
+            COVER_STATES,
+            &[device_name, host, channel],
+            state,
+        );
+    }
+
+    /// Set a state set gauge: 1 for `state`, 0 for every other known state.
+    fn set_state(&self, gauge: &IntGaugeVec, known: &[&str], labels: &[&str; 3], state: &str) {
+        for candidate in known {
+            gauge
+                .with_label_values(&[labels[0], labels[1], labels[2], candidate])
+                .set(if *candidate == state { 1 } else { 0 });
+        }
+
+        if !known.contains(&state) {
+            debug!("Unknown state {} on {} ({})", state, labels[0], labels[1]);
+        }
+    }
+
+    /// Count a failed status request under the reason of `error`.
+    pub fn record_scrape_error(&self, device_name: &str, host: &str, error: &ShellyError) {
+        self.scrape_errors_total
+            .with_label_values(&[device_name, host, error.reason()])
+            .inc();
+    }
+
+    pub fn mark_device_down(&self, device_name: &str, host: &str, model: &str, generation: &str) {
+        error!("Marking device {} as down", device_name);
+        self.device_up
+            .with_label_values(&[device_name, host, model, generation])
+            .set(0);
+    }
+
+    /// Handle a failed poll of a sleepy (battery powered) device.
+    ///
+    /// The last readings are kept and flagged as stale while the device has been
+    /// silent for at most `max_silence`; after that it is marked down.
+    pub fn mark_device_silent(
+        &self,
+        device_name: &str,
+        host: &str,
+        model: &str,
+        generation: &str,
+        max_silence: Duration,
+    ) {
+        let last_seen = self
+            .last_seen
+            .lock()
+            .unwrap()
+            .get(&(device_name.to_string(), host.to_string()))
+            .copied();
+
+        let silence = last_seen.and_then(|seen| (Utc::now() - seen).to_std().ok());
+
+        match silence {
+            Some(silence) if silence <= max_silence => {
+                debug!(
+                    "Sleepy device {} silent for {}s, keeping last readings",
+                    device_name,
+                    silence.as_secs()
+                );
+                self.device_stale
+                    .with_label_values(&[device_name, host])
+                    .set(1);
+            }
+            _ => {
+                self.device_stale
+                    .with_label_values(&[device_name, host])
+                    .set(1);
+                self.mark_device_down(device_name, host, model, generation);
+            }
+        }
+    }
+
+    pub fn gather(&self) -> Result<String> {
         let encoder = TextEncoder::new();
         let metric_families = self.registry.gather();
         let mut buffer = Vec::new();
@@ -410,62 +2359,66 @@ impl Metrics {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::shelly::{EnergyCounter, SwitchStatus, SystemStatus, Temperature, WifiStatus};
+    use crate::shelly::{ComponentEvent, EnergyCounter, SystemStatus, Temperature, WifiStatus};
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_gen2_metrics_update() {
-        // Use a single metrics instance for this test
-        let metrics = match Metrics::new() {
-            Ok(m) => m,
-            Err(_) => {
-                // If metrics are already registered, that's okay for tests
-                return;
-            }
+        let metrics = Metrics::new().unwrap();
+
+        let switch = SwitchStatus {
+            id: 0,
+            source: Some("manual".to_string()),
+            output: true,
+            apower: Some(25.5),
+            voltage: Some(230.0),
+            current: Some(0.11),
+            freq: Some(50.0),
+            pf: Some(0.98),
+            aenergy: Some(EnergyCounter {
+                total: 1500.0,
+                by_minute: vec![],
+                minute_ts: 0,
+            }),
+            ret_aenergy: None,
+            temperature: Some(Temperature {
+                t_c: Some(30.5),
+                t_f: Some(86.9),
+            }),
         };
 
         let status = ShellyGen2Status {
-            switch_0: Some(SwitchStatus {
-                id: 0,
-                source: Some("manual".to_string()),
-                output: true,
-                apower: Some(25.5),
-                voltage: Some(230.0),
-                current: Some(0.11),
-                freq: Some(50.0),
-                pf: Some(0.98),
-                aenergy: Some(EnergyCounter {
-                    total: 1500.0,
-                    by_minute: vec![],
-                    minute_ts: 0,
-                }),
-                ret_aenergy: None,
-                temperature: Some(Temperature {
-                    t_c: Some(30.5),
-                    t_f: Some(86.9),
-                }),
-            }),
-            switch_1: None,
-            switch_2: None,
-            switch_3: None,
             sys: Some(SystemStatus {
-                mac: "AA:BB:CC:DD:EE:FF".to_string(),
-                restart_required: false,
+                mac: Some("AA:BB:CC:DD:EE:FF".to_string()),
+                restart_required: Some(false),
                 time: None,
                 unixtime: None,
-                uptime: 3600,
-                ram_size: 262144,
-                ram_free: 131072,
-                fs_size: 524288,
-                fs_free: 262144,
-                cfg_rev: 1,
+                uptime: Some(3600),
+                ram_size: Some(262144),
+                ram_free: Some(131072),
+                fs_size: Some(524288),
+                fs_free: Some(262144),
+                cfg_rev: Some(1),
                 available_updates: None,
             }),
             wifi: Some(WifiStatus {
                 sta_ip: Some("192.168.1.100".to_string()),
-                status: "got ip".to_string(),
+                status: Some("got ip".to_string()),
                 ssid: Some("TestNetwork".to_string()),
                 rssi: Some(-65),
             }),
+            components: BTreeMap::from([
+                ("switch:0".to_string(), Component::Switch(switch.clone())),
+                (
+                    "switch:5".to_string(),
+                    Component::Switch(SwitchStatus { id: 5, ..switch }),
+                ),
+                (
+                    "cloud".to_string(),
+                    Component::Unknown(serde_json::json!({"connected": true})),
+                ),
+            ]),
+            ..Default::default()
         };
 
         metrics
@@ -483,15 +2436,815 @@ mod tests {
         assert!(output.contains("shelly_switch_power_watts"));
         assert!(output.contains("shelly_device_temperature_celsius"));
         assert!(output.contains("shelly_wifi_rssi_dbm"));
+        assert!(output.contains(r#"shelly_switch_power_watts{channel="5",device="test_device",host="192.168.1.100"} 25.5"#));
     }
 
     #[test]
-    fn test_device_down_marking() {
-        // Skip if metrics are already registered
-        let metrics = match Metrics::new() {
-            Ok(m) => m,
-            Err(_) => return,
+    fn test_cover_metrics_update() {
+        let metrics = Metrics::new().unwrap();
+
+        let cover: CoverStatus = serde_json::from_value(serde_json::json!({
+            "id": 0,
+            "source": "WS_in",
+            "state": "closing",
+            "apower": 112.3,
+            "voltage": 231.4,
+            "current": 0.52,
+            "pf": -0.91,
+            "freq": 50.0,
+            "aenergy": {"total": 321.5, "by_minute": [0.0, 0.0, 0.0], "minute_ts": 1700000000},
+            "temperature": {"tC": 41.2, "tF": 106.2},
+            "pos_control": true,
+            "last_direction": "close",
+            "current_pos": 63,
+            "target_pos": 20
+        }))
+        .unwrap();
+
+        let status = ShellyGen2Status {
+            components: BTreeMap::from([("cover:0".to_string(), Component::Cover(cover))]),
+            ..Default::default()
+        };
+        metrics
+            .update_device(
+                "blinds",
+                "192.168.1.110",
+                "SNSW-102P16EU",
+                "gen2",
+                &ShellyStatus::Gen2(Box::new(status)),
+            )
+            .unwrap();
+
+        let gen1: ShellyGen1Status = serde_json::from_value(serde_json::json!({
+            "rollers": [{
+                "state": "stop",
+                "power": 0.0,
+                "is_valid": true,
+                "safety_switch": false,
+                "overtemperature": false,
+                "stop_reason": "normal",
+                "last_direction": "open",
+                "current_pos": 100,
+                "calibrating": false,
+                "positioning": true
+            }]
+        }))
+        .unwrap();
+        metrics
+            .update_device(
+                "shutter",
+                "192.168.1.111",
+                "SHSW-25",
+                "gen1",
+                &ShellyStatus::Gen1(Box::new(gen1)),
+            )
+            .unwrap();
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"shelly_cover_state{channel="0",device="blinds",host="192.168.1.110",state="closing"} 1"#));
+        assert!(output.contains(
+            r#"shelly_cover_state{channel="0",device="blinds",host="192.168.1.110",state="open"} 0"#
+        ));
+        assert!(output.contains(
+            r#"shelly_cover_position_percent{channel="0",device="blinds",host="192.168.1.110"} 63"#
+        ));
+        assert!(output.contains(r#"shelly_cover_target_position_percent{channel="0",device="blinds",host="192.168.1.110"} 20"#));
+        assert!(output.contains(
+            r#"shelly_cover_power_watts{channel="0",device="blinds",host="192.168.1.110"} 112.3"#
+        ));
+        assert!(output.contains(r#"shelly_cover_energy_total_wh{channel="0",device="blinds",host="192.168.1.110"} 321.5"#));
+        assert!(output.contains(r#"shelly_cover_state{channel="0",device="shutter",host="192.168.1.111",state="stopped"} 1"#));
+        assert!(output.contains(r#"shelly_cover_position_percent{channel="0",device="shutter",host="192.168.1.111"} 100"#));
+        assert!(output.contains(
+            r#"shelly_cover_calibrated{channel="0",device="shutter",host="192.168.1.111"} 1"#
+        ));
+    }
+
+    #[test]
+    fn test_light_metrics_update() {
+        let metrics = Metrics::new().unwrap();
+
+        let rgbw: LightStatus = serde_json::from_value(serde_json::json!({
+            "id": 0,
+            "output": true,
+            "rgb": [255, 0, 64],
+            "brightness": 70,
+            "white": 128,
+            "apower": 9.3,
+            "voltage": 12.1,
+            "current": 0.78,
+            "aenergy": {"total": 12.4, "by_minute": [], "minute_ts": 0}
+        }))
+        .unwrap();
+
+        let status = ShellyGen2Status {
+            components: BTreeMap::from([("rgbw:0".to_string(), Component::Light(rgbw))]),
+            ..Default::default()
+        };
+        metrics
+            .update_device(
+                "strip",
+                "192.168.1.120",
+                "SNDC-0D4P10WW",
+                "gen2",
+                &ShellyStatus::Gen2(Box::new(status)),
+            )
+            .unwrap();
+
+        let dimmer: ShellyGen1Status = serde_json::from_value(serde_json::json!({
+            "lights": [{"ison": true, "mode": "white", "brightness": 42}],
+            "meters": [{"power": 18.5, "is_valid": true, "timestamp": 0, "counters": [], "total": 5021}]
+        }))
+        .unwrap();
+        metrics
+            .update_device(
+                "hallway",
+                "192.168.1.121",
+                "SHDM-2",
+                "gen1",
+                &ShellyStatus::Gen1(Box::new(dimmer)),
+            )
+            .unwrap();
+
+        let output = metrics.gather().unwrap();
+        assert!(
+            output.contains(
+                r#"shelly_light_output{channel="0",device="strip",host="192.168.1.120"} 1"#
+            )
+        );
+        assert!(output.contains(r#"shelly_light_color_value{channel="0",color="blue",device="strip",host="192.168.1.120"} 64"#));
+        assert!(output.contains(r#"shelly_light_color_value{channel="0",color="white",device="strip",host="192.168.1.120"} 128"#));
+        assert!(output.contains(
+            r#"shelly_light_brightness_percent{channel="0",device="strip",host="192.168.1.120"} 70"#
+        ));
+        assert!(output.contains(
+            r#"shelly_light_power_watts{channel="0",device="strip",host="192.168.1.120"} 9.3"#
+        ));
+        assert!(output.contains(r#"shelly_light_brightness_percent{channel="0",device="hallway",host="192.168.1.121"} 42"#));
+        assert!(output.contains(
+            r#"shelly_light_power_watts{channel="0",device="hallway",host="192.168.1.121"} 18.5"#
+        ));
+        assert!(!output.contains(r#"shelly_switch_power_watts{channel="0",device="hallway""#));
+    }
+
+    #[test]
+    fn test_input_metrics_update() {
+        let metrics = Metrics::new().unwrap();
+
+        let status = ShellyGen2Status {
+            components: BTreeMap::from([
+                (
+                    "input:0".to_string(),
+                    Component::Input(
+                        serde_json::from_value(serde_json::json!({"id": 0, "state": true}))
+                            .unwrap(),
+                    ),
+                ),
+                (
+                    "input:1".to_string(),
+                    Component::Input(
+                        serde_json::from_value(serde_json::json!({"id": 1, "percent": 37.5}))
+                            .unwrap(),
+                    ),
+                ),
+            ]),
+            ..Default::default()
+        };
+        metrics
+            .update_device(
+                "i4",
+                "192.168.1.130",
+                "SNSN-0024X",
+                "gen2",
+                &ShellyStatus::Gen2(Box::new(status)),
+            )
+            .unwrap();
+
+        let gen1_status = |event: &str, event_cnt: i64| {
+            let status: ShellyGen1Status = serde_json::from_value(serde_json::json!({
+                "inputs": [{"input": 1, "event": event, "event_cnt": event_cnt}]
+            }))
+            .unwrap();
+            ShellyStatus::Gen1(Box::new(status))
+        };
+        for (event, event_cnt) in [("S", 10), ("S", 12), ("L", 13), ("S", 1), ("S", 2)] {
+            metrics
+                .update_device(
+                    "button",
+                    "192.168.1.131",
+                    "SHIX3-1",
+                    "gen1",
+                    &gen1_status(event, event_cnt),
+                )
+                .unwrap();
+        }
+
+        let output = metrics.gather().unwrap();
+        assert!(
+            output
+                .contains(r#"shelly_input_state{channel="0",device="i4",host="192.168.1.130"} 1"#)
+        );
+        assert!(output.contains(
+            r#"shelly_input_percent{channel="1",device="i4",host="192.168.1.130"} 37.5"#
+        ));
+        assert!(
+            output.contains(
+                r#"shelly_input_state{channel="0",device="button",host="192.168.1.131"} 1"#
+            )
+        );
+        assert!(output.contains(r#"shelly_input_events_total{channel="0",device="button",event="S",host="192.168.1.131"} 3"#));
+        assert!(output.contains(r#"shelly_input_events_total{channel="0",device="button",event="L",host="192.168.1.131"} 1"#));
+    }
+
+    #[test]
+    fn test_em_metrics_update() {
+        let metrics = Metrics::new().unwrap();
+
+        let em: EmStatus = serde_json::from_value(serde_json::json!({
+            "id": 0,
+            "a_current": 4.0, "a_voltage": 231.0, "a_act_power": 900.0, "a_aprt_power": 924.0,
+            "a_pf": 0.97, "a_freq": 50.0,
+            "b_current": 4.0, "b_voltage": 232.0, "b_act_power": -450.0, "b_aprt_power": 928.0,
+            "b_pf": -0.48, "b_freq": 50.0,
+            "c_current": 1.0, "c_voltage": 233.0, "c_act_power": 200.0, "c_aprt_power": 233.0,
+            "c_pf": 0.86, "c_freq": 50.0,
+            "n_current": 3.1, "total_current": 9.0, "total_act_power": 650.0,
+            "total_aprt_power": 2085.0
+        }))
+        .unwrap();
+        let emdata: EmDataStatus = serde_json::from_value(serde_json::json!({
+            "id": 0,
+            "a_total_act_energy": 1000.5, "a_total_act_ret_energy": 0.0,
+            "b_total_act_energy": 20.0, "b_total_act_ret_energy": 3000.25,
+            "c_total_act_energy": 500.0, "c_total_act_ret_energy": 0.0,
+            "total_act": 1520.5, "total_act_ret": 3000.25
+        }))
+        .unwrap();
+
+        let status = ShellyGen2Status {
+            components: BTreeMap::from([
+                ("em:0".to_string(), Component::Em(em)),
+                ("emdata:0".to_string(), Component::EmData(emdata)),
+            ]),
+            ..Default::default()
+        };
+        metrics
+            .update_device(
+                "mains",
+                "192.168.1.140",
+                "SPEM-003CEBEU",
+                "gen2",
+                &ShellyStatus::Gen2(Box::new(status)),
+            )
+            .unwrap();
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"shelly_em_voltage_volts{channel="0",device="mains",host="192.168.1.140",phase="c"} 233"#));
+        assert!(output.contains(r#"shelly_em_active_power_watts{channel="0",device="mains",host="192.168.1.140",phase="b"} -450"#));
+        assert!(output.contains(r#"shelly_em_active_power_watts{channel="0",device="mains",host="192.168.1.140",phase="total"} 650"#));
+        assert!(output.contains(r#"shelly_em_current_amps{channel="0",device="mains",host="192.168.1.140",phase="total"} 9"#));
+        assert!(output.contains(
+            r#"shelly_em_neutral_current_amps{channel="0",device="mains",host="192.168.1.140"} 3.1"#
+        ));
+        assert!(output.contains(r#"shelly_em_current_imbalance_ratio{channel="0",device="mains",host="192.168.1.140"} 0.6666666666666666"#));
+        assert!(output.contains(r#"shelly_em_energy_total_wh{channel="0",device="mains",host="192.168.1.140",phase="a"} 1000.5"#));
+        assert!(output.contains(r#"shelly_em_returned_energy_total_wh{channel="0",device="mains",host="192.168.1.140",phase="total"} 3000.25"#));
+        assert!(!output.contains(r#"shelly_em_voltage_volts{channel="0",device="mains",host="192.168.1.140",phase="total"}"#));
+    }
+
+    #[test]
+    fn test_emeter_metrics_update() {
+        let metrics = Metrics::new().unwrap();
+
+        let status: ShellyGen2Status = serde_json::from_value(serde_json::json!({
+            "em1:1": {"id": 1, "current": 2.381, "voltage": 231.6, "act_power": -512.3,
+                "aprt_power": 551.4, "pf": -0.93, "freq": 50.0},
+            "em1data:1": {"id": 1, "total_act_energy": 182734.21, "total_act_ret_energy": 90211.07},
+            "pm1:0": {"id": 0, "voltage": 229.1, "current": 0.451, "apower": 97.6, "freq": 50.0,
+                "aenergy": {"total": 5963.174, "by_minute": [], "minute_ts": 0},
+                "ret_aenergy": {"total": 12.5, "by_minute": [], "minute_ts": 0}}
+        }))
+        .unwrap();
+        metrics
+            .update_device(
+                "meter",
+                "192.168.1.150",
+                "SPEM-002CEBEU50",
+                "gen2",
+                &ShellyStatus::Gen2(Box::new(status)),
+            )
+            .unwrap();
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(
+            r#"shelly_emeter_power_watts{channel="1",device="meter",host="192.168.1.150"} -512.3"#
+        ));
+        assert!(output.contains(r#"shelly_emeter_apparent_power_va{channel="1",device="meter",host="192.168.1.150"} 551.4"#));
+        assert!(output.contains(r#"shelly_emeter_returned_energy_total_wh{channel="1",device="meter",host="192.168.1.150"} 90211.07"#));
+        assert!(output.contains(
+            r#"shelly_emeter_power_watts{channel="0",device="meter",host="192.168.1.150"} 97.6"#
+        ));
+        assert!(output.contains(r#"shelly_emeter_energy_total_wh{channel="0",device="meter",host="192.168.1.150"} 5963.174"#));
+        assert!(output.contains(r#"shelly_emeter_returned_energy_total_wh{channel="0",device="meter",host="192.168.1.150"} 12.5"#));
+
+        let gen1: ShellyGen1Status = serde_json::from_value(serde_json::json!({
+            "emeters": [
+                {"power": 412.3, "reactive": -88.1, "voltage": 229.4, "is_valid": true,
+                    "total": 73311.2, "total_returned": 0.0},
+                {"power": -245.06, "pf": -0.61, "current": 1.72, "voltage": 232.9,
+                    "is_valid": true, "total": 812034.7, "total_returned": 151120.4},
+                {"power": 0.0, "is_valid": false}
+            ]
+        }))
+        .unwrap();
+        metrics
+            .update_device(
+                "em",
+                "192.168.1.151",
+                "SHEM",
+                "gen1",
+                &ShellyStatus::Gen1(Box::new(gen1)),
+            )
+            .unwrap();
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"shelly_emeter_reactive_power_var{channel="0",device="em",host="192.168.1.151"} -88.1"#));
+        assert!(output.contains(
+            r#"shelly_emeter_power_factor{channel="1",device="em",host="192.168.1.151"} -0.61"#
+        ));
+        assert!(output.contains(r#"shelly_emeter_returned_energy_total_wh{channel="1",device="em",host="192.168.1.151"} 151120.4"#));
+        assert!(!output.contains(r#"shelly_emeter_power_watts{channel="2",device="em""#));
+    }
+
+    #[test]
+    fn test_sensor_metrics_update() {
+        let metrics = Metrics::new().unwrap();
+
+        let plus_ht: ShellyGen2Status = serde_json::from_value(serde_json::json!({
+            "devicepower:0": {"id": 0, "battery": {"V": 5.62, "percent": 76},
+                "external": {"present": false}},
+            "humidity:0": {"id": 0, "rh": 52.3},
+            "temperature:0": {"id": 0, "tC": 19.4, "tF": 66.9},
+            "smoke:0": {"id": 0, "alarm": true, "mute": false}
+        }))
+        .unwrap();
+        metrics
+            .update_device(
+                "bedroom",
+                "192.168.1.160",
+                "SNSN-0013A",
+                "gen2",
+                &ShellyStatus::Gen2(Box::new(plus_ht)),
+            )
+            .unwrap();
+
+        let door: ShellyGen1Status = serde_json::from_value(serde_json::json!({
+            "sensor": {"state": "close", "is_valid": true},
+            "lux": {"value": 231, "illumination": "twilight", "is_valid": true},
+            "accel": {"tilt": 86, "vibration": 0},
+            "tmp": {"value": 21.3, "units": "C", "tC": 21.3, "tF": 70.3, "is_valid": true},
+            "bat": {"value": 62, "voltage": 5.52},
+            "flood": false,
+            "charger": true
+        }))
+        .unwrap();
+        metrics
+            .update_device(
+                "door",
+                "192.168.1.161",
+                "SHDW-2",
+                "gen1",
+                &ShellyStatus::Gen1(Box::new(door)),
+            )
+            .unwrap();
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"shelly_sensor_temperature_celsius{channel="0",device="bedroom",host="192.168.1.160"} 19.4"#));
+        assert!(output.contains(r#"shelly_sensor_humidity_percent{channel="0",device="bedroom",host="192.168.1.160"} 52.3"#));
+        assert!(output.contains(
+            r#"shelly_battery_percent{channel="0",device="bedroom",host="192.168.1.160"} 76"#
+        ));
+        assert!(output.contains(r#"shelly_battery_voltage_volts{channel="0",device="bedroom",host="192.168.1.160"} 5.62"#));
+        assert!(output.contains(
+            r#"shelly_external_power_present{channel="0",device="bedroom",host="192.168.1.160"} 0"#
+        ));
+        assert!(output.contains(
+            r#"shelly_smoke_alarm{channel="0",device="bedroom",host="192.168.1.160"} 1"#
+        ));
+        assert!(
+            output.contains(
+                r#"shelly_sensor_open{channel="0",device="door",host="192.168.1.161"} 0"#
+            )
+        );
+        assert!(output.contains(
+            r#"shelly_sensor_illuminance_lux{channel="0",device="door",host="192.168.1.161"} 231"#
+        ));
+        assert!(output.contains(
+            r#"shelly_sensor_tilt_degrees{channel="0",device="door",host="192.168.1.161"} 86"#
+        ));
+        assert!(output.contains(r#"shelly_sensor_temperature_celsius{channel="0",device="door",host="192.168.1.161"} 21.3"#));
+        assert!(output.contains(
+            r#"shelly_battery_percent{channel="0",device="door",host="192.168.1.161"} 62"#
+        ));
+        assert!(
+            output.contains(
+                r#"shelly_flood_alarm{channel="0",device="door",host="192.168.1.161"} 0"#
+            )
+        );
+        assert!(output.contains(
+            r#"shelly_external_power_present{channel="0",device="door",host="192.168.1.161"} 1"#
+        ));
+    }
+
+    #[test]
+    fn test_gen1_relay_temperature_not_a_sensor() {
+        let metrics = Metrics::new().unwrap();
+
+        let status: ShellyGen1Status = serde_json::from_value(serde_json::json!({
+            "relays": [{"ison": true}],
+            "meters": [{"power": 41.7, "is_valid": true, "total": 123456}],
+            "temperature": 48.6,
+            "tmp": {"tC": 48.6, "tF": 119.5, "is_valid": true},
+            "uptime": 3600
+        }))
+        .unwrap();
+        metrics
+            .update_device(
+                "kitchen",
+                "192.168.1.50",
+                "SHSW-PM",
+                "gen1",
+                &ShellyStatus::Gen1(Box::new(status)),
+            )
+            .unwrap();
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(
+            r#"shelly_device_temperature_celsius{device="kitchen",host="192.168.1.50"} 48.6"#
+        ));
+        assert!(!output.contains("shelly_sensor_temperature_celsius{"));
+    }
+
+    #[test]
+    fn test_external_sensor_metrics_update() {
+        let metrics = Metrics::new().unwrap();
+
+        let status: ShellyGen1Status = serde_json::from_value(serde_json::json!({
+            "ext_temperature": {
+                "0": {"hwID": "28e3b79e0b000058", "tC": 58.4, "tF": 137.1},
+                "1": {"hwID": "2893f79f0b0000d2", "tC": -18.6, "tF": -1.5}
+            },
+            "ext_humidity": {"0": {"hwID": "000000000000000e", "hum": 61.2}},
+            "ext_switch": {"0": {"input": 1}}
+        }))
+        .unwrap();
+        metrics
+            .update_device(
+                "boiler",
+                "192.168.1.180",
+                "SHSW-PM",
+                "gen1",
+                &ShellyStatus::Gen1(Box::new(status)),
+            )
+            .unwrap();
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"shelly_external_temperature_celsius{device="boiler",host="192.168.1.180",sensor="0",sensor_id="28e3b79e0b000058"} 58.4"#));
+        assert!(output.contains(r#"shelly_external_temperature_celsius{device="boiler",host="192.168.1.180",sensor="1",sensor_id="2893f79f0b0000d2"} -18.6"#));
+        assert!(output.contains(r#"shelly_external_humidity_percent{device="boiler",host="192.168.1.180",sensor="0",sensor_id="000000000000000e"} 61.2"#));
+        assert!(output.contains(
+            r#"shelly_external_switch_state{device="boiler",host="192.168.1.180",sensor="0"} 1"#
+        ));
+    }
+
+    #[test]
+    fn test_addon_peripheral_metrics_update() {
+        let mut pulse_factors = HashMap::new();
+        pulse_factors.insert(("192.168.1.190".to_string(), "100".to_string()), 0.001);
+        let metrics = Metrics::new().unwrap().with_pulse_factors(pulse_factors);
+
+        let status = |total: u64| -> ShellyStatus {
+            ShellyStatus::Gen2(Box::new(
+                serde_json::from_value(serde_json::json!({
+                    "temperature:100": {"id": 100, "tC": 21.4, "tF": 70.5},
+                    "humidity:100": {"id": 100, "rh": 48.7},
+                    "voltmeter:100": {"id": 100, "voltage": 4.12, "xvoltage": null},
+                    "input:100": {"id": 100, "counts": {"total": total}, "freq": 0.5},
+                    "input:101": {"id": 101, "counts": {"total": total}, "freq": 0.0},
+                    "illuminance:0": {"id": 0, "lux": 270, "illumination": "twilight"}
+                }))
+                .unwrap(),
+            ))
+        };
+
+        for total in [1000, 1250, 50] {
+            metrics
+                .update_device(
+                    "boiler-room",
+                    "192.168.1.190",
+                    "SNSW-001X16EU",
+                    "gen2",
+                    &status(total),
+                )
+                .unwrap();
+        }
+
+        let mut names = BTreeMap::new();
+        names.insert("temperature:100".to_string(), "Tank top".to_string());
+        metrics.update_component_names("boiler-room", "192.168.1.190", &names);
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"shelly_sensor_temperature_celsius{channel="100",device="boiler-room",host="192.168.1.190"} 21.4"#));
+        assert!(output.contains(r#"shelly_sensor_humidity_percent{channel="100",device="boiler-room",host="192.168.1.190"} 48.7"#));
+        assert!(output.contains(r#"shelly_voltmeter_voltage_volts{channel="100",device="boiler-room",host="192.168.1.190"} 4.12"#));
+        assert!(output.contains(r#"shelly_sensor_illuminance_lux{channel="0",device="boiler-room",host="192.168.1.190"} 270"#));
+        assert!(output.contains(r#"shelly_input_frequency_hz{channel="100",device="boiler-room",host="192.168.1.190"} 0.5"#));
+        // 1000 + 250 + 50 pulses after the reset, scaled by the unit factor
+        assert!(output.contains(r#"shelly_input_count_total{channel="100",device="boiler-room",host="192.168.1.190"} 1.3"#));
+        assert!(output.contains(r#"shelly_input_count_total{channel="101",device="boiler-room",host="192.168.1.190"} 1300"#));
+        assert!(output.contains(r#"shelly_component_info{channel="100",component="temperature",device="boiler-room",host="192.168.1.190",name="Tank top"} 1"#));
+    }
+
+    #[test]
+    fn test_bthome_metrics_update() {
+        let metrics = Metrics::new().unwrap();
+
+        let mut status: ShellyGen2Status = serde_json::from_value(serde_json::json!({
+            "bthomedevice:200": {"id": 200, "rssi": -71, "battery": 98, "packet_id": 35,
+                "last_updated_ts": 1729150000},
+            "bthomesensor:200": {"id": 200, "value": 21.8, "last_updated_ts": 1729150000},
+            "bthomesensor:201": {"id": 201, "value": true, "last_updated_ts": 1729150000},
+            "bthomesensor:202": {"id": 202, "value": 54, "last_updated_ts": 1729150000}
+        }))
+        .unwrap();
+        let config = |obj_id: Option<u32>, name: Option<&str>| BthomeConfig {
+            addr: "7c:c6:b6:61:e8:1a".to_string(),
+            name: name.map(str::to_string),
+            obj_id,
+            idx: obj_id.map(|_| 0),
+        };
+        status.bthome_config = BTreeMap::from([
+            (
+                "bthomedevice:200".to_string(),
+                config(None, Some("Bedroom H&T")),
+            ),
+            ("bthomesensor:200".to_string(), config(Some(0x45), None)),
+            ("bthomesensor:201".to_string(), config(Some(0x2d), None)),
+        ]);
+
+        metrics
+            .update_device(
+                "gateway",
+                "192.168.1.60",
+                "SNSW-001P16EU",
+                "gen2",
+                &ShellyStatus::Gen2(Box::new(status)),
+            )
+            .unwrap();
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"shelly_bthome_battery_percent{address="7c:c6:b6:61:e8:1a",device="gateway",host="192.168.1.60",name="Bedroom H&T"} 98"#));
+        assert!(output.contains(r#"shelly_bthome_rssi_dbm{address="7c:c6:b6:61:e8:1a",device="gateway",host="192.168.1.60",name="Bedroom H&T"} -71"#));
+        assert!(output.contains(r#"shelly_bthome_packet_id{address="7c:c6:b6:61:e8:1a",device="gateway",host="192.168.1.60",name="Bedroom H&T"} 35"#));
+        assert!(output.contains(r#"shelly_bthome_last_updated_timestamp_seconds{address="7c:c6:b6:61:e8:1a",device="gateway",host="192.168.1.60",name="Bedroom H&T"} 1729150000"#));
+        assert!(output.contains(r#"shelly_bthome_sensor_value{address="7c:c6:b6:61:e8:1a",device="gateway",host="192.168.1.60",index="0",name="Bedroom H&T",object_id="69",type="temperature"} 21.8"#));
+        assert!(output.contains(r#"shelly_bthome_sensor_value{address="7c:c6:b6:61:e8:1a",device="gateway",host="192.168.1.60",index="0",name="Bedroom H&T",object_id="45",type="window"} 1"#));
+        // Sensors without config can't be attributed to a BLU device
+        let sensor_values = output
+            .lines()
+            .filter(|line| line.starts_with("shelly_bthome_sensor_value{"))
+            .count();
+        assert_eq!(sensor_values, 2);
+    }
+
+    #[test]
+    fn test_thermostat_metrics_update() {
+        let metrics = Metrics::new().unwrap();
+
+        let trv: ShellyGen1Status = serde_json::from_value(serde_json::json!({
+            "thermostats": [{
+                "pos": 44.5,
+                "target_t": {"enabled": true, "value": 69.8, "value_op": 46.4, "units": "F"},
+                "tmp": {"value": 19.4, "units": "C", "is_valid": true},
+                "window_open": true
+            }],
+            "bat": {"value": 79, "voltage": 3.652}
+        }))
+        .unwrap();
+        metrics
+            .update_device(
+                "living-trv",
+                "192.168.1.70",
+                "SHTRV-01",
+                "gen1",
+                &ShellyStatus::Gen1(Box::new(trv)),
+            )
+            .unwrap();
+
+        let display: ShellyGen2Status = serde_json::from_value(serde_json::json!({
+            "thermostat:0": {"id": 0, "enable": true, "target_C": 20.5, "current_C": 20.1,
+                "output": true, "schedules": {"enable": false}},
+            "blutrv:200": {"id": 200, "target_C": 18, "current_C": 18.6, "pos": 12,
+                "rssi": -68, "battery": 100, "packet_id": 3, "last_updated_ts": 1729150000,
+                "paired": true, "rpc": true, "rsv": 32}
+        }))
+        .unwrap();
+        metrics
+            .update_device(
+                "hall-display",
+                "192.168.1.71",
+                "SAWD-0A1XX10EU1",
+                "gen2",
+                &ShellyStatus::Gen2(Box::new(display)),
+            )
+            .unwrap();
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"shelly_thermostat_target_celsius{channel="0",device="living-trv",host="192.168.1.70"} 21"#));
+        assert!(output.contains(r#"shelly_thermostat_current_celsius{channel="0",device="living-trv",host="192.168.1.70"} 19.4"#));
+        assert!(output.contains(r#"shelly_thermostat_valve_position_percent{channel="0",device="living-trv",host="192.168.1.70"} 44.5"#));
+        assert!(output.contains(
+            r#"shelly_thermostat_enabled{channel="0",device="living-trv",host="192.168.1.70"} 1"#
+        ));
+        assert!(output.contains(r#"shelly_thermostat_window_open{channel="0",device="living-trv",host="192.168.1.70"} 1"#));
+        assert!(output.contains(
+            r#"shelly_battery_percent{channel="0",device="living-trv",host="192.168.1.70"} 79"#
+        ));
+
+        assert!(output.contains(r#"shelly_thermostat_target_celsius{channel="0",device="hall-display",host="192.168.1.71"} 20.5"#));
+        assert!(output.contains(
+            r#"shelly_thermostat_enabled{channel="0",device="hall-display",host="192.168.1.71"} 1"#
+        ));
+        assert!(output.contains(r#"shelly_thermostat_current_celsius{channel="200",device="hall-display",host="192.168.1.71"} 18.6"#));
+        assert!(output.contains(r#"shelly_thermostat_valve_position_percent{channel="200",device="hall-display",host="192.168.1.71"} 12"#));
+        assert!(output.contains(
+            r#"shelly_battery_percent{channel="200",device="hall-display",host="192.168.1.71"} 100"#
+        ));
+    }
+
+    #[test]
+    fn test_motion_and_gas_metrics_update() {
+        let metrics = Metrics::new().unwrap();
+
+        let motion: ShellyGen1Status = serde_json::from_value(serde_json::json!({
+            "sensor": {"motion": true, "vibration": false, "timestamp": 1729150000,
+                "active": true, "is_valid": true},
+            "lux": {"value": 87, "illumination": "twilight", "is_valid": true},
+            "bat": {"value": 93, "voltage": 4.11, "charging": false}
+        }))
+        .unwrap();
+        metrics
+            .update_device(
+                "hallway-motion",
+                "192.168.1.80",
+                "SHMOS-01",
+                "gen1",
+                &ShellyStatus::Gen1(Box::new(motion)),
+            )
+            .unwrap();
+
+        let gas: ShellyGen1Status = serde_json::from_value(serde_json::json!({
+            "gas_sensor": {"sensor_state": "normal", "self_test_state": "completed",
+                "alarm_state": "heavy"},
+            "concentration": {"ppm": 812, "is_valid": true}
+        }))
+        .unwrap();
+        metrics
+            .update_device(
+                "kitchen-gas",
+                "192.168.1.81",
+                "SHGS-1",
+                "gen1",
+                &ShellyStatus::Gen1(Box::new(gas)),
+            )
+            .unwrap();
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(
+            r#"shelly_sensor_motion{channel="0",device="hallway-motion",host="192.168.1.80"} 1"#
+        ));
+        assert!(output.contains(
+            r#"shelly_sensor_vibration{channel="0",device="hallway-motion",host="192.168.1.80"} 0"#
+        ));
+        assert!(output.contains(r#"shelly_sensor_illuminance_lux{channel="0",device="hallway-motion",host="192.168.1.80"} 87"#));
+        assert!(output.contains(
+            r#"shelly_battery_percent{channel="0",device="hallway-motion",host="192.168.1.80"} 93"#
+        ));
+
+        assert!(output.contains(r#"shelly_gas_concentration_ppm{channel="0",device="kitchen-gas",host="192.168.1.81"} 812"#));
+        assert!(output.contains(r#"shelly_gas_alarm_state{channel="0",device="kitchen-gas",host="192.168.1.81",state="heavy"} 1"#));
+        assert!(output.contains(r#"shelly_gas_alarm_state{channel="0",device="kitchen-gas",host="192.168.1.81",state="none"} 0"#));
+        assert!(output.contains(r#"shelly_gas_sensor_state{channel="0",device="kitchen-gas",host="192.168.1.81",state="normal"} 1"#));
+        assert!(output.contains(r#"shelly_gas_self_test_state{channel="0",device="kitchen-gas",host="192.168.1.81",state="completed"} 1"#));
+    }
+
+    #[test]
+    fn test_device_info_metric() {
+        let metrics = Metrics::new().unwrap();
+
+        let info = DeviceInfo {
+            name: "Kitchen".to_string(),
+            id: "shellyplus1pm-a8032ab12345".to_string(),
+            mac: "A8032AB12345".to_string(),
+            model: "SNSW-001P16EU".to_string(),
+            generation: 2,
+            fw_id: "20241011-114455/1.4.4-g6d2a586".to_string(),
+            ver: "1.4.4".to_string(),
+            app: "Plus1PM".to_string(),
+            auth_en: true,
+            auth_domain: Some("shellyplus1pm-a8032ab12345".to_string()),
+        };
+        metrics.update_device_info("kitchen", "192.168.1.90", &info);
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"shelly_device_info{app="Plus1PM",auth_en="true",device="kitchen",fw_id="20241011-114455/1.4.4-g6d2a586",host="192.168.1.90",id="shellyplus1pm-a8032ab12345",mac="A8032AB12345",ver="1.4.4"} 1"#));
+    }
+
+    #[test]
+    fn test_scrape_error_reasons() {
+        let metrics = Metrics::new().unwrap();
+
+        metrics.record_scrape_error("test_device", "192.168.1.100", &ShellyError::Timeout);
+        metrics.record_scrape_error("test_device", "192.168.1.100", &ShellyError::Timeout);
+        metrics.record_scrape_error(
+            "test_device",
+            "192.168.1.100",
+            &ShellyError::Unauthorized(reqwest::StatusCode::UNAUTHORIZED),
+        );
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"shelly_scrape_errors_total{device="test_device",host="192.168.1.100",reason="timeout"} 2"#));
+        assert!(output.contains(r#"shelly_scrape_errors_total{device="test_device",host="192.168.1.100",reason="auth"} 1"#));
+    }
+
+    #[test]
+    fn test_status_parse_errors() {
+        let metrics = Metrics::new().unwrap();
+
+        let status = ShellyGen2Status {
+            components: BTreeMap::from([(
+                "switch:0".to_string(),
+                Component::Switch(
+                    serde_json::from_value(serde_json::json!({
+                        "id": 0, "output": true
+                    }))
+                    .unwrap(),
+                ),
+            )]),
+            parse_errors: vec!["switch:1".to_string()],
+            ..Default::default()
+        };
+        metrics
+            .update_device(
+                "test_device",
+                "192.168.1.100",
+                "SNSW-102P16EU",
+                "gen2",
+                &ShellyStatus::Gen2(Box::new(status)),
+            )
+            .unwrap();
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"shelly_status_parse_errors_total{component="switch:1",device="test_device",host="192.168.1.100"} 1"#));
+        assert!(output.contains(
+            r#"shelly_switch_output{channel="0",device="test_device",host="192.168.1.100"} 1"#
+        ));
+    }
+
+    #[test]
+    fn test_pushed_input_events() {
+        let metrics = Metrics::new().unwrap();
+
+        let event = |event: &str| ComponentEvent {
+            component: "input:0".to_string(),
+            event: event.to_string(),
+        };
+        let status = ShellyGen2Status {
+            events: vec![
+                event("single_push"),
+                event("single_push"),
+                event("long_push"),
+            ],
+            ..Default::default()
         };
+        metrics
+            .update_device(
+                "button",
+                "192.168.1.140",
+                "SNSN-0024X",
+                "gen2",
+                &ShellyStatus::Gen2(Box::new(status)),
+            )
+            .unwrap();
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"shelly_input_events_total{channel="0",device="button",event="single_push",host="192.168.1.140"} 2"#));
+        assert!(output.contains(r#"shelly_input_events_total{channel="0",device="button",event="long_push",host="192.168.1.140"} 1"#));
+    }
+
+    #[test]
+    fn test_device_down_marking() {
+        let metrics = Metrics::new().unwrap();
 
         metrics.mark_device_down("test_device", "192.168.1.100", "Shelly Plus 1", "gen2");
 
@@ -500,4 +3253,53 @@ mod tests {
         assert!(output.contains(r#"device="test_device""#));
         assert!(output.contains("} 0"));
     }
+
+    #[test]
+    fn test_sleepy_device_silence() {
+        let metrics = Metrics::new().unwrap();
+        let status =
+            || ShellyStatus::Gen1(Box::new(serde_json::from_str(r#"{"uptime": 1}"#).unwrap()));
+
+        metrics
+            .update_device("ht", "192.168.1.170", "SHHT-1", "gen1", &status())
+            .unwrap();
+        metrics.mark_device_silent(
+            "ht",
+            "192.168.1.170",
+            "SHHT-1",
+            "gen1",
+            Duration::from_secs(3600),
+        );
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"shelly_device_up{device="ht",generation="gen1",host="192.168.1.170",model="SHHT-1"} 1"#));
+        assert!(output.contains(r#"shelly_device_stale{device="ht",host="192.168.1.170"} 1"#));
+        assert!(output.contains(
+            r#"shelly_device_last_seen_timestamp_seconds{device="ht",host="192.168.1.170"}"#
+        ));
+
+        // Silence window exceeded
+        metrics.last_seen.lock().unwrap().insert(
+            ("ht".to_string(), "192.168.1.170".to_string()),
+            Utc::now() - chrono::Duration::hours(2),
+        );
+        metrics.mark_device_silent(
+            "ht",
+            "192.168.1.170",
+            "SHHT-1",
+            "gen1",
+            Duration::from_secs(3600),
+        );
+
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"shelly_device_up{device="ht",generation="gen1",host="192.168.1.170",model="SHHT-1"} 0"#));
+
+        // Back online
+        metrics
+            .update_device("ht", "192.168.1.170", "SHHT-1", "gen1", &status())
+            .unwrap();
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"shelly_device_up{device="ht",generation="gen1",host="192.168.1.170",model="SHHT-1"} 1"#));
+        assert!(output.contains(r#"shelly_device_stale{device="ht",host="192.168.1.170"} 0"#));
+    }
 }
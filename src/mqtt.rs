@@ -0,0 +1,787 @@
+//! Ingestion of the status devices publish to an MQTT broker, for devices
+//! that can reach the broker but not be reached by the exporter.
+//!
+//! Gen1 devices publish single values under `shellies/<id>/...`, and their
+//! full `/status` document as `shellies/<id>/info`. Gen2+ devices publish
+//! each component as `<prefix>/status/<component>` and RPC notifications on
+//! `<prefix>/events/rpc`. Both are assembled into the documents the HTTP API
+//! returns, so that they go through the existing metrics.
+//!
+//! Gen2+ devices also take RPC requests on `<prefix>/rpc` and answer on the
+//! `<src>/rpc` topic of the caller, which lets the exporter poll devices it
+//! can only reach through the broker.
+
+use anyhow::{Result, anyhow};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS, SubscribeFilter};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, info, warn};
+
+use crate::rpc::{Incoming, PushedStatus, RpcChannel, RpcFrame};
+use crate::shelly::{ComponentEvent, ShellyError, ShellyGeneration};
+
+const GEN1_PREFIX: &str = "shellies/";
+
+/// Gen2 topic prefixes default to the device id; prefixes spanning several
+/// levels are not matched.
+const SUBSCRIPTIONS: [&str; 4] = ["shellies/#", "+/status/+", "+/events/rpc", "+/online"];
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Highest channel index accepted from a Gen1 topic; no device has more
+/// channels, and anything above would grow the status from untrusted input.
+const MAX_GEN1_CHANNEL: usize = 16;
+
+/// Devices tracked at most; anyone able to publish could otherwise make up
+/// device ids without limit.
+const MAX_DEVICES: usize = 256;
+
+/// Connection options for a broker given as `host` or `host:port`.
+pub fn connect_options(
+    broker: &str,
+    username: Option<&str>,
+    password: Option<&str>,
+) -> Result<MqttOptions> {
+    let (host, port) = match broker.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| anyhow!("Invalid MQTT broker '{}'", broker))?,
+        ),
+        None => (broker, 1883),
+    };
+
+    let client_id = format!("shelly-exporter-{:08x}", rand::random::<u32>());
+    let mut options = MqttOptions::new(client_id, host, port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let Some(username) = username {
+        options.set_credentials(username, password.unwrap_or_default());
+    }
+
+    Ok(options)
+}
+
+#[derive(Debug)]
+struct MqttDevice {
+    generation: ShellyGeneration,
+    model: String,
+    state: PushedStatus,
+}
+
+impl MqttDevice {
+    fn new(generation: ShellyGeneration) -> Self {
+        // Devices are assumed online until their `online` topic says otherwise
+        let mut state = PushedStatus::default();
+        state.set_connected(true);
+
+        Self {
+            generation,
+            model: "Unknown".to_string(),
+            state,
+        }
+    }
+}
+
+/// Connection to the broker, with the statuses received over it keyed by
+/// Gen1 device id or Gen2 topic prefix.
+#[derive(Debug)]
+pub struct MqttListener {
+    client: AsyncClient,
+    devices: Mutex<HashMap<String, MqttDevice>>,
+    /// Where to route RPC responses to, keyed by the `src` of the requests
+    responses: Mutex<HashMap<String, UnboundedSender<RpcFrame>>>,
+}
+
+impl MqttListener {
+    /// Connect to the broker in the background, reconnecting whenever the
+    /// connection is lost.
+    pub fn connect(options: MqttOptions) -> Arc<Self> {
+        let broker = format!(
+            "{}:{}",
+            options.broker_address().0,
+            options.broker_address().1
+        );
+        let (client, eventloop) = AsyncClient::new(options, 64);
+        let listener = Arc::new(Self {
+            client,
+            devices: Mutex::new(HashMap::new()),
+            responses: Mutex::new(HashMap::new()),
+        });
+        tokio::spawn(listener.clone().run(broker, eventloop));
+
+        listener
+    }
+
+    /// Id, generation and model of all devices heard from so far.
+    pub fn devices(&self) -> Vec<(String, ShellyGeneration, String)> {
+        self.devices
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, device)| (id.clone(), device.generation, device.model.clone()))
+            .collect()
+    }
+
+    /// The status of a device, or `None` once it went offline.
+    pub fn status(&self, id: &str) -> Option<Value> {
+        self.devices.lock().unwrap().get_mut(id)?.state.status()
+    }
+
+    pub fn take_events(&self, id: &str) -> Vec<ComponentEvent> {
+        self.devices
+            .lock()
+            .unwrap()
+            .get_mut(id)
+            .map(|device| device.state.take_events())
+            .unwrap_or_default()
+    }
+
+    /// RPC transport for the Gen2+ device publishing under `prefix`.
+    pub fn rpc(
+        self: &Arc<Self>,
+        prefix: &str,
+        auth: Option<(String, String)>,
+        timeout: Duration,
+    ) -> MqttRpc {
+        let channel = RpcChannel::new(auth);
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(channel.src().to_string(), sender);
+
+        let topic = format!("{}/rpc", channel.src());
+        if let Err(e) = self.client.try_subscribe(&topic, QoS::AtMostOnce) {
+            warn!("Failed to subscribe to {}: {}", topic, e);
+        }
+
+        MqttRpc {
+            listener: self.clone(),
+            prefix: prefix.to_string(),
+            timeout,
+            channel: Mutex::new(channel),
+            responses: tokio::sync::Mutex::new(receiver),
+        }
+    }
+
+    async fn run(self: Arc<Self>, broker: String, mut eventloop: EventLoop) {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("Connected to MQTT broker {}", broker);
+                    let response_topics: Vec<String> = self
+                        .responses
+                        .lock()
+                        .unwrap()
+                        .keys()
+                        .map(|src| format!("{src}/rpc"))
+                        .collect();
+                    let filters: Vec<SubscribeFilter> = SUBSCRIPTIONS
+                        .map(String::from)
+                        .into_iter()
+                        .chain(response_topics)
+                        .map(|topic| SubscribeFilter::new(topic, QoS::AtMostOnce))
+                        .collect();
+
+                    // Requests are only taken from the channel by polling the event
+                    // loop, so waiting for room in it here could stall forever
+                    let client = self.client.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = client.subscribe_many(filters).await {
+                            warn!("Failed to subscribe to MQTT topics: {}", e);
+                        }
+                    });
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    self.receive(&publish.topic, &publish.payload)
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!(
+                        "MQTT connection to {} failed: {}, retrying in {:?}",
+                        broker, e, RECONNECT_DELAY
+                    );
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    }
+
+    fn receive(&self, topic: &str, payload: &[u8]) {
+        let payload = String::from_utf8_lossy(payload);
+        let payload = payload.trim();
+
+        if let Some(src) = topic.strip_suffix("/rpc")
+            && let Some(responses) = self.responses.lock().unwrap().get(src)
+        {
+            match serde_json::from_str(payload) {
+                // The transport may be gone already, in which case nobody waits
+                Ok(frame) => {
+                    let _ = responses.send(frame);
+                }
+                Err(e) => debug!("Malformed RPC response on {}: {}", topic, e),
+            }
+            return;
+        }
+
+        let mut devices = self.devices.lock().unwrap();
+
+        if let Some(rest) = topic.strip_prefix(GEN1_PREFIX) {
+            let mut segments = rest.split('/');
+            let id = segments.next().unwrap_or_default();
+            let path: Vec<&str> = segments.collect();
+
+            match (id, path.as_slice()) {
+                ("announce", []) | (_, ["announce"]) => {
+                    #[derive(Deserialize)]
+                    struct Announce {
+                        id: String,
+                        model: String,
+                    }
+
+                    match serde_json::from_str::<Announce>(payload) {
+                        Ok(announce) => {
+                            if let Some(device) =
+                                device(&mut devices, &announce.id, ShellyGeneration::Gen1, true)
+                            {
+                                device.model = announce.model;
+                            }
+                        }
+                        Err(e) => debug!("Malformed announcement on {}: {}", topic, e),
+                    }
+                }
+                (_, ["online"]) => {
+                    let online = payload == "true";
+                    if let Some(device) = device(&mut devices, id, ShellyGeneration::Gen1, online) {
+                        device.state.set_connected(online);
+                    }
+                }
+                // Broadcast topics such as `shellies/command` carry no device id
+                (_, []) => {}
+                _ => {
+                    let announced = path == ["info"];
+                    if let Some(device) =
+                        device(&mut devices, id, ShellyGeneration::Gen1, announced)
+                    {
+                        update_gen1(device.state.status_mut(), &path, payload);
+                    }
+                }
+            }
+        } else if let Some((prefix, component)) = topic.rsplit_once("/status/") {
+            match serde_json::from_str::<Value>(payload) {
+                Ok(value) => {
+                    if let Some(device) =
+                        device(&mut devices, prefix, ShellyGeneration::Gen2, false)
+                        && let Value::Object(status) = device.state.status_mut()
+                    {
+                        status.insert(component.to_string(), value);
+                    }
+                }
+                Err(e) => debug!("Malformed status on {}: {}", topic, e),
+            }
+        } else if let Some(prefix) = topic.strip_suffix("/events/rpc") {
+            match serde_json::from_str::<RpcFrame>(payload) {
+                Ok(RpcFrame {
+                    method: Some(method),
+                    params,
+                    src,
+                    ..
+                }) => {
+                    // Notifications name the device that sent them, which
+                    // identifies devices published under a custom prefix
+                    let announced = src.as_deref().is_some_and(is_device_id);
+                    if let Some(device) =
+                        device(&mut devices, prefix, ShellyGeneration::Gen2, announced)
+                    {
+                        // Deltas apply to whatever was assembled from the status topics
+                        device.state.status_mut();
+                        device.state.apply(&method, params.unwrap_or_default());
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => debug!("Malformed notification on {}: {}", topic, e),
+            }
+        } else if let Some(prefix) = topic.strip_suffix("/online")
+            && let Some(device) = devices.get_mut(prefix)
+        {
+            device.state.set_connected(payload == "true");
+        }
+    }
+}
+
+/// Whether `id` has the shape of a Shelly device id: the model followed by
+/// the MAC address or its last three bytes, e.g. `shelly1pm-A4CF12F45678`.
+fn is_device_id(id: &str) -> bool {
+    id.rsplit_once('-').is_some_and(|(model, mac)| {
+        model.starts_with("shelly")
+            && matches!(mac.len(), 6 | 12)
+            && mac.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+/// The device publishing under `id`. Unknown devices are only added when
+/// the topic `announced` them or `id` looks like a device id, and while
+/// fewer than `MAX_DEVICES` are tracked.
+fn device<'a>(
+    devices: &'a mut HashMap<String, MqttDevice>,
+    id: &str,
+    generation: ShellyGeneration,
+    announced: bool,
+) -> Option<&'a mut MqttDevice> {
+    if !devices.contains_key(id) {
+        if !(announced || is_device_id(id)) || id.is_empty() || id.contains(['/', '+', '#']) {
+            return None;
+        }
+        if devices.len() >= MAX_DEVICES {
+            debug!(
+                "Ignoring MQTT device {}, already tracking {}",
+                id, MAX_DEVICES
+            );
+            return None;
+        }
+        devices.insert(id.to_string(), MqttDevice::new(generation));
+    }
+
+    devices.get_mut(id)
+}
+
+/// RPC requests to a Gen2+ device through the broker, one at a time.
+#[derive(Debug)]
+pub struct MqttRpc {
+    listener: Arc<MqttListener>,
+    prefix: String,
+    timeout: Duration,
+    channel: Mutex<RpcChannel>,
+    /// Responses routed here by the listener, locked for the duration of a call
+    responses: tokio::sync::Mutex<UnboundedReceiver<RpcFrame>>,
+}
+
+impl MqttRpc {
+    /// Call `method` and wait for its result, answering a digest challenge
+    /// once if the device asks for credentials.
+    pub async fn call(&self, method: &str) -> Result<Value, ShellyError> {
+        let mut responses = self.responses.lock().await;
+        let request = self.channel.lock().unwrap().request(method);
+        self.publish(request).await?;
+
+        let response = tokio::time::timeout(self.timeout, async {
+            while let Some(frame) = responses.recv().await {
+                let incoming = self.channel.lock().unwrap().receive(frame);
+                match incoming {
+                    Ok(Incoming::Response { result, .. }) => return Ok(result),
+                    Ok(Incoming::Retry(request)) => self.publish(request).await?,
+                    Ok(_) => {}
+                    Err(e) => return Err(ShellyError::Request(e.to_string())),
+                }
+            }
+            Err(ShellyError::Connection("MQTT listener stopped".to_string()))
+        })
+        .await;
+
+        response.unwrap_or_else(|_| {
+            // A response arriving after all is of no use to the next call
+            self.channel.lock().unwrap().clear_pending();
+            Err(ShellyError::Timeout)
+        })
+    }
+
+    async fn publish(&self, request: String) -> Result<(), ShellyError> {
+        let topic = format!("{}/rpc", self.prefix);
+        debug!("Publishing RPC request to {}", topic);
+
+        self.listener
+            .client
+            .publish(topic, QoS::AtMostOnce, false, request)
+            .await
+            .map_err(|e| ShellyError::Connection(e.to_string()))
+    }
+}
+
+impl Drop for MqttRpc {
+    fn drop(&mut self) {
+        let channel = self.channel.get_mut().unwrap();
+        self.listener
+            .responses
+            .lock()
+            .unwrap()
+            .remove(channel.src());
+        let _ = self
+            .listener
+            .client
+            .try_unsubscribe(format!("{}/rpc", channel.src()));
+    }
+}
+
+/// Apply a single Gen1 value, published under `path` below the device, to
+/// its `/status` document.
+fn update_gen1(status: &mut Value, path: &[&str], payload: &str) {
+    let number = payload.parse::<f64>().ok().map(|number| json!(number));
+
+    let (section, index, field, value) = match path {
+        ["info"] => {
+            match serde_json::from_str(payload) {
+                Ok(info) => *status = info,
+                Err(e) => debug!("Malformed Gen1 info: {}", e),
+            }
+            return;
+        }
+        [kind @ ("relay" | "light"), index] => {
+            let section = if *kind == "relay" { "relays" } else { "lights" };
+            (section, *index, "ison", Some(json!(payload == "on")))
+        }
+        ["relay" | "light", index, "power"] => ("meters", *index, "power", number),
+        ["relay" | "light", index, "energy"] => ("meters", *index, "total", number),
+        ["input", index] => (
+            "inputs",
+            *index,
+            "input",
+            payload.parse::<i64>().ok().map(|input| json!(input)),
+        ),
+        ["emeter", index, field] => {
+            let field = match *field {
+                "reactive_power" => "reactive",
+                "power" | "pf" | "voltage" | "current" | "total" | "total_returned" => field,
+                _ => return,
+            };
+            set_entry(status, "emeters", index, "is_valid", json!(true));
+            ("emeters", *index, field, number)
+        }
+        _ => {
+            let (key, value) = match path {
+                ["temperature"] => ("temperature", number),
+                ["sensor", "temperature"] => {
+                    ("tmp", number.map(|n| json!({"value": n, "is_valid": true})))
+                }
+                ["sensor", "humidity"] => {
+                    ("hum", number.map(|n| json!({"value": n, "is_valid": true})))
+                }
+                ["sensor", "lux"] => ("lux", number.map(|n| json!({"value": n, "is_valid": true}))),
+                ["sensor", "battery"] => ("bat", number.map(|n| json!({"value": n}))),
+                ["sensor", "flood"] => ("flood", Some(json!(payload == "true"))),
+                _ => return,
+            };
+            if let (Some(value), Value::Object(status)) = (value, status) {
+                status.insert(key.to_string(), value);
+            }
+            return;
+        }
+    };
+
+    if let Some(value) = value {
+        set_entry(status, section, index, field, value);
+    }
+}
+
+/// Set `field` of entry `index` of the array `section`, growing it as needed.
+fn set_entry(status: &mut Value, section: &str, index: &str, field: &str, value: Value) {
+    let (Ok(index), Value::Object(status)) = (index.parse::<usize>(), status) else {
+        return;
+    };
+    if index >= MAX_GEN1_CHANNEL {
+        debug!(
+            "Ignoring {} entry {} beyond the channel limit",
+            section, index
+        );
+        return;
+    }
+
+    let entries = status.entry(section).or_insert_with(|| json!([]));
+    if !entries.is_array() {
+        *entries = json!([]);
+    }
+    if let Value::Array(entries) = entries {
+        if entries.len() <= index {
+            entries.resize(index + 1, json!({}));
+        }
+        if let Value::Object(entry) = &mut entries[index] {
+            entry.insert(field.to_string(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shelly::{Component, ShellyClient, ShellyStatus};
+    use bytes::BytesMut;
+    use rumqttc::{ConnAck, ConnectReturnCode, Publish, SubAck, SubscribeReasonCode};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Stand-in for a broker: accepts the exporter's subscriptions, then
+    /// delivers `messages` as if devices had published them. RPC requests
+    /// are answered with the result `answer` returns for them, if any.
+    async fn serve_broker(
+        listener: TcpListener,
+        messages: Vec<(&'static str, String)>,
+        answer: fn(&Value) -> Option<Value>,
+    ) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buffer = BytesMut::new();
+        let mut subscriptions = 0;
+        let mut delivered = false;
+
+        loop {
+            let packet = match Packet::read(&mut buffer, 65536) {
+                Ok(packet) => packet,
+                Err(_) => {
+                    if stream.read_buf(&mut buffer).await.unwrap() == 0 {
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            let mut replies = Vec::new();
+            match packet {
+                Packet::Connect(..) => replies.push(Packet::ConnAck(ConnAck::new(
+                    ConnectReturnCode::Success,
+                    false,
+                ))),
+                Packet::Subscribe(subscribe) => {
+                    replies.push(Packet::SubAck(SubAck::new(
+                        subscribe.pkid,
+                        vec![
+                            SubscribeReasonCode::Success(QoS::AtMostOnce);
+                            subscribe.filters.len()
+                        ],
+                    )));
+                    subscriptions += subscribe.filters.len();
+                    if !delivered && subscriptions >= SUBSCRIPTIONS.len() {
+                        delivered = true;
+                        replies.extend(messages.iter().map(|(topic, payload)| {
+                            Packet::Publish(Publish::new(*topic, QoS::AtMostOnce, payload.clone()))
+                        }));
+                    }
+                }
+                Packet::Publish(publish) if publish.topic.ends_with("/rpc") => {
+                    let request: Value = serde_json::from_slice(&publish.payload).unwrap();
+                    if let Some(result) = answer(&request) {
+                        let response = json!({"id": request["id"], "src": "shellyplus1pm-a8032ab12345",
+                            "dst": request["src"], "result": result});
+                        let topic = format!("{}/rpc", request["src"].as_str().unwrap());
+                        replies.push(Packet::Publish(Publish::new(
+                            topic,
+                            QoS::AtMostOnce,
+                            response.to_string(),
+                        )));
+                    }
+                }
+                Packet::PingReq => replies.push(Packet::PingResp),
+                _ => {}
+            }
+            write_packets(&mut stream, replies).await;
+        }
+    }
+
+    async fn write_packets(stream: &mut TcpStream, packets: Vec<Packet>) {
+        let mut out = BytesMut::new();
+        for packet in packets {
+            packet.write(&mut out, 65536).unwrap();
+        }
+        stream.write_all(&out).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mqtt_ingestion() {
+        let broker = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = broker.local_addr().unwrap().to_string();
+        let gen1 = "shellies/shelly1pm-A4CF12F45678";
+        let gen2 = "shellyplus1pm-a8032ab12345";
+        let messages = vec![
+            (
+                "shellies/announce",
+                json!({"id": "shelly1pm-A4CF12F45678", "model": "SHSW-PM", "mac": "A4CF12F45678"})
+                    .to_string(),
+            ),
+            ("shellies/shelly1pm-A4CF12F45678/online", "true".to_string()),
+            ("shellies/shelly1pm-A4CF12F45678/relay/0", "on".to_string()),
+            ("shellies/shelly1pm-A4CF12F45678/relay/0/power", "41.7".to_string()),
+            ("shellies/shelly1pm-A4CF12F45678/relay/0/energy", "123456".to_string()),
+            (
+                "shellyplus1pm-a8032ab12345/status/switch:0",
+                json!({"id": 0, "source": "init", "output": false, "apower": 0.0}).to_string(),
+            ),
+            (
+                "shellyplus1pm-a8032ab12345/events/rpc",
+                json!({"src": gen2, "dst": "shellyplus1pm-a8032ab12345/events", "method": "NotifyStatus",
+                    "params": {"ts": 1700000000.0, "switch:0": {"id": 0, "output": true, "apower": 12.5}}})
+                .to_string(),
+            ),
+            (
+                "shellyplus1pm-a8032ab12345/events/rpc",
+                json!({"src": gen2, "dst": "shellyplus1pm-a8032ab12345/events", "method": "NotifyEvent",
+                    "params": {"ts": 1700000001.0, "events": [{"component": "input:0", "id": 0, "event": "single_push"}]}})
+                .to_string(),
+            ),
+            ("shellyplus1pm-a8032ab12345/online", "false".to_string()),
+            ("shellies/shelly1pm-A4CF12F45678/temperature", "48.6".to_string()),
+        ];
+        tokio::spawn(serve_broker(broker, messages, |_| None));
+
+        let listener = MqttListener::connect(connect_options(&address, None, None).unwrap());
+
+        // The broker delivers in order, so everything arrived once the last value did
+        let gen1 = gen1.trim_start_matches(GEN1_PREFIX);
+        for _ in 0..100 {
+            if listener
+                .devices
+                .lock()
+                .unwrap()
+                .get_mut(gen1)
+                .is_some_and(|device| device.state.status_mut().get("temperature").is_some())
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let gen1_client =
+            ShellyClient::mqtt_only(gen1, ShellyGeneration::Gen1, listener.clone()).unwrap();
+        let ShellyStatus::Gen1(status) = gen1_client.get_status().await.unwrap() else {
+            panic!("expected Gen1 status");
+        };
+        assert!(status.relays.as_ref().unwrap().get(0).unwrap().ison);
+        let meter = &status.meters.as_ref().unwrap().get(0).unwrap();
+        assert_eq!(meter.power, 41.7);
+        assert_eq!(meter.total, Some(123456.0));
+        assert_eq!(status.temperature, Some(48.6));
+
+        let devices = listener.devices();
+        assert!(devices.contains(&(
+            "shelly1pm-A4CF12F45678".to_string(),
+            ShellyGeneration::Gen1,
+            "SHSW-PM".to_string()
+        )));
+
+        // The Gen2 device went offline after its last notification, so its
+        // status is handed out once more and then no longer
+        let gen2_client =
+            ShellyClient::mqtt_only(gen2, ShellyGeneration::Gen2, listener.clone()).unwrap();
+        let ShellyStatus::Gen2(status) = gen2_client.get_status().await.unwrap() else {
+            panic!("expected Gen2 status");
+        };
+        let Some(Component::Switch(switch)) = status.components.get("switch:0") else {
+            panic!("expected switch:0");
+        };
+        assert!(switch.output);
+        assert_eq!(switch.apower, Some(12.5));
+        assert_eq!(status.events.len(), 1);
+        assert_eq!(status.events[0].event, "single_push");
+        assert!(matches!(
+            gen2_client.get_status().await,
+            Err(ShellyError::NotPushed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rpc_over_mqtt() {
+        let broker = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = broker.local_addr().unwrap().to_string();
+        tokio::spawn(serve_broker(broker, Vec::new(), |request| {
+            match request["method"].as_str().unwrap() {
+                "Shelly.GetDeviceInfo" => Some(json!({
+                    "name": "Garage", "id": "shellyplus1pm-a8032ab12345", "mac": "A8032AB12345",
+                    "model": "SNSW-001P16EU", "gen": 2, "fw_id": "20231107-164738/1.0.8-g8c7bb8d",
+                    "ver": "1.0.8", "app": "Plus1PM", "auth_en": false, "auth_domain": null
+                })),
+                "Shelly.GetStatus" => Some(json!({
+                    "switch:0": {"id": 0, "source": "init", "output": true, "apower": 7.5}
+                })),
+                // The device behind this prefix never answers
+                _ => None,
+            }
+        }));
+
+        let listener = MqttListener::connect(connect_options(&address, None, None).unwrap());
+
+        // More response topics than the request channel holds are subscribed to
+        // on connect without stalling the connection
+        let idle: Vec<MqttRpc> = (0..100)
+            .map(|n| listener.rpc(&format!("shellyplus1-{n:06x}"), None, Duration::ZERO))
+            .collect();
+
+        let (client, info) = ShellyClient::over_mqtt(
+            "shellyplus1pm-a8032ab12345",
+            listener.clone(),
+            Duration::from_secs(5),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(info.model, "SNSW-001P16EU");
+        assert_eq!(client.generation, ShellyGeneration::Gen2);
+        assert_eq!(client.get_device_info().await.unwrap().app, "Plus1PM");
+
+        let ShellyStatus::Gen2(status) = client.get_status().await.unwrap() else {
+            panic!("expected Gen2 status");
+        };
+        let Some(Component::Switch(switch)) = status.components.get("switch:0") else {
+            panic!("expected switch:0");
+        };
+        assert!(switch.output);
+        assert_eq!(switch.apower, Some(7.5));
+
+        let rpc = listener.rpc(
+            "shellyplus1pm-a8032ab12345",
+            None,
+            Duration::from_millis(200),
+        );
+        assert!(matches!(
+            rpc.call("Shelly.GetConfig").await,
+            Err(ShellyError::Timeout)
+        ));
+        drop(idle);
+    }
+
+    #[tokio::test]
+    async fn test_device_creation() {
+        // No broker is needed to feed messages in directly
+        let listener = MqttListener::connect(connect_options("127.0.0.1:1", None, None).unwrap());
+        let notify = json!({"src": "shellyplus1-a8032ab12345", "method": "NotifyStatus",
+            "params": {"switch:0": {"id": 0, "output": true}}})
+        .to_string();
+
+        listener.receive("shellies/command", b"announce");
+        listener.receive("shellies/thermostat/relay/0", b"on");
+        listener.receive("shellies/thermostat/online", b"false");
+        listener.receive("zigbee/status/switch:0", br#"{"id": 0}"#);
+        listener.receive("shellies/shelly1-A4CF12/relay/0", b"on");
+        listener.receive("shellies/garage/online", b"true");
+        listener.receive("kitchen/events/rpc", notify.as_bytes());
+
+        let mut ids: Vec<String> = listener.devices().into_iter().map(|(id, ..)| id).collect();
+        ids.sort();
+        assert_eq!(ids, ["garage", "kitchen", "shelly1-A4CF12"]);
+
+        for n in 0..MAX_DEVICES {
+            listener.receive(&format!("shellies/device{n}/online"), b"true");
+        }
+        assert_eq!(listener.devices().len(), MAX_DEVICES);
+    }
+
+    #[test]
+    fn test_update_gen1_values() {
+        let mut status = json!({});
+        update_gen1(&mut status, &["relay", "1"], "off");
+        update_gen1(&mut status, &["emeter", "0", "power"], "230.5");
+        update_gen1(&mut status, &["emeter", "0", "reactive_power"], "-12.0");
+        update_gen1(&mut status, &["sensor", "humidity"], "48");
+        update_gen1(&mut status, &["sensor", "battery"], "not a number");
+        update_gen1(&mut status, &["unknown"], "1");
+        update_gen1(&mut status, &["relay", "4000000000"], "on");
+
+        assert_eq!(
+            status,
+            json!({
+                "relays": [{}, {"ison": false}],
+                "emeters": [{"is_valid": true, "power": 230.5, "reactive": -12.0}],
+                "hum": {"value": 48.0, "is_valid": true}
+            })
+        );
+    }
+}
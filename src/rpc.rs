@@ -0,0 +1,445 @@
+//! JSON-RPC frames exchanged with Gen2+ devices over persistent connections,
+//! and the status those connections keep up to date.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::auth::rpc_digest_response;
+use crate::shelly::ComponentEvent;
+
+/// Events kept between two polls; older ones are dropped beyond this.
+const MAX_PENDING_EVENTS: usize = 1000;
+
+#[derive(Debug, Serialize)]
+pub struct RpcRequest {
+    pub id: u64,
+    /// Where the device sends responses and notifications to
+    pub src: String,
+    pub method: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<RpcAuth>,
+}
+
+/// A frame sent by a device: responses carry an `id`, notifications a `method`.
+#[derive(Debug, Deserialize)]
+pub struct RpcFrame {
+    pub id: Option<u64>,
+    /// Id of the device that sent the frame
+    pub src: Option<String>,
+    pub method: Option<String>,
+    pub params: Option<Value>,
+    pub result: Option<Value>,
+    pub error: Option<RpcError>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn is_unauthorized(&self) -> bool {
+        self.code == 401
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RpcAuth {
+    realm: String,
+    username: String,
+    nonce: u64,
+    cnonce: u64,
+    response: String,
+    algorithm: &'static str,
+}
+
+impl RpcAuth {
+    /// Answer the digest challenge a device sends as the message of a 401 error.
+    pub fn answer(error: &RpcError, username: &str, password: &str) -> Option<Self> {
+        #[derive(Deserialize)]
+        struct Challenge {
+            realm: String,
+            nonce: u64,
+            nc: Option<u32>,
+        }
+
+        let challenge: Challenge = serde_json::from_str(&error.message).ok()?;
+        let cnonce = u64::from(rand::random::<u32>());
+        let response = rpc_digest_response(
+            username,
+            password,
+            &challenge.realm,
+            challenge.nonce,
+            challenge.nc.unwrap_or(1),
+            cnonce,
+        );
+
+        Some(Self {
+            realm: challenge.realm,
+            username: username.to_string(),
+            nonce: challenge.nonce,
+            cnonce,
+            response,
+            algorithm: "SHA-256",
+        })
+    }
+}
+
+/// What a received frame turned out to be.
+#[derive(Debug)]
+pub enum Incoming {
+    Notification {
+        method: String,
+        params: Value,
+    },
+    Response {
+        method: String,
+        result: Value,
+    },
+    /// The request was refused for lack of credentials; send this frame to
+    /// repeat it authenticated
+    Retry(String),
+    Ignored,
+}
+
+/// Request bookkeeping for one connection: ids, the `src` replies are
+/// addressed to, and answering digest challenges.
+#[derive(Debug)]
+pub struct RpcChannel {
+    src: String,
+    auth: Option<(String, String)>,
+    next_id: u64,
+    /// Method of every unanswered request, and whether it carried credentials
+    pending: HashMap<u64, (String, bool)>,
+}
+
+impl RpcChannel {
+    pub fn new(auth: Option<(String, String)>) -> Self {
+        Self {
+            src: format!("shelly-exporter-{:08x}", rand::random::<u32>()),
+            auth,
+            next_id: 0,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// The `src` requests are sent with, which responses are addressed to.
+    pub fn src(&self) -> &str {
+        &self.src
+    }
+
+    /// Serialize a new request for `method`.
+    pub fn request(&mut self, method: &str) -> String {
+        self.encode(method, None)
+    }
+
+    /// Classify a frame, matching responses to the requests they answer.
+    pub fn receive(&mut self, frame: RpcFrame) -> Result<Incoming> {
+        if let Some(method) = frame.method {
+            return Ok(Incoming::Notification {
+                method,
+                params: frame.params.unwrap_or_default(),
+            });
+        }
+
+        let Some((method, authenticated)) = frame.id.and_then(|id| self.pending.remove(&id)) else {
+            return Ok(Incoming::Ignored);
+        };
+
+        match (frame.error, frame.result) {
+            (Some(error), _) => {
+                // A request that already carried credentials gets no second try
+                let answer = self
+                    .auth
+                    .as_ref()
+                    .filter(|_| error.is_unauthorized() && !authenticated)
+                    .and_then(|(username, password)| RpcAuth::answer(&error, username, password));
+                match answer {
+                    Some(answer) => Ok(Incoming::Retry(self.encode(&method, Some(answer)))),
+                    None => Err(anyhow!(
+                        "{} failed: {} {}",
+                        method,
+                        error.code,
+                        error.message
+                    )),
+                }
+            }
+            (None, Some(result)) => Ok(Incoming::Response { method, result }),
+            (None, None) => Ok(Incoming::Ignored),
+        }
+    }
+
+    /// Forget all unanswered requests, so that late responses are ignored.
+    pub fn clear_pending(&mut self) {
+        self.pending.clear();
+    }
+
+    fn encode(&mut self, method: &str, auth: Option<RpcAuth>) -> String {
+        self.next_id += 1;
+        self.pending
+            .insert(self.next_id, (method.to_string(), auth.is_some()));
+
+        let request = RpcRequest {
+            id: self.next_id,
+            src: self.src.clone(),
+            method: method.to_string(),
+            params: None,
+            auth,
+        };
+        serde_json::to_string(&request).expect("RPC requests always serialize")
+    }
+}
+
+/// A device status assembled from a full `Shelly.GetStatus` result and the
+/// notifications received since.
+#[derive(Debug, Default)]
+pub struct PushedStatus {
+    status: Option<Value>,
+    connected: bool,
+    events: Vec<ComponentEvent>,
+}
+
+impl PushedStatus {
+    /// Replace the status with a full `Shelly.GetStatus` result.
+    pub fn replace(&mut self, status: Value) {
+        self.status = Some(status);
+    }
+
+    /// The status to update in place, started empty if there is none yet.
+    pub fn status_mut(&mut self) -> &mut Value {
+        self.status
+            .get_or_insert_with(|| Value::Object(Default::default()))
+    }
+
+    pub fn set_connected(&mut self, connected: bool) {
+        self.connected = connected;
+    }
+
+    /// Apply a `NotifyStatus`, `NotifyFullStatus` or `NotifyEvent` notification.
+    pub fn apply(&mut self, method: &str, params: Value) {
+        match method {
+            "NotifyFullStatus" => self.replace(without_timestamp(params)),
+            "NotifyStatus" => {
+                // A delta is meaningless until there is a full status to apply it to
+                if let Some(status) = &mut self.status {
+                    merge(status, without_timestamp(params));
+                }
+            }
+            "NotifyEvent" => {
+                #[derive(Deserialize)]
+                struct Events {
+                    #[serde(default)]
+                    events: Vec<ComponentEvent>,
+                }
+
+                if let Ok(Events { events }) = serde_json::from_value(params) {
+                    self.events.extend(events);
+                    let excess = self.events.len().saturating_sub(MAX_PENDING_EVENTS);
+                    self.events.drain(..excess);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The current status. Once the connection is lost, the last status
+    /// received is handed out one more time and then forgotten.
+    pub fn status(&mut self) -> Option<Value> {
+        if self.connected {
+            self.status.clone()
+        } else {
+            self.status.take()
+        }
+    }
+
+    pub fn take_events(&mut self) -> Vec<ComponentEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+/// Notifications carry a `ts` next to the components, which is not one.
+fn without_timestamp(mut params: Value) -> Value {
+    if let Value::Object(entries) = &mut params {
+        entries.remove("ts");
+    }
+    params
+}
+
+/// Merge a status delta into `target`; objects are merged key by key, any
+/// other value replaces what was there.
+fn merge(target: &mut Value, delta: Value) {
+    match (target, delta) {
+        (Value::Object(target), Value::Object(delta)) => {
+            for (key, value) in delta {
+                match target.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, delta) => *target = delta,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_apply_notifications() {
+        let mut pushed = PushedStatus::default();
+        pushed.set_connected(true);
+
+        // Deltas before the first full status are dropped
+        pushed.apply(
+            "NotifyStatus",
+            json!({"ts": 1.0, "switch:0": {"output": true}}),
+        );
+        assert!(pushed.status().is_none());
+
+        pushed.replace(json!({
+            "switch:0": {"id": 0, "output": false, "apower": 0.0, "aenergy": {"total": 10.0}},
+            "input:0": {"id": 0, "state": false}
+        }));
+        pushed.apply(
+            "NotifyStatus",
+            json!({"ts": 2.0, "switch:0": {"output": true, "aenergy": {"total": 10.5}}}),
+        );
+        pushed.apply(
+            "NotifyEvent",
+            json!({"ts": 3.0, "events": [
+                {"component": "input:0", "id": 0, "event": "single_push", "ts": 3.0}
+            ]}),
+        );
+
+        assert_eq!(
+            pushed.status().unwrap(),
+            json!({
+                "switch:0": {"id": 0, "output": true, "apower": 0.0, "aenergy": {"total": 10.5}},
+                "input:0": {"id": 0, "state": false}
+            })
+        );
+
+        let events = pushed.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].component, "input:0");
+        assert_eq!(events[0].event, "single_push");
+        assert!(pushed.take_events().is_empty());
+
+        pushed.apply(
+            "NotifyFullStatus",
+            json!({"ts": 4.0, "switch:0": {"id": 0}}),
+        );
+        assert_eq!(pushed.status().unwrap(), json!({"switch:0": {"id": 0}}));
+
+        // After a disconnect the last status is handed out once more
+        pushed.set_connected(false);
+        assert!(pushed.status().is_some());
+        assert!(pushed.status().is_none());
+    }
+
+    #[test]
+    fn test_channel_matches_responses() {
+        let mut channel = RpcChannel::new(Some(("admin".to_string(), "secret".to_string())));
+
+        let request: Value = serde_json::from_str(&channel.request("Shelly.GetStatus")).unwrap();
+        assert_eq!(request["id"], 1);
+        assert!(
+            request["src"]
+                .as_str()
+                .unwrap()
+                .starts_with("shelly-exporter-")
+        );
+        assert!(request.get("auth").is_none());
+
+        let frame = |value: Value| serde_json::from_value::<RpcFrame>(value).unwrap();
+        let challenge = r#"{"auth_type": "digest", "nonce": 1625038762, "nc": 1, "realm": "shellypro4pm-f008d1d8b8b8", "algorithm": "SHA-256"}"#;
+
+        // A 401 is answered by repeating the request with credentials
+        let Incoming::Retry(retry) = channel
+            .receive(frame(
+                json!({"id": 1, "error": {"code": 401, "message": challenge}}),
+            ))
+            .unwrap()
+        else {
+            panic!("expected a retry");
+        };
+        let retry: Value = serde_json::from_str(&retry).unwrap();
+        assert_eq!(retry["id"], 2);
+        assert_eq!(retry["method"], "Shelly.GetStatus");
+        assert_eq!(retry["auth"]["username"], "admin");
+
+        // ...but only once
+        assert!(
+            channel
+                .receive(frame(
+                    json!({"id": 2, "error": {"code": 401, "message": challenge}})
+                ))
+                .is_err()
+        );
+
+        channel.request("Shelly.GetDeviceInfo");
+        let Incoming::Response { method, result } = channel
+            .receive(frame(
+                json!({"id": 3, "result": {"model": "SPSW-004PE16EU"}}),
+            ))
+            .unwrap()
+        else {
+            panic!("expected a response");
+        };
+        assert_eq!(method, "Shelly.GetDeviceInfo");
+        assert_eq!(result["model"], "SPSW-004PE16EU");
+
+        // Responses to unknown requests and notifications
+        assert!(matches!(
+            channel
+                .receive(frame(json!({"id": 3, "result": {}})))
+                .unwrap(),
+            Incoming::Ignored
+        ));
+        assert!(matches!(
+            channel
+                .receive(frame(json!({"method": "NotifyStatus", "params": {}})))
+                .unwrap(),
+            Incoming::Notification { .. }
+        ));
+    }
+
+    #[test]
+    fn test_answer_auth_challenge() {
+        let error = RpcError {
+            code: 401,
+            message: r#"{"auth_type": "digest", "nonce": 1625038762, "nc": 1, "realm": "shellypro4pm-f008d1d8b8b8", "algorithm": "SHA-256"}"#.to_string(),
+        };
+        assert!(error.is_unauthorized());
+
+        let auth = RpcAuth::answer(&error, "admin", "secret").unwrap();
+        assert_eq!(auth.realm, "shellypro4pm-f008d1d8b8b8");
+        assert_eq!(auth.nonce, 1625038762);
+        assert_eq!(
+            auth.response,
+            rpc_digest_response(
+                "admin",
+                "secret",
+                "shellypro4pm-f008d1d8b8b8",
+                1625038762,
+                1,
+                auth.cnonce
+            )
+        );
+
+        let garbled = RpcError {
+            code: 401,
+            message: "not a challenge".to_string(),
+        };
+        assert!(RpcAuth::answer(&garbled, "admin", "secret").is_none());
+    }
+}
@@ -1,43 +1,387 @@
 use anyhow::{Result, anyhow};
-use reqwest::Client;
+use reqwest::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use reqwest::{Client, Response, StatusCode};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
-use tracing::{debug, info};
+use std::collections::BTreeMap;
+use std::error::Error as _;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::{debug, info, warn};
+
+use crate::auth::{DigestChallenge, DigestSession};
+use crate::coiot::CoiotListener;
+use crate::mqtt::{MqttListener, MqttRpc};
+use crate::websocket::WebSocketTransport;
+
+/// Why a request to a device failed, classified so that alerts can tell
+/// a wrong password from an unplugged device.
+#[derive(Debug, Error)]
+pub enum ShellyError {
+    #[error("request timed out")]
+    Timeout,
+    #[error("authentication failed: HTTP {0}")]
+    Unauthorized(StatusCode),
+    #[error("connection refused: {0}")]
+    ConnectionRefused(String),
+    #[error("connection failed: {0}")]
+    Connection(String),
+    #[error("unexpected response: HTTP {0}")]
+    Http(StatusCode),
+    #[error("failed to parse response: {0}")]
+    Parse(String),
+    #[error("request failed: {0}")]
+    Request(String),
+    /// A push-only device has nothing new to report, e.g. because it is
+    /// asleep or disconnected. Expected, so not a scrape error.
+    #[error("no status pushed since the previous poll")]
+    NotPushed,
+}
+
+impl ShellyError {
+    /// Short, stable label for the `reason` of `shelly_scrape_errors_total`.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ShellyError::Timeout => "timeout",
+            ShellyError::Unauthorized(_) => "auth",
+            ShellyError::ConnectionRefused(_) => "connection_refused",
+            ShellyError::Connection(_) => "connection",
+            ShellyError::Http(_) => "http_status",
+            ShellyError::Parse(_) => "parse",
+            ShellyError::Request(_) => "request",
+            ShellyError::NotPushed => "not_pushed",
+        }
+    }
+}
+
+impl From<reqwest::Error> for ShellyError {
+    fn from(error: reqwest::Error) -> Self {
+        // reqwest only exposes the cause through the source chain
+        let mut causes = Vec::new();
+        let mut refused = false;
+        let mut source = error.source();
+        while let Some(cause) = source {
+            if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+                refused |= io.kind() == std::io::ErrorKind::ConnectionRefused;
+            }
+            causes.push(cause.to_string());
+            source = cause.source();
+        }
+        let message = std::iter::once(error.to_string())
+            .chain(causes.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(": ");
+
+        if error.is_timeout() {
+            ShellyError::Timeout
+        } else if error.is_decode() {
+            ShellyError::Parse(message)
+        } else if refused {
+            ShellyError::ConnectionRefused(message)
+        } else if error.is_connect() {
+            // Includes failed DNS lookups, which reqwest doesn't tell apart
+            ShellyError::Connection(message)
+        } else {
+            ShellyError::Request(message)
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct ShellyClient {
     client: Client,
     base_url: String,
     auth: Option<(String, String)>,
+    digest: Arc<Mutex<Option<DigestSession>>>,
+    bthome_config: Arc<Mutex<BthomeConfigCache>>,
+    /// Where status is pushed from, if anywhere
+    push: Option<PushSource>,
+    /// Whether the device can only be heard from, not polled over HTTP
+    push_only: bool,
+    /// RPC over MQTT, replacing HTTP for devices reached through the broker
+    mqtt_rpc: Option<Arc<MqttRpc>>,
     pub generation: ShellyGeneration,
 }
 
+/// How long the BTHome config is cached at most, so that a BLU device paired
+/// again under the same component key gets its new address and name.
+const BTHOME_CONFIG_REFRESH: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Default)]
+struct BthomeConfigCache {
+    /// Config of the BTHome components seen so far, keyed by `type:id`;
+    /// `None` for components the device reported no usable config for
+    config: BTreeMap<String, Option<BthomeConfig>>,
+    refreshed: Option<Instant>,
+}
+
+/// A source of status that devices push instead of being polled for.
+#[derive(Debug, Clone)]
+enum PushSource {
+    WebSocket(Arc<WebSocketTransport>),
+    /// CoIoT listener and the address the device multicasts from
+    Coiot(Arc<CoiotListener>, IpAddr),
+    /// MQTT listener and the id the device publishes under
+    Mqtt(Arc<MqttListener>, String),
+}
+
+impl PushSource {
+    fn status(&self) -> Option<serde_json::Value> {
+        match self {
+            PushSource::WebSocket(transport) => transport.status(),
+            PushSource::Coiot(listener, ip) => listener.take_status(*ip),
+            PushSource::Mqtt(listener, id) => listener.status(id),
+        }
+    }
+
+    fn take_events(&self) -> Vec<ComponentEvent> {
+        match self {
+            PushSource::WebSocket(transport) => transport.take_events(),
+            PushSource::Coiot(..) => Vec::new(),
+            PushSource::Mqtt(listener, id) => listener.take_events(id),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ShellyGeneration {
     Gen1,
     Gen2,
+    Gen3,
+    Gen4,
 }
 
-// Gen2 Status structures
-#[derive(Debug, Deserialize, Serialize)]
+impl ShellyGeneration {
+    /// Map the `gen` field reported by `Shelly.GetDeviceInfo` to a generation.
+    pub fn from_rpc_gen(generation: i64) -> Self {
+        match generation {
+            3 => ShellyGeneration::Gen3,
+            4 => ShellyGeneration::Gen4,
+            _ => ShellyGeneration::Gen2,
+        }
+    }
+
+    /// Whether the device speaks the JSON-RPC API (Gen2 and later).
+    pub fn is_rpc(&self) -> bool {
+        *self != ShellyGeneration::Gen1
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ShellyGeneration::Gen1 => "gen1",
+            ShellyGeneration::Gen2 => "gen2",
+            ShellyGeneration::Gen3 => "gen3",
+            ShellyGeneration::Gen4 => "gen4",
+        }
+    }
+}
+
+// Gen2 Status structures (shared by all RPC generations)
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct ShellyGen2Status {
-    #[serde(rename = "switch:0", default)]
-    pub switch_0: Option<SwitchStatus>,
-    #[serde(rename = "switch:1", default)]
-    pub switch_1: Option<SwitchStatus>,
-    #[serde(rename = "switch:2", default)]
-    pub switch_2: Option<SwitchStatus>,
-    #[serde(rename = "switch:3", default)]
-    pub switch_3: Option<SwitchStatus>,
     pub sys: Option<SystemStatus>,
     pub wifi: Option<WifiStatus>,
+    /// Every other component, keyed by its `type:id` name (e.g. `switch:0`)
+    #[serde(flatten, deserialize_with = "deserialize_components")]
+    pub components: BTreeMap<String, Component>,
+    /// Config of the `bthomedevice`/`bthomesensor` components, which is where
+    /// their BLE address and object id live
+    #[serde(skip)]
+    pub bthome_config: BTreeMap<String, BthomeConfig>,
+    /// Keys of the components that were skipped because they failed to parse
+    #[serde(skip)]
+    pub parse_errors: Vec<String>,
+    /// Events pushed by the device since the previous status, e.g. button presses
+    #[serde(skip)]
+    pub events: Vec<ComponentEvent>,
+}
+
+/// An event reported through a `NotifyEvent` notification.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ComponentEvent {
+    /// Component that emitted the event, e.g. `input:0`
+    pub component: String,
+    /// Event name, e.g. `single_push` or `long_push`
+    pub event: String,
+}
+
+/// A single Gen2 component, decoded according to the type in its key.
+#[derive(Debug, Serialize, Clone)]
+#[serde(untagged)]
+pub enum Component {
+    Switch(SwitchStatus),
+    Cover(CoverStatus),
+    Light(LightStatus),
+    Input(InputStatus),
+    Em(EmStatus),
+    EmData(EmDataStatus),
+    Em1(Em1Status),
+    Em1Data(Em1DataStatus),
+    Pm1(Pm1Status),
+    Temperature(TemperatureStatus),
+    Humidity(HumidityStatus),
+    Voltmeter(VoltmeterStatus),
+    Illuminance(IlluminanceStatus),
+    DevicePower(DevicePowerStatus),
+    Smoke(AlarmStatus),
+    Flood(AlarmStatus),
+    Thermostat(ThermostatStatus),
+    BthomeDevice(BthomeDeviceStatus),
+    BthomeSensor(BthomeSensorStatus),
+    /// A component type the exporter has no decoder for (yet)
+    Unknown(serde_json::Value),
+}
+
+impl Component {
+    /// Decode a component from its `type:id` key and raw JSON value.
+    pub fn decode(key: &str, value: serde_json::Value) -> serde_json::Result<Self> {
+        let (kind, _) = split_component_key(key);
+
+        Ok(match kind {
+            "switch" => Component::Switch(serde_json::from_value(value)?),
+            "cover" => Component::Cover(serde_json::from_value(value)?),
+            "light" | "rgb" | "rgbw" | "cct" => Component::Light(serde_json::from_value(value)?),
+            "input" => Component::Input(serde_json::from_value(value)?),
+            "em" => Component::Em(serde_json::from_value(value)?),
+            "emdata" => Component::EmData(serde_json::from_value(value)?),
+            "em1" => Component::Em1(serde_json::from_value(value)?),
+            "em1data" => Component::Em1Data(serde_json::from_value(value)?),
+            "pm1" => Component::Pm1(serde_json::from_value(value)?),
+            "temperature" => Component::Temperature(serde_json::from_value(value)?),
+            "humidity" => Component::Humidity(serde_json::from_value(value)?),
+            "voltmeter" => Component::Voltmeter(serde_json::from_value(value)?),
+            "illuminance" => Component::Illuminance(serde_json::from_value(value)?),
+            "devicepower" => Component::DevicePower(serde_json::from_value(value)?),
+            "smoke" => Component::Smoke(serde_json::from_value(value)?),
+            "flood" => Component::Flood(serde_json::from_value(value)?),
+            "thermostat" | "blutrv" => Component::Thermostat(serde_json::from_value(value)?),
+            "bthomedevice" => Component::BthomeDevice(serde_json::from_value(value)?),
+            "bthomesensor" => Component::BthomeSensor(serde_json::from_value(value)?),
+            _ => Component::Unknown(value),
+        })
+    }
+}
+
+/// Split a component key such as `switch:1` into its type and id.
+pub fn split_component_key(key: &str) -> (&str, Option<&str>) {
+    match key.split_once(':') {
+        Some((kind, id)) => (kind, Some(id)),
+        None => (key, None),
+    }
+}
+
+fn deserialize_components<'de, D>(deserializer: D) -> Result<BTreeMap<String, Component>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let raw = BTreeMap::<String, serde_json::Value>::deserialize(deserializer)?;
+
+    raw.into_iter()
+        .map(|(key, value)| {
+            let component = Component::decode(&key, value)
+                .map_err(|e| serde::de::Error::custom(format!("{key}: {e}")))?;
+            Ok((key, component))
+        })
+        .collect()
+}
+
+/// Decode a status object one top-level entry at a time. Entries that fail
+/// to decode (a Gen2 component, a Gen1 section such as `relays`) are left out
+/// and returned with their error, so the rest of the status is still usable.
+/// Within arrays such as the Gen1 `relays`, malformed items are replaced by
+/// `null` so that only their own channel is lost.
+fn decode_lenient<T: DeserializeOwned>(
+    value: serde_json::Value,
+) -> Result<(T, Vec<(String, serde_json::Error)>), ShellyError> {
+    let serde_json::Value::Object(entries) = value else {
+        return Err(ShellyError::Parse("expected a JSON object".to_string()));
+    };
+
+    let single = |key: &str, entry: serde_json::Value| {
+        serde_json::Value::Object(serde_json::Map::from_iter([(key.to_string(), entry)]))
+    };
+
+    let mut valid = serde_json::Map::new();
+    let mut errors = Vec::new();
+
+    for (key, mut entry) in entries {
+        if let serde_json::Value::Array(items) = &mut entry {
+            for (idx, item) in items.iter_mut().enumerate() {
+                let probe = single(&key, serde_json::Value::Array(vec![item.clone()]));
+                if let Err(e) = T::deserialize(&probe) {
+                    errors.push((format!("{key}[{idx}]"), e));
+                    *item = serde_json::Value::Null;
+                }
+            }
+        }
+
+        let single = single(&key, entry);
+        match T::deserialize(&single) {
+            Ok(_) => {
+                if let serde_json::Value::Object(single) = single {
+                    valid.extend(single);
+                }
+            }
+            Err(e) => errors.push((key, e)),
+        }
+    }
+
+    let decoded = serde_json::from_value(serde_json::Value::Object(valid))
+        .map_err(|e| ShellyError::Parse(e.to_string()))?;
+    Ok((decoded, errors))
+}
+
+/// Channels of a Gen1 status array such as `relays`, numbered by position.
+/// Entries that failed to decode are `None`, so the others keep their number.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Channels<T>(pub Vec<Option<T>>);
+
+impl<T> Channels<T> {
+    /// Channel number and entry of every channel that decoded.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.0
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, entry)| Some((idx, entry.as_ref()?)))
+    }
+
+    /// Entry of channel `idx`, if it decoded.
+    #[cfg(test)]
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        self.0.get(idx)?.as_ref()
+    }
 }
 
 // Gen1 Status structures
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ShellyGen1Status {
-    pub relays: Option<Vec<RelayStatus>>,
-    pub meters: Option<Vec<MeterStatus>>,
+    pub relays: Option<Channels<RelayStatus>>,
+    pub rollers: Option<Channels<RollerStatus>>,
+    pub lights: Option<Channels<LightGen1Status>>,
+    pub inputs: Option<Channels<InputGen1Status>>,
+    pub meters: Option<Channels<MeterStatus>>,
+    pub emeters: Option<Channels<EmeterStatus>>,
+    pub thermostats: Option<Channels<ThermostatGen1Status>>,
+    /// Sensor readings of battery devices (H&T, Flood, Door/Window, Smoke)
+    pub tmp: Option<SensorTemperature>,
+    pub hum: Option<SensorValue>,
+    pub lux: Option<SensorValue>,
+    pub bat: Option<BatteryGen1Status>,
+    pub sensor: Option<SensorGen1Status>,
+    pub accel: Option<AccelStatus>,
+    pub flood: Option<bool>,
+    pub smoke: Option<bool>,
+    pub charger: Option<bool>,
+    /// Shelly Gas readings
+    pub gas_sensor: Option<GasSensorStatus>,
+    pub concentration: Option<GasConcentration>,
+    /// Temperature add-on readings keyed by sensor index
+    pub ext_temperature: Option<BTreeMap<String, ExtTemperatureStatus>>,
+    pub ext_humidity: Option<BTreeMap<String, ExtHumidityStatus>>,
+    pub ext_switch: Option<BTreeMap<String, ExtSwitchStatus>>,
     pub temperature: Option<f64>,
     pub overtemperature: Option<bool>,
     pub wifi_sta: Option<WifiGen1Status>,
@@ -47,12 +391,16 @@ pub struct ShellyGen1Status {
     pub fs_size: Option<i64>,
     pub fs_free: Option<i64>,
     pub uptime: Option<i64>,
+    /// Keys of the sections (or `section[channel]` entries) that were skipped
+    /// because they failed to parse
+    #[serde(skip)]
+    pub parse_errors: Vec<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct RelayStatus {
     pub ison: bool,
-    pub has_timer: bool,
+    pub has_timer: Option<bool>,
     pub timer_started: Option<i64>,
     pub timer_duration: Option<i64>,
     pub timer_remaining: Option<i64>,
@@ -60,29 +408,202 @@ pub struct RelayStatus {
     pub source: Option<String>,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RollerStatus {
+    pub state: String,
+    pub power: Option<f64>,
+    pub is_valid: Option<bool>,
+    pub safety_switch: Option<bool>,
+    pub overtemperature: Option<bool>,
+    pub stop_reason: Option<String>,
+    pub last_direction: Option<String>,
+    pub current_pos: Option<f64>,
+    pub calibrating: Option<bool>,
+    pub positioning: Option<bool>,
+}
+
+/// Gen1 `lights[]` entry (Dimmer, Bulb, Duo, RGBW2) in white or color mode
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LightGen1Status {
+    pub ison: bool,
+    pub source: Option<String>,
+    pub mode: Option<String>,
+    pub brightness: Option<f64>,
+    pub gain: Option<f64>,
+    pub red: Option<f64>,
+    pub green: Option<f64>,
+    pub blue: Option<f64>,
+    pub white: Option<f64>,
+    pub temp: Option<f64>,
+    pub overpower: Option<bool>,
+}
+
+/// Gen1 `inputs[]` entry; `event_cnt` counts every event, `event` is the last one
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InputGen1Status {
+    pub input: i32,
+    pub event: Option<String>,
+    pub event_cnt: Option<i64>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct MeterStatus {
     pub power: f64,
+    pub is_valid: Option<bool>,
+    pub timestamp: Option<i64>,
+    pub counters: Option<Vec<f64>>,
+    pub total: Option<f64>,
+}
+
+/// Gen1 `emeters[]` entry (Shelly EM and 3EM)
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EmeterStatus {
+    pub power: f64,
+    pub reactive: Option<f64>,
+    pub pf: Option<f64>,
+    pub voltage: Option<f64>,
+    pub current: Option<f64>,
     pub is_valid: bool,
-    pub timestamp: i64,
-    pub counters: Vec<f64>,
-    pub total: f64,
+    pub total: Option<f64>,
+    pub total_returned: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SensorTemperature {
+    pub value: Option<f64>,
+    pub units: Option<String>,
+    #[serde(rename = "tC")]
+    pub t_c: Option<f64>,
+    #[serde(rename = "tF")]
+    pub t_f: Option<f64>,
+    pub is_valid: Option<bool>,
+}
+
+impl SensorTemperature {
+    pub fn celsius(&self) -> Option<f64> {
+        if self.is_valid == Some(false) {
+            return None;
+        }
+
+        match (self.t_c, self.units.as_deref(), self.value) {
+            (Some(t_c), _, _) => Some(t_c),
+            (None, Some("F"), Some(value)) => Some((value - 32.0) * 5.0 / 9.0),
+            (None, _, value) => value,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SensorValue {
+    pub value: Option<f64>,
+    pub is_valid: Option<bool>,
+}
+
+impl SensorValue {
+    pub fn valid_value(&self) -> Option<f64> {
+        self.value.filter(|_| self.is_valid != Some(false))
+    }
+}
+
+/// Gen1 `thermostats[]` entry of the Shelly TRV
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ThermostatGen1Status {
+    /// Valve position in percent, -1 while not calibrated
+    pub pos: Option<f64>,
+    pub target_t: Option<ThermostatTarget>,
+    pub tmp: Option<SensorTemperature>,
+    pub window_open: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ThermostatTarget {
+    pub enabled: Option<bool>,
+    pub value: Option<f64>,
+    pub units: Option<String>,
+}
+
+impl ThermostatTarget {
+    pub fn celsius(&self) -> Option<f64> {
+        match (self.units.as_deref(), self.value) {
+            (Some("F"), Some(value)) => Some((value - 32.0) * 5.0 / 9.0),
+            (_, value) => value,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BatteryGen1Status {
+    pub value: Option<f64>,
+    pub voltage: Option<f64>,
+}
+
+/// Gen1 `sensor` object; Door/Window report `state` as "open" or "close"
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SensorGen1Status {
+    /// Door/Window contact state (`open` or `close`)
+    pub state: Option<String>,
+    /// Motion sensor readings
+    pub motion: Option<bool>,
+    pub vibration: Option<bool>,
+    pub is_valid: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AccelStatus {
+    pub tilt: Option<f64>,
+    pub vibration: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GasSensorStatus {
+    pub sensor_state: Option<String>,
+    pub self_test_state: Option<String>,
+    pub alarm_state: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GasConcentration {
+    pub ppm: Option<f64>,
+    pub is_valid: Option<bool>,
+}
+
+/// DS18B20/DHT22 probe on the Gen1 temperature add-on
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExtTemperatureStatus {
+    #[serde(rename = "hwID")]
+    pub hw_id: Option<String>,
+    #[serde(rename = "tC")]
+    pub t_c: Option<f64>,
+    #[serde(rename = "tF")]
+    pub t_f: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExtHumidityStatus {
+    #[serde(rename = "hwID")]
+    pub hw_id: Option<String>,
+    pub hum: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExtSwitchStatus {
+    pub input: i32,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct WifiGen1Status {
-    pub connected: bool,
+    pub connected: Option<bool>,
     pub ssid: Option<String>,
     pub ip: Option<String>,
-    pub rssi: i32,
+    pub rssi: Option<i32>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct UpdateStatus {
-    pub status: String,
-    pub has_update: bool,
+    pub status: Option<String>,
+    pub has_update: Option<bool>,
     pub new_version: Option<String>,
-    pub old_version: String,
+    pub old_version: Option<String>,
 }
 
 // Unified status enum
@@ -108,506 +629,2552 @@ pub struct SwitchStatus {
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct EnergyCounter {
-    pub total: f64,
-    pub by_minute: Vec<f64>,
-    pub minute_ts: i64,
+pub struct CoverStatus {
+    pub id: i32,
+    pub source: Option<String>,
+    pub state: String,
+    pub apower: Option<f64>,
+    pub voltage: Option<f64>,
+    pub current: Option<f64>,
+    pub freq: Option<f64>,
+    pub pf: Option<f64>,
+    pub aenergy: Option<EnergyCounter>,
+    pub temperature: Option<Temperature>,
+    pub pos_control: Option<bool>,
+    pub current_pos: Option<f64>,
+    pub target_pos: Option<f64>,
+    pub last_direction: Option<String>,
 }
 
+/// Gen2 `light:N`, `rgb:N`, `rgbw:N` and `cct:N` components
 #[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct Temperature {
-    #[serde(rename = "tC")]
-    pub t_c: Option<f64>,
-    #[serde(rename = "tF")]
-    pub t_f: Option<f64>,
+pub struct LightStatus {
+    pub id: i32,
+    pub source: Option<String>,
+    pub output: bool,
+    pub brightness: Option<f64>,
+    pub ct: Option<f64>,
+    pub rgb: Option<Vec<f64>>,
+    pub white: Option<f64>,
+    pub apower: Option<f64>,
+    pub voltage: Option<f64>,
+    pub current: Option<f64>,
+    pub aenergy: Option<EnergyCounter>,
+    pub temperature: Option<Temperature>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct SystemStatus {
-    pub mac: String,
-    pub restart_required: bool,
-    pub time: Option<String>,
-    pub unixtime: Option<i64>,
-    pub uptime: i64,
-    pub ram_size: i64,
-    pub ram_free: i64,
-    pub fs_size: i64,
-    pub fs_free: i64,
-    pub cfg_rev: i32,
-    pub available_updates: Option<AvailableUpdates>,
+/// Gen2 `input:N` component in switch/button, analog or counter mode
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct InputStatus {
+    pub id: i32,
+    /// Digital state; `null` for inputs in button mode
+    pub state: Option<bool>,
+    /// Analog reading (analog mode)
+    pub percent: Option<f64>,
+    /// Pulse counts (counter mode)
+    pub counts: Option<InputCounts>,
+    pub freq: Option<f64>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct AvailableUpdates {
-    pub stable: Option<UpdateInfo>,
-    pub beta: Option<UpdateInfo>,
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct InputCounts {
+    pub total: f64,
+    pub xtotal: Option<f64>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct UpdateInfo {
-    pub version: String,
+/// Gen2 `em:N` component of three-phase energy meters (Pro 3EM)
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EmStatus {
+    pub id: i32,
+    pub a_current: Option<f64>,
+    pub a_voltage: Option<f64>,
+    pub a_act_power: Option<f64>,
+    pub a_aprt_power: Option<f64>,
+    pub a_pf: Option<f64>,
+    pub a_freq: Option<f64>,
+    pub b_current: Option<f64>,
+    pub b_voltage: Option<f64>,
+    pub b_act_power: Option<f64>,
+    pub b_aprt_power: Option<f64>,
+    pub b_pf: Option<f64>,
+    pub b_freq: Option<f64>,
+    pub c_current: Option<f64>,
+    pub c_voltage: Option<f64>,
+    pub c_act_power: Option<f64>,
+    pub c_aprt_power: Option<f64>,
+    pub c_pf: Option<f64>,
+    pub c_freq: Option<f64>,
+    pub n_current: Option<f64>,
+    pub total_current: Option<f64>,
+    pub total_act_power: Option<f64>,
+    pub total_aprt_power: Option<f64>,
+    pub errors: Option<Vec<String>>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct WifiStatus {
-    pub sta_ip: Option<String>,
-    pub status: String,
-    pub ssid: Option<String>,
-    pub rssi: Option<i32>,
+/// Readings of one phase (or the total) of an `em:N` component
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EmPhase {
+    pub current: Option<f64>,
+    pub voltage: Option<f64>,
+    pub act_power: Option<f64>,
+    pub aprt_power: Option<f64>,
+    pub pf: Option<f64>,
+    pub freq: Option<f64>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct DeviceInfo {
-    pub name: String,
-    pub id: String,
-    pub mac: String,
-    pub model: String,
-    #[serde(rename = "gen")]
-    pub generation: i32,
-    pub fw_id: String,
-    pub ver: String,
-    pub app: String,
-    pub auth_en: bool,
-    pub auth_domain: Option<String>,
+impl EmStatus {
+    /// Per-phase readings labelled `a`, `b`, `c` and `total`.
+    pub fn phases(&self) -> [(&'static str, EmPhase); 4] {
+        [
+            (
+                "a",
+                EmPhase {
+                    current: self.a_current,
+                    voltage: self.a_voltage,
+                    act_power: self.a_act_power,
+                    aprt_power: self.a_aprt_power,
+                    pf: self.a_pf,
+                    freq: self.a_freq,
+                },
+            ),
+            (
+                "b",
+                EmPhase {
+                    current: self.b_current,
+                    voltage: self.b_voltage,
+                    act_power: self.b_act_power,
+                    aprt_power: self.b_aprt_power,
+                    pf: self.b_pf,
+                    freq: self.b_freq,
+                },
+            ),
+            (
+                "c",
+                EmPhase {
+                    current: self.c_current,
+                    voltage: self.c_voltage,
+                    act_power: self.c_act_power,
+                    aprt_power: self.c_aprt_power,
+                    pf: self.c_pf,
+                    freq: self.c_freq,
+                },
+            ),
+            (
+                "total",
+                EmPhase {
+                    current: self.total_current,
+                    act_power: self.total_act_power,
+                    aprt_power: self.total_aprt_power,
+                    ..Default::default()
+                },
+            ),
+        ]
+    }
+
+    /// Largest deviation of a phase current from the three-phase average,
+    /// relative to that average (0 for a perfectly balanced load).
+    pub fn current_imbalance(&self) -> Option<f64> {
+        let currents = [self.a_current?, self.b_current?, self.c_current?];
+        let average = currents.iter().sum::<f64>() / 3.0;
+        if average <= 0.0 {
+            return None;
+        }
+
+        let max_deviation = currents
+            .iter()
+            .map(|current| (current - average).abs())
+            .fold(0.0, f64::max);
+        Some(max_deviation / average)
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct RpcRequest {
-    id: i32,
-    method: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    params: Option<serde_json::Value>,
+/// Gen2 `emdata:N` component with the energy counters of an `em:N` meter
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EmDataStatus {
+    pub id: i32,
+    pub a_total_act_energy: Option<f64>,
+    pub a_total_act_ret_energy: Option<f64>,
+    pub b_total_act_energy: Option<f64>,
+    pub b_total_act_ret_energy: Option<f64>,
+    pub c_total_act_energy: Option<f64>,
+    pub c_total_act_ret_energy: Option<f64>,
+    pub total_act: Option<f64>,
+    pub total_act_ret: Option<f64>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct RpcResponse<T> {
-    id: i32,
-    result: Option<T>,
-    error: Option<RpcError>,
+impl EmDataStatus {
+    /// `(phase, consumed Wh, returned Wh)` for phases `a`, `b`, `c` and `total`.
+    pub fn phases(&self) -> [(&'static str, Option<f64>, Option<f64>); 4] {
+        [
+            ("a", self.a_total_act_energy, self.a_total_act_ret_energy),
+            ("b", self.b_total_act_energy, self.b_total_act_ret_energy),
+            ("c", self.c_total_act_energy, self.c_total_act_ret_energy),
+            ("total", self.total_act, self.total_act_ret),
+        ]
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct RpcError {
-    code: i32,
-    message: String,
+/// Gen2 `em1:N` component of single-phase energy meters (Pro EM)
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Em1Status {
+    pub id: i32,
+    pub current: Option<f64>,
+    pub voltage: Option<f64>,
+    pub act_power: Option<f64>,
+    pub aprt_power: Option<f64>,
+    pub pf: Option<f64>,
+    pub freq: Option<f64>,
+    pub calibration: Option<String>,
+    pub errors: Option<Vec<String>>,
 }
 
-impl ShellyClient {
-    pub fn new(
-        base_url: String,
-        timeout: Duration,
-        auth: Option<(String, String)>,
-        generation: ShellyGeneration,
-    ) -> Result<Self> {
-        let client = Client::builder()
-            .timeout(timeout)
-            .build()
-            .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?;
+/// Gen2 `em1data:N` component with the energy counters of an `em1:N` meter
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Em1DataStatus {
+    pub id: i32,
+    pub total_act_energy: Option<f64>,
+    pub total_act_ret_energy: Option<f64>,
+}
 
-        Ok(Self {
-            client,
-            base_url,
-            auth,
-            generation,
-        })
+/// Gen2 `pm1:N` power meter component (Plus PM Mini)
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Pm1Status {
+    pub id: i32,
+    pub apower: Option<f64>,
+    pub voltage: Option<f64>,
+    pub current: Option<f64>,
+    pub freq: Option<f64>,
+    pub pf: Option<f64>,
+    pub aenergy: Option<EnergyCounter>,
+    pub ret_aenergy: Option<EnergyCounter>,
+    pub errors: Option<Vec<String>>,
+}
+
+/// Gen2 `temperature:N` sensor component
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TemperatureStatus {
+    pub id: i32,
+    #[serde(rename = "tC")]
+    pub t_c: Option<f64>,
+    #[serde(rename = "tF")]
+    pub t_f: Option<f64>,
+    pub errors: Option<Vec<String>>,
+}
+
+/// Gen2 `humidity:N` sensor component
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct HumidityStatus {
+    pub id: i32,
+    pub rh: Option<f64>,
+    pub errors: Option<Vec<String>>,
+}
+
+/// Gen2 `voltmeter:N` component of the Plus Add-on
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VoltmeterStatus {
+    pub id: i32,
+    pub voltage: Option<f64>,
+    pub xvoltage: Option<f64>,
+    pub errors: Option<Vec<String>>,
+}
+
+/// Gen2 `illuminance:N` sensor component
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct IlluminanceStatus {
+    pub id: i32,
+    pub lux: Option<f64>,
+    pub illumination: Option<String>,
+    pub errors: Option<Vec<String>>,
+}
+
+/// Gen2 `devicepower:N` component of battery powered devices
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DevicePowerStatus {
+    pub id: i32,
+    pub battery: Option<BatteryStatus>,
+    pub external: Option<ExternalPowerStatus>,
+    pub errors: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BatteryStatus {
+    #[serde(rename = "V")]
+    pub voltage: Option<f64>,
+    pub percent: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ExternalPowerStatus {
+    pub present: bool,
+}
+
+/// Gen2 `smoke:N` and `flood:N` alarm components
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AlarmStatus {
+    pub id: i32,
+    pub alarm: bool,
+    pub mute: Option<bool>,
+}
+
+/// Gen2 `thermostat:N` (Wall Display) and `blutrv:N` (BLU TRV on a gateway) components
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ThermostatStatus {
+    pub id: i32,
+    pub enable: Option<bool>,
+    #[serde(rename = "target_C")]
+    pub target_c: Option<f64>,
+    #[serde(rename = "current_C")]
+    pub current_c: Option<f64>,
+    /// Valve position in percent (BLU TRV)
+    pub pos: Option<f64>,
+    pub window_open: Option<bool>,
+    pub battery: Option<f64>,
+    pub errors: Option<Vec<String>>,
+}
+
+/// Gen2 `bthomedevice:N` component: a BLU device paired with this gateway
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BthomeDeviceStatus {
+    pub id: i32,
+    pub rssi: Option<i64>,
+    pub battery: Option<f64>,
+    pub packet_id: Option<i64>,
+    pub last_updated_ts: Option<i64>,
+    pub errors: Option<Vec<String>>,
+}
+
+/// Gen2 `bthomesensor:N` component: a single object reported by a BLU device
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BthomeSensorStatus {
+    pub id: i32,
+    /// Numeric or boolean reading, depending on the BTHome object
+    pub value: Option<serde_json::Value>,
+    pub last_updated_ts: Option<i64>,
+}
+
+impl BthomeSensorStatus {
+    pub fn numeric_value(&self) -> Option<f64> {
+        match self.value.as_ref()? {
+            serde_json::Value::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+            value => value.as_f64(),
+        }
+    }
+}
+
+/// Config of a `bthomedevice:N` or `bthomesensor:N` component
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct BthomeConfig {
+    pub addr: String,
+    pub name: Option<String>,
+    /// BTHome object id (sensors only)
+    pub obj_id: Option<u32>,
+    /// Index among objects of the same id on one device (sensors only)
+    pub idx: Option<u32>,
+}
+
+/// Name of a BTHome object id, for the objects sent by Shelly BLU devices.
+pub fn bthome_object_name(obj_id: u32) -> &'static str {
+    match obj_id {
+        0x01 => "battery",
+        0x02 | 0x45 => "temperature",
+        0x03 | 0x2e => "humidity",
+        0x05 => "illuminance",
+        0x0c => "voltage",
+        0x1a => "door",
+        0x20 => "moisture",
+        0x21 => "motion",
+        0x2d => "window",
+        0x3a => "button",
+        0x3f => "rotation",
+        _ => "unknown",
     }
+}
 
-    pub async fn detect_generation(
-        base_url: &str,
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EnergyCounter {
+    pub total: f64,
+    pub by_minute: Vec<f64>,
+    pub minute_ts: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Temperature {
+    #[serde(rename = "tC")]
+    pub t_c: Option<f64>,
+    #[serde(rename = "tF")]
+    pub t_f: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SystemStatus {
+    pub mac: Option<String>,
+    pub restart_required: Option<bool>,
+    pub time: Option<String>,
+    pub unixtime: Option<i64>,
+    pub uptime: Option<i64>,
+    pub ram_size: Option<i64>,
+    pub ram_free: Option<i64>,
+    pub fs_size: Option<i64>,
+    pub fs_free: Option<i64>,
+    pub cfg_rev: Option<i32>,
+    pub available_updates: Option<AvailableUpdates>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AvailableUpdates {
+    pub stable: Option<UpdateInfo>,
+    pub beta: Option<UpdateInfo>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UpdateInfo {
+    pub version: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WifiStatus {
+    pub sta_ip: Option<String>,
+    pub status: Option<String>,
+    pub ssid: Option<String>,
+    pub rssi: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub id: String,
+    pub mac: String,
+    pub model: String,
+    #[serde(rename = "gen")]
+    pub generation: i32,
+    pub fw_id: String,
+    pub ver: String,
+    pub app: String,
+    pub auth_en: bool,
+    pub auth_domain: Option<String>,
+}
+
+/// Gen1 `/shelly` response, available without authentication. Firmware
+/// versions differ in what they include, so every field is optional.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Gen1ShellyInfo {
+    #[serde(rename = "type", default)]
+    pub device_type: String,
+    #[serde(default)]
+    pub mac: String,
+    #[serde(default)]
+    pub auth: bool,
+    #[serde(default)]
+    pub fw: String,
+    pub num_outputs: Option<u32>,
+    pub num_meters: Option<u32>,
+    pub num_rollers: Option<u32>,
+    /// Only present on Gen2 and later devices, which serve `/shelly` too
+    #[serde(rename = "gen")]
+    pub generation: Option<i64>,
+}
+
+/// Gen1 device families that are parsed differently, derived from the
+/// model code reported by `/shelly`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gen1Family {
+    /// Relays and plugs (Shelly 1/1PM/2.5/4Pro, Plug, UNI)
+    Relay,
+    /// Dimmers, bulbs and RGBW controllers, whose meters measure light channels
+    Light,
+    /// Energy meters, sensors and everything else
+    Other,
+}
+
+impl Gen1Family {
+    /// Classify a Gen1 model code such as `SHSW-25`; `None` if it isn't one.
+    pub fn from_model(model: &str) -> Option<Self> {
+        const RELAYS: &[&str] = &["SHSW-", "SHPLG", "SHUNI-"];
+        const LIGHTS: &[&str] = &["SHDM-", "SHRGBW2", "SHBLB-", "SHBDUO-", "SHVIN-", "SHCB-"];
+
+        if LIGHTS.iter().any(|prefix| model.starts_with(prefix)) {
+            Some(Gen1Family::Light)
+        } else if RELAYS.iter().any(|prefix| model.starts_with(prefix)) {
+            Some(Gen1Family::Relay)
+        } else if model.starts_with("SH") {
+            Some(Gen1Family::Other)
+        } else {
+            None
+        }
+    }
+}
+
+/// The parts of the Gen1 `/settings` response that identify the device
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Gen1Settings {
+    pub device: Gen1SettingsDevice,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Gen1SettingsDevice {
+    pub hostname: String,
+}
+
+/// Extract the version from a Gen1 firmware id such as
+/// `20230913-112003/v1.14.0-gcb84623`.
+fn gen1_firmware_version(fw: &str) -> &str {
+    let version = fw.rsplit('/').next().unwrap_or(fw);
+    let version = version.strip_prefix('v').unwrap_or(version);
+    version.split('-').next().unwrap_or(version)
+}
+
+impl ShellyClient {
+    pub fn new(
+        base_url: String,
         timeout: Duration,
         auth: Option<(String, String)>,
-    ) -> Result<ShellyGeneration> {
+        generation: ShellyGeneration,
+    ) -> Result<Self> {
         let client = Client::builder()
             .timeout(timeout)
             .build()
             .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?;
 
-        // Try Gen2 endpoint first
-        let gen2_url = format!("{base_url}/rpc/Shelly.GetDeviceInfo");
-        let mut request = client.get(&gen2_url);
+        Ok(Self {
+            client,
+            base_url,
+            auth,
+            digest: Arc::new(Mutex::new(None)),
+            bthome_config: Arc::new(Mutex::new(BthomeConfigCache::default())),
+            push: None,
+            push_only: false,
+            mqtt_rpc: None,
+            generation,
+        })
+    }
+
+    /// A client for a device that connected to the exporter's WebSocket
+    /// endpoint itself. Its status is only ever pushed, so there is nothing
+    /// to fall back to while it is disconnected.
+    pub fn inbound(
+        host: &str,
+        generation: ShellyGeneration,
+        transport: Arc<WebSocketTransport>,
+    ) -> Result<Self> {
+        let mut client = Self::new(host.to_string(), Duration::ZERO, None, generation)?;
+        client.push = Some(PushSource::WebSocket(transport));
+        client.push_only = true;
+        Ok(client)
+    }
 
-        if let Some((username, password)) = &auth {
-            request = request.basic_auth(username, Some(password));
-        }
+    /// A client for a device only heard of through the MQTT broker, under
+    /// the id (Gen1) or topic prefix (Gen2) it publishes with.
+    pub fn mqtt_only(
+        device_id: &str,
+        generation: ShellyGeneration,
+        listener: Arc<MqttListener>,
+    ) -> Result<Self> {
+        let mut client = Self::new(device_id.to_string(), Duration::ZERO, None, generation)?;
+        client.push = Some(PushSource::Mqtt(listener, device_id.to_string()));
+        client.push_only = true;
+        Ok(client)
+    }
 
-        if let Ok(response) = request.send().await {
-            if response.status().is_success() {
-                info!("Detected Gen2 device at {}", base_url);
-                return Ok(ShellyGeneration::Gen2);
-            }
+    /// A client for a Gen2+ device that is polled with RPC requests through
+    /// the MQTT broker, under the topic prefix it is configured with. Status
+    /// the device publishes by itself is used when there is any.
+    ///
+    /// The generation is taken from `Shelly.GetDeviceInfo`, whose result is
+    /// returned along with the client.
+    pub async fn over_mqtt(
+        prefix: &str,
+        listener: Arc<MqttListener>,
+        timeout: Duration,
+        auth: Option<(String, String)>,
+    ) -> Result<(Self, DeviceInfo)> {
+        let rpc = Arc::new(listener.rpc(prefix, auth.clone(), timeout));
+        let info: DeviceInfo = serde_json::from_value(rpc.call("Shelly.GetDeviceInfo").await?)?;
+        let generation = ShellyGeneration::from_rpc_gen(info.generation.into());
+
+        let mut client = Self::new(format!("mqtt://{prefix}"), timeout, auth, generation)?;
+        client.mqtt_rpc = Some(rpc);
+        client.push = Some(PushSource::Mqtt(listener, prefix.to_string()));
+        Ok((client, info))
+    }
+
+    /// A client for a Gen1 device only known from its CoIoT packets, such as
+    /// a sleeping sensor. `base_url` is `http://<ip>` of the device.
+    pub fn coiot_only(base_url: &str, listener: Arc<CoiotListener>) -> Result<Self> {
+        let mut client = Self::new(
+            base_url.to_string(),
+            Duration::ZERO,
+            None,
+            ShellyGeneration::Gen1,
+        )?;
+        if !client.enable_coiot(listener) {
+            return Err(anyhow!("{} is not an IP address", base_url));
         }
+        client.push_only = true;
+        Ok(client)
+    }
+
+    /// Whether the client was registered by a device connecting to the
+    /// exporter's WebSocket endpoint.
+    pub fn is_inbound(&self) -> bool {
+        self.push_only && matches!(self.push, Some(PushSource::WebSocket(_)))
+    }
+
+    /// Fall back to the values the device multicasts over CoIoT when polling
+    /// `/status` fails. Only works for devices configured by IP address;
+    /// returns whether the device's address could be determined.
+    pub fn enable_coiot(&mut self, listener: Arc<CoiotListener>) -> bool {
+        let ip = reqwest::Url::parse(&self.base_url)
+            .ok()
+            .and_then(|url| url.host_str()?.trim_matches(['[', ']']).parse().ok());
+        self.push = ip.map(|ip| PushSource::Coiot(listener, ip));
+        self.push.is_some()
+    }
+
+    /// Keep a WebSocket connection to the device and answer status requests
+    /// from what it pushes, falling back to HTTP while it is disconnected.
+    pub fn enable_websocket(&mut self, timeout: Duration) {
+        self.push = Some(PushSource::WebSocket(Arc::new(
+            WebSocketTransport::connect(&self.base_url, self.auth.clone(), timeout),
+        )));
+    }
+
+    pub async fn detect_generation(
+        base_url: &str,
+        timeout: Duration,
+        auth: Option<(String, String)>,
+    ) -> Result<ShellyGeneration> {
+        // Try the RPC endpoint (Gen2 and later) first
+        let client = Self::new(
+            base_url.to_string(),
+            timeout,
+            auth.clone(),
+            ShellyGeneration::Gen2,
+        )?;
 
-        // Try Gen1 endpoint
-        let gen1_url = format!("{base_url}/settings");
-        let mut request = client.get(&gen1_url);
+        if let Ok(response) = client.get("/rpc/Shelly.GetDeviceInfo").await
+            && response.status().is_success()
+        {
+            let generation = response
+                .json::<serde_json::Value>()
+                .await
+                .ok()
+                .and_then(|info| info.get("gen")?.as_i64())
+                .map(ShellyGeneration::from_rpc_gen)
+                .unwrap_or(ShellyGeneration::Gen2);
 
-        if let Some((username, password)) = &auth {
-            request = request.basic_auth(username, Some(password));
+            info!("Detected {:?} device at {}", generation, base_url);
+            return Ok(generation);
         }
 
-        if let Ok(response) = request.send().await {
-            if response.status().is_success() {
-                info!("Detected Gen1 device at {}", base_url);
-                return Ok(ShellyGeneration::Gen1);
+        // Gen1 devices answer `/shelly` with their type, even with auth enabled.
+        // Later generations serve it too, with a `gen` field, which catches
+        // Gen2+ devices whose RPC probe failed (timeout, auth)
+        let client = Self::new(base_url.to_string(), timeout, auth, ShellyGeneration::Gen1)?;
+
+        if let Ok(response) = client.get("/shelly").await
+            && response.status().is_success()
+        {
+            match response.json::<Gen1ShellyInfo>().await {
+                Ok(Gen1ShellyInfo {
+                    generation: Some(generation),
+                    ..
+                }) if generation >= 2 => {
+                    let generation = ShellyGeneration::from_rpc_gen(generation);
+                    info!(
+                        "Detected {:?} device at {} from /shelly",
+                        generation, base_url
+                    );
+                    return Ok(generation);
+                }
+                Ok(shelly) if shelly.generation.is_none() && !shelly.device_type.is_empty() => {
+                    info!(
+                        "Detected Gen1 {} at {} ({} outputs, {} meters)",
+                        shelly.device_type,
+                        base_url,
+                        shelly.num_outputs.unwrap_or(0),
+                        shelly.num_meters.unwrap_or(0)
+                    );
+                    return Ok(ShellyGeneration::Gen1);
+                }
+                Ok(_) => debug!("Unrecognized /shelly response from {}", base_url),
+                Err(e) => debug!("Unexpected /shelly response from {}: {}", base_url, e),
             }
         }
 
+        // Fall back to `/settings` for firmware without a usable `/shelly`
+        if let Ok(response) = client.get("/settings").await
+            && response.status().is_success()
+        {
+            info!("Detected Gen1 device at {}", base_url);
+            return Ok(ShellyGeneration::Gen1);
+        }
+
         Err(anyhow!(
             "Failed to detect Shelly generation for {}",
             base_url
         ))
     }
 
-    pub async fn get_device_info(&self) -> Result<DeviceInfo> {
-        let url = format!("{}/rpc/Shelly.GetDeviceInfo", self.base_url);
-        debug!("Fetching device info from: {}", url);
+    /// Send an authenticated GET request for `path`.
+    ///
+    /// Gen1 devices use HTTP Basic auth. Gen2+ devices use HTTP Digest auth
+    /// (SHA-256): the last challenge is cached so later requests authenticate
+    /// up front, and a rejected or stale nonce is retried once with the fresh
+    /// challenge from the 401 response.
+    async fn get(&self, path: &str) -> reqwest::Result<Response> {
+        let url = format!("{}{}", self.base_url, path);
+
+        let Some((username, password)) = &self.auth else {
+            return self.client.get(&url).send().await;
+        };
+
+        if self.generation == ShellyGeneration::Gen1 {
+            return self
+                .client
+                .get(&url)
+                .basic_auth(username, Some(password))
+                .send()
+                .await;
+        }
+
+        let cached = self
+            .digest
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map(|session| session.authorization("GET", path, username, password));
 
         let mut request = self.client.get(&url);
+        if let Some(authorization) = cached {
+            request = request.header(AUTHORIZATION, authorization);
+        }
 
-        if let Some((username, password)) = &self.auth {
-            request = request.basic_auth(username, Some(password));
+        let response = request.send().await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
         }
 
-        let response = request
+        let Some(challenge) = response
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(DigestChallenge::parse)
+        else {
+            return Ok(response);
+        };
+
+        debug!(
+            "Digest challenge from {} (realm {}, stale: {})",
+            self.base_url, challenge.realm, challenge.stale
+        );
+
+        let authorization = self
+            .digest
+            .lock()
+            .unwrap()
+            .insert(DigestSession::new(challenge))
+            .authorization("GET", path, username, password);
+
+        self.client
+            .get(&url)
+            .header(AUTHORIZATION, authorization)
             .send()
             .await
-            .map_err(|e| anyhow!("Failed to fetch device info: {}", e))?;
+    }
 
-        if !response.status().is_success() {
-            return Err(anyhow!(
-                "Failed to fetch device info: HTTP {}",
-                response.status()
-            ));
+    /// GET `path` and decode the JSON body, classifying any failure.
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T, ShellyError> {
+        debug!("Fetching {}{}", self.base_url, path);
+
+        let response = self.get(path).await?;
+
+        match response.status() {
+            status if status.is_success() => {}
+            status @ (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+                return Err(ShellyError::Unauthorized(status));
+            }
+            status => return Err(ShellyError::Http(status)),
         }
 
-        let device_info = response
-            .json::<DeviceInfo>()
-            .await
-            .map_err(|e| anyhow!("Failed to parse device info: {}", e))?;
+        let body = response.bytes().await?;
+        serde_json::from_slice(&body).map_err(|e| ShellyError::Parse(e.to_string()))
+    }
+
+    /// Call an RPC method of a Gen2+ device, over MQTT for devices reached
+    /// through the broker and over HTTP otherwise.
+    async fn call_rpc<T: DeserializeOwned>(&self, method: &str) -> Result<T, ShellyError> {
+        match &self.mqtt_rpc {
+            Some(rpc) => serde_json::from_value(rpc.call(method).await?)
+                .map_err(|e| ShellyError::Parse(e.to_string())),
+            None => self.get_json(&format!("/rpc/{method}")).await,
+        }
+    }
+
+    pub async fn get_device_info(&self) -> Result<DeviceInfo, ShellyError> {
+        if !self.generation.is_rpc() {
+            return self.get_gen1_device_info().await;
+        }
+
+        let device_info: DeviceInfo = self.call_rpc("Shelly.GetDeviceInfo").await?;
+
+        info!("Device info: {} ({})", device_info.name, device_info.model);
+        Ok(device_info)
+    }
+
+    /// Build the device info of a Gen1 device from `/shelly` and, when the
+    /// credentials allow it, `/settings` for the hostname and name.
+    async fn get_gen1_device_info(&self) -> Result<DeviceInfo, ShellyError> {
+        let shelly: Gen1ShellyInfo = self.get_json("/shelly").await?;
+        let settings = self.get_json::<Gen1Settings>("/settings").await.ok();
+
+        let id = settings
+            .as_ref()
+            .map(|settings| settings.device.hostname.clone())
+            .unwrap_or_default();
+        let name = settings
+            .and_then(|settings| settings.name)
+            .unwrap_or_else(|| id.clone());
+
+        let device_info = DeviceInfo {
+            name,
+            id,
+            mac: shelly.mac,
+            model: if shelly.device_type.is_empty() {
+                "Unknown".to_string()
+            } else {
+                shelly.device_type
+            },
+            generation: 1,
+            ver: gen1_firmware_version(&shelly.fw).to_string(),
+            fw_id: shelly.fw,
+            app: String::new(),
+            auth_en: shelly.auth,
+            auth_domain: None,
+        };
 
         info!("Device info: {} ({})", device_info.name, device_info.model);
         Ok(device_info)
     }
 
-    pub async fn get_status(&self) -> Result<ShellyStatus> {
-        match self.generation {
-            ShellyGeneration::Gen2 => self.get_gen2_status().await,
-            ShellyGeneration::Gen1 => self.get_gen1_status().await,
-        }
+    /// Fetch the user configured names of all components (e.g. `temperature:100`).
+    /// Components without a name are left out.
+    pub async fn get_component_names(&self) -> Result<BTreeMap<String, String>, ShellyError> {
+        Ok(self
+            .get_config()
+            .await?
+            .into_iter()
+            .filter(|(key, _)| key.contains(':'))
+            .filter_map(|(key, value)| {
+                let name = value.get("name")?.as_str()?.to_string();
+                Some((key, name))
+            })
+            .collect())
+    }
+
+    async fn get_config(&self) -> Result<BTreeMap<String, serde_json::Value>, ShellyError> {
+        self.call_rpc("Shelly.GetConfig").await
+    }
+
+    /// Attach the config of all BTHome components in `status`, refreshing the
+    /// cached config when a BLU device was paired or removed since the last
+    /// refresh, and every `BTHOME_CONFIG_REFRESH`.
+    async fn attach_bthome_config(&self, status: &mut ShellyGen2Status) {
+        let is_bthome =
+            |key: &str| matches!(split_component_key(key).0, "bthomedevice" | "bthomesensor");
+
+        let stale = {
+            let cache = self.bthome_config.lock().unwrap();
+            let added = status
+                .components
+                .keys()
+                .any(|key| is_bthome(key) && !cache.config.contains_key(key));
+            let removed = cache.config.keys().any(|key| {
+                split_component_key(key).0 == "bthomedevice" && !status.components.contains_key(key)
+            });
+            let expired = cache
+                .refreshed
+                .is_some_and(|at| at.elapsed() >= BTHOME_CONFIG_REFRESH);
+            added || removed || expired
+        };
+
+        if stale {
+            match self.get_config().await {
+                Ok(config) => {
+                    let mut bthome: BTreeMap<_, _> = config
+                        .into_iter()
+                        .filter(|(key, _)| is_bthome(key))
+                        .map(|(key, value)| (key, serde_json::from_value(value).ok()))
+                        .collect();
+                    for key in status.components.keys().filter(|key| is_bthome(key)) {
+                        bthome.entry(key.clone()).or_insert(None);
+                    }
+                    *self.bthome_config.lock().unwrap() = BthomeConfigCache {
+                        config: bthome,
+                        refreshed: Some(Instant::now()),
+                    };
+                }
+                Err(e) => debug!("Failed to refresh BTHome config: {}", e),
+            }
+        }
+
+        let cache = self.bthome_config.lock().unwrap();
+        status.bthome_config = status
+            .components
+            .keys()
+            .filter_map(|key| Some((key.clone(), cache.config.get(key)?.clone()?)))
+            .collect();
+    }
+
+    pub async fn get_status(&self) -> Result<ShellyStatus, ShellyError> {
+        match self.generation {
+            ShellyGeneration::Gen1 => self.get_gen1_status().await,
+            ShellyGeneration::Gen2 | ShellyGeneration::Gen3 | ShellyGeneration::Gen4 => {
+                self.get_gen2_status().await
+            }
+        }
+    }
+
+    /// Fetch a status document, skipping (and logging) entries that fail to parse.
+    async fn get_status_lenient<T: DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<(T, Vec<String>), ShellyError> {
+        let value = self.get_json(path).await?;
+        self.decode_status(value)
+    }
+
+    fn decode_status<T: DeserializeOwned>(
+        &self,
+        value: serde_json::Value,
+    ) -> Result<(T, Vec<String>), ShellyError> {
+        let (status, errors) = decode_lenient(value)?;
+
+        let skipped = errors
+            .into_iter()
+            .map(|(key, e)| {
+                warn!("Skipping malformed {} from {}: {}", key, self.base_url, e);
+                key
+            })
+            .collect();
+
+        Ok((status, skipped))
+    }
+
+    async fn get_gen2_status(&self) -> Result<ShellyStatus, ShellyError> {
+        let (mut status, parse_errors): (ShellyGen2Status, _) =
+            match self.push.as_ref().and_then(PushSource::status) {
+                Some(value) => self.decode_status(value)?,
+                None if self.push_only => return Err(ShellyError::NotPushed),
+                None => self.decode_status(self.call_rpc("Shelly.GetStatus").await?)?,
+            };
+        status.parse_errors = parse_errors;
+        if let Some(push) = &self.push {
+            status.events = push.take_events();
+        }
+
+        self.attach_bthome_config(&mut status).await;
+
+        debug!("Gen2 status fetched successfully");
+        Ok(ShellyStatus::Gen2(Box::new(status)))
+    }
+
+    async fn get_gen1_status(&self) -> Result<ShellyStatus, ShellyError> {
+        // Take the pushed status on every poll so that a fallback is never stale
+        let pushed = self.push.as_ref().and_then(PushSource::status);
+        let (mut status, parse_errors): (ShellyGen1Status, _) = if self.push_only {
+            self.decode_status(pushed.ok_or(ShellyError::NotPushed)?)?
+        } else {
+            // CoIoT only carries part of `/status`, so it is used when the
+            // device can't be polled, such as a sensor that went back to sleep
+            match (self.get_status_lenient("/status").await, pushed) {
+                (Ok(status), _) => status,
+                (Err(e), Some(value)) => {
+                    debug!("Using pushed status for {}: {}", self.base_url, e);
+                    self.decode_status(value)?
+                }
+                (Err(e), None) => return Err(e),
+            }
+        };
+        status.parse_errors = parse_errors;
+
+        debug!("Gen1 status fetched successfully");
+        Ok(ShellyStatus::Gen1(Box::new(status)))
+    }
+
+    pub async fn discover_devices(_timeout: Duration) -> Result<Vec<String>> {
+        info!("Starting mDNS discovery for Shelly devices...");
+        let devices = Vec::new();
+
+        // Note: mDNS discovery would be implemented here
+        // For now, we'll return an empty list and rely on manually configured devices
+
+        Ok(devices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+    use std::collections::HashMap;
+    use wiremock::{
+        Match, Mock, MockServer, Request, ResponseTemplate,
+        matchers::{header, method, path},
+    };
+
+    const DIGEST_CHALLENGE: &str = r#"Digest qop="auth", realm="shellyplus1-a8032ab12345", nonce="60dc59c6", algorithm=SHA-256"#;
+
+    /// Accepts requests carrying a valid SHA-256 digest response for `nonce`.
+    struct DigestMatcher {
+        nonce: &'static str,
+        password: &'static str,
+    }
+
+    impl Match for DigestMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let Some(authorization) = request
+                .headers
+                .get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Digest "))
+            else {
+                return false;
+            };
+
+            let params: HashMap<&str, &str> = authorization
+                .split(", ")
+                .filter_map(|param| param.split_once('='))
+                .map(|(key, value)| (key, value.trim_matches('"')))
+                .collect();
+
+            let hash = |input: String| format!("{:x}", Sha256::digest(input.as_bytes()));
+            let ha1 = hash(format!(
+                "{}:{}:{}",
+                params["username"], params["realm"], self.password
+            ));
+            let ha2 = hash(format!("GET:{}", request.url.path()));
+            let expected = hash(format!(
+                "{ha1}:{}:{}:{}:auth:{ha2}",
+                self.nonce, params["nc"], params["cnonce"]
+            ));
+
+            params["nonce"] == self.nonce && params["response"] == expected
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_device_info() {
+        let mock_server = MockServer::start().await;
+
+        let device_info_response = r#"{
+            "name": "Test Shelly",
+            "id": "shelly1-123456",
+            "mac": "AA:BB:CC:DD:EE:FF",
+            "model": "SNSW-001X16EU",
+            "gen": 2,
+            "fw_id": "20230913-123456/v1.14.0",
+            "ver": "1.14.0",
+            "app": "S1",
+            "auth_en": false,
+            "auth_domain": null
+        }"#;
+
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetDeviceInfo"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(device_info_response))
+            .mount(&mock_server)
+            .await;
+
+        let client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen2,
+        )
+        .unwrap();
+
+        let info = client.get_device_info().await.unwrap();
+        assert_eq!(info.name, "Test Shelly");
+        assert_eq!(info.model, "SNSW-001X16EU");
+        assert_eq!(info.generation, 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_gen1_device_info() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/shelly"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"type": "SHSW-25", "mac": "A4CF12F45678", "auth": true,
+                    "fw": "20230913-112003/v1.14.0-gcb84623", "discoverable": false,
+                    "longid": 1, "num_outputs": 2, "num_meters": 2, "num_rollers": 1}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/settings"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"device": {"type": "SHSW-25", "mac": "A4CF12F45678",
+                    "hostname": "shellyswitch25-F45678", "num_outputs": 2},
+                    "name": "Garage", "fw": "20230913-112003/v1.14.0-gcb84623"}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen1,
+        )
+        .unwrap();
+
+        let info = client.get_device_info().await.unwrap();
+        assert_eq!(info.name, "Garage");
+        assert_eq!(info.id, "shellyswitch25-F45678");
+        assert_eq!(info.mac, "A4CF12F45678");
+        assert_eq!(info.model, "SHSW-25");
+        assert_eq!(info.generation, 1);
+        assert_eq!(info.fw_id, "20230913-112003/v1.14.0-gcb84623");
+        assert_eq!(info.ver, "1.14.0");
+        assert!(info.auth_en);
+    }
+
+    #[tokio::test]
+    async fn test_get_status() {
+        let mock_server = MockServer::start().await;
+
+        let status_response = r#"{
+            "switch:0": {
+                "id": 0,
+                "source": "manual",
+                "output": true,
+                "apower": 15.5,
+                "voltage": 230.1,
+                "current": 0.067,
+                "freq": 50.0,
+                "pf": 0.99,
+                "aenergy": {
+                    "total": 1234.567,
+                    "by_minute": [250.0, 251.0, 249.0],
+                    "minute_ts": 1234567890
+                },
+                "temperature": {
+                    "tC": 25.5,
+                    "tF": 77.9
+                }
+            },
+            "sys": {
+                "mac": "AA:BB:CC:DD:EE:FF",
+                "restart_required": false,
+                "time": "12:34:56",
+                "unixtime": 1234567890,
+                "uptime": 3600,
+                "ram_size": 262144,
+                "ram_free": 131072,
+                "fs_size": 524288,
+                "fs_free": 262144,
+                "cfg_rev": 10,
+                "available_updates": null
+            },
+            "wifi": {
+                "sta_ip": "192.168.1.100",
+                "status": "got ip",
+                "ssid": "TestNetwork",
+                "rssi": -65
+            }
+        }"#;
+
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetStatus"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(status_response))
+            .mount(&mock_server)
+            .await;
+
+        let client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen2,
+        )
+        .unwrap();
+
+        let status = client.get_status().await.unwrap();
+
+        match status {
+            ShellyStatus::Gen2(gen2_status) => {
+                let Some(Component::Switch(switch)) = gen2_status.components.get("switch:0") else {
+                    panic!("Expected switch:0 component");
+                };
+                assert!(switch.output);
+                assert_eq!(switch.apower, Some(15.5));
+                assert_eq!(switch.voltage, Some(230.1));
+
+                assert!(gen2_status.sys.is_some());
+                let sys = gen2_status.sys.unwrap();
+                assert_eq!(sys.uptime, Some(3600));
+
+                assert!(gen2_status.wifi.is_some());
+                let wifi = gen2_status.wifi.unwrap();
+                assert_eq!(wifi.sta_ip, Some("192.168.1.100".to_string()));
+                assert_eq!(wifi.rssi, Some(-65));
+            }
+            ShellyStatus::Gen1(_) => panic!("Expected Gen2 status"),
+        }
+    }
+
+    #[test]
+    fn test_gen2_component_map() {
+        let status: ShellyGen2Status = serde_json::from_str(
+            r#"{
+                "switch:0": {"id": 0, "output": false},
+                "switch:7": {"id": 7, "output": true, "apower": 4.2},
+                "cloud": {"connected": true},
+                "sys": null
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(status.components.len(), 3);
+        assert!(matches!(
+            status.components.get("switch:7"),
+            Some(Component::Switch(SwitchStatus {
+                id: 7,
+                output: true,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            status.components.get("cloud"),
+            Some(Component::Unknown(_))
+        ));
+        assert_eq!(split_component_key("switch:7"), ("switch", Some("7")));
+        assert_eq!(split_component_key("cloud"), ("cloud", None));
+
+        let malformed = serde_json::from_str::<ShellyGen2Status>(r#"{"switch:0": {"id": 0}}"#);
+        assert!(malformed.unwrap_err().to_string().contains("switch:0"));
+    }
+
+    #[test]
+    fn test_light_fixtures() {
+        // Gen1 Dimmer 2
+        let dimmer: ShellyGen1Status = serde_json::from_str(
+            r#"{
+                "lights": [{"ison": true, "source": "input", "has_timer": false, "timer_started": 0,
+                    "timer_duration": 0, "timer_remaining": 0, "mode": "white", "brightness": 42,
+                    "transition": 0}],
+                "meters": [{"power": 18.5, "overpower": 0.0, "is_valid": true,
+                    "timestamp": 1700000000, "counters": [1.1, 2.2, 3.3], "total": 5021}]
+            }"#,
+        )
+        .unwrap();
+        let lights = dimmer.lights.unwrap();
+        assert!(lights.get(0).unwrap().ison);
+        assert_eq!(lights.get(0).unwrap().brightness, Some(42.0));
+
+        // Gen1 Duo
+        let duo: ShellyGen1Status = serde_json::from_str(
+            r#"{"lights": [{"ison": false, "source": "http", "has_timer": false, "brightness": 80,
+                "white": 35, "temp": 3500, "transition": 0}]}"#,
+        )
+        .unwrap();
+        assert_eq!(duo.lights.unwrap().get(0).unwrap().temp, Some(3500.0));
+
+        // Gen1 RGBW2 / Bulb in color mode
+        let rgbw2: ShellyGen1Status = serde_json::from_str(
+            r#"{"lights": [{"ison": true, "source": "http", "has_timer": false, "mode": "color",
+                "red": 255, "green": 120, "blue": 0, "white": 10, "gain": 75, "effect": 0,
+                "transition": 500, "power": 6.2, "overpower": false}]}"#,
+        )
+        .unwrap();
+        let lights = rgbw2.lights.unwrap();
+        assert_eq!(lights.get(0).unwrap().mode.as_deref(), Some("color"));
+        assert_eq!(lights.get(0).unwrap().green, Some(120.0));
+        assert_eq!(lights.get(0).unwrap().gain, Some(75.0));
+
+        // Plus Wall Dimmer / Pro Dimmer
+        let pro_dimmer: ShellyGen2Status = serde_json::from_str(
+            r#"{
+                "light:0": {"id": 0, "source": "init", "output": true, "brightness": 55,
+                    "temperature": {"tC": 44.1, "tF": 111.4}, "aenergy": {"total": 803.2,
+                    "by_minute": [0.0, 0.0, 0.0], "minute_ts": 1700000000}, "apower": 21.4,
+                    "current": 0.1, "voltage": 229.7},
+                "light:1": {"id": 1, "source": "init", "output": false, "brightness": 100}
+            }"#,
+        )
+        .unwrap();
+        let Some(Component::Light(light)) = pro_dimmer.components.get("light:0") else {
+            panic!("Expected light:0 component");
+        };
+        assert_eq!(light.brightness, Some(55.0));
+        assert_eq!(light.apower, Some(21.4));
+        assert!(matches!(
+            pro_dimmer.components.get("light:1"),
+            Some(Component::Light(LightStatus { output: false, .. }))
+        ));
+
+        // Plus RGBW PM
+        let rgbw_pm: ShellyGen2Status = serde_json::from_str(
+            r#"{"rgbw:0": {"id": 0, "source": "WS_in", "output": true, "rgb": [255, 0, 64],
+                "brightness": 70, "white": 128, "temperature": {"tC": 38.3, "tF": 100.9},
+                "aenergy": {"total": 12.4, "by_minute": [0.0, 0.0, 0.0], "minute_ts": 1700000000},
+                "apower": 9.3, "current": 0.78, "voltage": 12.1}}"#,
+        )
+        .unwrap();
+        let Some(Component::Light(rgbw)) = rgbw_pm.components.get("rgbw:0") else {
+            panic!("Expected rgbw:0 component");
+        };
+        assert_eq!(rgbw.rgb, Some(vec![255.0, 0.0, 64.0]));
+        assert_eq!(rgbw.white, Some(128.0));
+    }
+
+    #[test]
+    fn test_input_fixtures() {
+        let i4: ShellyGen2Status = serde_json::from_str(
+            r#"{
+                "input:0": {"id": 0, "state": true},
+                "input:1": {"id": 1, "state": null},
+                "input:2": {"id": 2, "percent": 37.5, "xpercent": null},
+                "input:3": {"id": 3, "counts": {"total": 1502, "xtotal": null}, "freq": 0.5}
+            }"#,
+        )
+        .unwrap();
+
+        let input = |key| match i4.components.get(key) {
+            Some(Component::Input(input)) => input.clone(),
+            other => panic!("Expected input component, got {other:?}"),
+        };
+        assert_eq!(input("input:0").state, Some(true));
+        assert_eq!(input("input:1").state, None);
+        assert_eq!(input("input:2").percent, Some(37.5));
+        assert_eq!(input("input:3").counts.map(|c| c.total), Some(1502.0));
+
+        let gen1: ShellyGen1Status = serde_json::from_str(
+            r#"{"inputs": [{"input": 0, "event": "", "event_cnt": 0},
+                {"input": 1, "event": "L", "event_cnt": 12}]}"#,
+        )
+        .unwrap();
+        let inputs = gen1.inputs.unwrap();
+        assert_eq!(inputs.get(1).unwrap().input, 1);
+        assert_eq!(inputs.get(1).unwrap().event.as_deref(), Some("L"));
+        assert_eq!(inputs.get(1).unwrap().event_cnt, Some(12));
+    }
+
+    #[tokio::test]
+    async fn test_get_pro_3em_status() {
+        let mock_server = MockServer::start().await;
+
+        let status_response = r#"{
+            "em:0": {"id": 0, "a_current": 4.029, "a_voltage": 236.1, "a_act_power": 951.2,
+                "a_aprt_power": 951.9, "a_pf": 1, "a_freq": 50, "b_current": 4.027,
+                "b_voltage": 236.201, "b_act_power": -951.1, "b_aprt_power": 951.8, "b_pf": 1,
+                "b_freq": 50, "c_current": 3.03, "c_voltage": 236.402, "c_act_power": 715.4,
+                "c_aprt_power": 716.2, "c_pf": 1, "c_freq": 50, "n_current": null,
+                "total_current": 11.086, "total_act_power": 715.5, "total_aprt_power": 2619.9,
+                "user_calibrated_phase": [], "errors": ["phase_sequence"]},
+            "emdata:0": {"id": 0, "a_total_act_energy": 2776175.88, "a_total_act_ret_energy": 0,
+                "b_total_act_energy": 1.86, "b_total_act_ret_energy": 2776173.54,
+                "c_total_act_energy": 2085352.66, "c_total_act_ret_energy": 0,
+                "total_act": 4861530.41, "total_act_ret": 2776173.54},
+            "temperature:0": {"id": 0, "tC": 44.2, "tF": 111.6}
+        }"#;
+
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetStatus"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(status_response))
+            .mount(&mock_server)
+            .await;
+
+        let client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen2,
+        )
+        .unwrap();
+
+        let ShellyStatus::Gen2(status) = client.get_status().await.unwrap() else {
+            panic!("Expected Gen2 status");
+        };
+
+        let Some(Component::Em(em)) = status.components.get("em:0") else {
+            panic!("Expected em:0 component");
+        };
+        let phases = em.phases();
+        assert_eq!(phases[1].0, "b");
+        assert_eq!(phases[1].1.act_power, Some(-951.1));
+        assert_eq!(phases[3].1.current, Some(11.086));
+        assert_eq!(phases[3].1.voltage, None);
+        assert_eq!(em.errors, Some(vec!["phase_sequence".to_string()]));
+
+        // Phase c carries 3.03 A against an average of 3.695 A
+        let imbalance = em.current_imbalance().unwrap();
+        assert!((imbalance - 0.18).abs() < 0.001);
+
+        let Some(Component::EmData(emdata)) = status.components.get("emdata:0") else {
+            panic!("Expected emdata:0 component");
+        };
+        assert_eq!(emdata.phases()[1], ("b", Some(1.86), Some(2776173.54)));
+    }
+
+    #[tokio::test]
+    async fn test_get_pro_em_status() {
+        let mock_server = MockServer::start().await;
+
+        // Captured from a Pro EM-50 (firmware 1.3.3)
+        let status_response = r#"{
+            "ble": {},
+            "cloud": {"connected": true},
+            "em1:0": {"id": 0, "current": 2.381, "voltage": 231.6, "act_power": -512.3,
+                "aprt_power": 551.4, "pf": -0.93, "freq": 50.0, "calibration": "factory"},
+            "em1:1": {"id": 1, "current": 0.029, "voltage": 231.6, "act_power": 0.0,
+                "aprt_power": 6.6, "pf": 0.0, "freq": 50.0, "calibration": "factory"},
+            "em1data:0": {"id": 0, "total_act_energy": 182734.21, "total_act_ret_energy": 90211.07},
+            "em1data:1": {"id": 1, "total_act_energy": 0.0, "total_act_ret_energy": 0.0},
+            "switch:0": {"id": 0, "source": "init", "output": false}
+        }"#;
+
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetStatus"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(status_response))
+            .mount(&mock_server)
+            .await;
+
+        let client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen2,
+        )
+        .unwrap();
+
+        let ShellyStatus::Gen2(status) = client.get_status().await.unwrap() else {
+            panic!("Expected Gen2 status");
+        };
+
+        let Some(Component::Em1(em1)) = status.components.get("em1:0") else {
+            panic!("Expected em1:0 component");
+        };
+        assert_eq!(em1.act_power, Some(-512.3));
+        assert_eq!(em1.aprt_power, Some(551.4));
+        assert_eq!(em1.calibration.as_deref(), Some("factory"));
+
+        let Some(Component::Em1Data(em1data)) = status.components.get("em1data:0") else {
+            panic!("Expected em1data:0 component");
+        };
+        assert_eq!(em1data.total_act_energy, Some(182734.21));
+        assert_eq!(em1data.total_act_ret_energy, Some(90211.07));
+    }
+
+    #[tokio::test]
+    async fn test_get_plus_pm_mini_status() {
+        let mock_server = MockServer::start().await;
+
+        // Captured from a Plus PM Mini Gen3 (firmware 1.2.2)
+        let status_response = r#"{
+            "pm1:0": {"id": 0, "voltage": 229.1, "current": 0.451, "apower": 97.6, "freq": 50.0,
+                "aenergy": {"total": 5963.174, "by_minute": [1627.418, 1624.117, 1627.418],
+                    "minute_ts": 1712233620},
+                "ret_aenergy": {"total": 12.5, "by_minute": [0.0, 0.0, 0.0],
+                    "minute_ts": 1712233620}},
+            "sys": {"mac": "543204A1B2C3", "restart_required": false, "time": "14:27",
+                "unixtime": 1712233637, "uptime": 912331, "ram_size": 260420,
+                "ram_free": 147284, "fs_size": 1048576, "fs_free": 720896, "cfg_rev": 12,
+                "kvs_rev": 0, "schedule_rev": 0, "webhook_rev": 0,
+                "available_updates": {}, "reset_reason": 3},
+            "wifi": {"sta_ip": "192.168.1.57", "status": "got ip", "ssid": "TestNetwork",
+                "rssi": -58}
+        }"#;
+
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetStatus"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(status_response))
+            .mount(&mock_server)
+            .await;
+
+        let client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen3,
+        )
+        .unwrap();
+
+        let ShellyStatus::Gen2(status) = client.get_status().await.unwrap() else {
+            panic!("Expected Gen2 status");
+        };
+
+        let Some(Component::Pm1(pm1)) = status.components.get("pm1:0") else {
+            panic!("Expected pm1:0 component");
+        };
+        assert_eq!(pm1.apower, Some(97.6));
+        assert_eq!(pm1.aenergy.as_ref().map(|e| e.total), Some(5963.174));
+        assert_eq!(pm1.ret_aenergy.as_ref().map(|e| e.total), Some(12.5));
+        assert_eq!(status.sys.unwrap().uptime, Some(912331));
+    }
+
+    #[tokio::test]
+    async fn test_authentication() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetDeviceInfo"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&mock_server)
+            .await;
+
+        let client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen2,
+        )
+        .unwrap();
+
+        let result = client.get_device_info().await;
+        assert!(matches!(result, Err(ShellyError::Unauthorized(_))));
+        assert!(result.unwrap_err().to_string().contains("HTTP 401"));
+    }
+
+    #[tokio::test]
+    async fn test_get_gen1_status() {
+        let mock_server = MockServer::start().await;
+
+        let status_response = r#"{
+            "relays": [{
+                "ison": true,
+                "has_timer": false,
+                "timer_started": 0,
+                "timer_duration": 0,
+                "timer_remaining": 0,
+                "source": "input"
+            }],
+            "meters": [{
+                "power": 23.45,
+                "is_valid": true,
+                "timestamp": 1234567890,
+                "counters": [1234.56, 0.0, 0.0],
+                "total": 1234.56
+            }],
+            "temperature": 25.5,
+            "overtemperature": false,
+            "wifi_sta": {
+                "connected": true,
+                "ssid": "TestNetwork",
+                "ip": "192.168.1.101",
+                "rssi": -60
+            },
+            "uptime": 7200
+        }"#;
+
+        Mock::given(method("GET"))
+            .and(path("/status"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(status_response))
+            .mount(&mock_server)
+            .await;
+
+        let client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen1,
+        )
+        .unwrap();
+
+        let status = client.get_status().await.unwrap();
+
+        match status {
+            ShellyStatus::Gen1(gen1_status) => {
+                assert!(gen1_status.relays.is_some());
+                let relays = gen1_status.relays.unwrap();
+                assert_eq!(relays.0.len(), 1);
+                assert!(relays.get(0).unwrap().ison);
+
+                assert!(gen1_status.meters.is_some());
+                let meters = gen1_status.meters.unwrap();
+                assert_eq!(meters.0.len(), 1);
+                assert_eq!(meters.get(0).unwrap().power, 23.45);
+                assert_eq!(meters.get(0).unwrap().total, Some(1234.56));
+
+                assert_eq!(gen1_status.temperature, Some(25.5));
+                assert_eq!(gen1_status.uptime, Some(7200));
+
+                assert!(gen1_status.wifi_sta.is_some());
+                let wifi = gen1_status.wifi_sta.unwrap();
+                assert_eq!(wifi.connected, Some(true));
+                assert_eq!(wifi.ip, Some("192.168.1.101".to_string()));
+                assert_eq!(wifi.rssi, Some(-60));
+            }
+            ShellyStatus::Gen2(_) => panic!("Expected Gen1 status"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_gen1_emeter_status() {
+        let mock_server = MockServer::start().await;
+
+        // Shelly 3EM
+        let status_response = r#"{
+            "relays": [{"ison": false, "has_timer": false, "timer_started": 0,
+                "timer_duration": 0, "timer_remaining": 0, "overpower": false,
+                "is_valid": true, "source": "input"}],
+            "emeters": [
+                {"power": 1216.42, "pf": 0.98, "current": 5.31, "voltage": 233.65,
+                    "is_valid": true, "total": 2458101.3, "total_returned": 18.2},
+                {"power": -245.06, "pf": -0.61, "current": 1.72, "voltage": 232.9,
+                    "is_valid": true, "total": 812034.7, "total_returned": 151120.4},
+                {"power": 0.0, "pf": 0.0, "current": 0.0, "voltage": 233.1,
+                    "is_valid": true, "total": 300.0, "total_returned": 0.0}
+            ],
+            "total_power": 971.36,
+            "uptime": 5500
+        }"#;
+
+        Mock::given(method("GET"))
+            .and(path("/status"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(status_response))
+            .mount(&mock_server)
+            .await;
+
+        let client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen1,
+        )
+        .unwrap();
+
+        let ShellyStatus::Gen1(status) = client.get_status().await.unwrap() else {
+            panic!("Expected Gen1 status");
+        };
+
+        let emeters = status.emeters.unwrap();
+        assert_eq!(emeters.0.len(), 3);
+        assert_eq!(emeters.get(1).unwrap().power, -245.06);
+        assert_eq!(emeters.get(1).unwrap().total_returned, Some(151120.4));
+        assert_eq!(emeters.get(0).unwrap().reactive, None);
+
+        // Shelly EM reports reactive power instead of current and power factor
+        let em: ShellyGen1Status = serde_json::from_str(
+            r#"{"emeters": [{"power": 412.3, "reactive": -88.1, "voltage": 229.4,
+                "is_valid": true, "total": 73311.2, "total_returned": 0.0}]}"#,
+        )
+        .unwrap();
+        assert_eq!(em.emeters.unwrap().get(0).unwrap().reactive, Some(-88.1));
+    }
+
+    #[test]
+    fn test_battery_sensor_fixtures() {
+        // Gen1 H&T
+        let ht: ShellyGen1Status = serde_json::from_str(
+            r#"{
+                "tmp": {"value": 71.6, "units": "F", "tC": 22.0, "tF": 71.6, "is_valid": true},
+                "hum": {"value": 48.5, "is_valid": true},
+                "bat": {"value": 87, "voltage": 2.91},
+                "act_reasons": ["sensor"],
+                "connect_retries": 0,
+                "sensor_error": 0
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(ht.tmp.unwrap().celsius(), Some(22.0));
+        assert_eq!(ht.hum.unwrap().valid_value(), Some(48.5));
+        assert_eq!(ht.bat.unwrap().voltage, Some(2.91));
+
+        // Gen1 Flood without tC/tF
+        let flood: ShellyGen1Status = serde_json::from_str(
+            r#"{"flood": true, "tmp": {"value": 68.0, "units": "F", "is_valid": true},
+                "bat": {"value": 100, "voltage": 3.01}, "charger": false}"#,
+        )
+        .unwrap();
+        assert_eq!(flood.flood, Some(true));
+        assert_eq!(flood.tmp.unwrap().celsius(), Some(20.0));
+
+        // Gen1 Door/Window 2
+        let dw: ShellyGen1Status = serde_json::from_str(
+            r#"{"sensor": {"state": "open", "is_valid": true},
+                "lux": {"value": 231, "illumination": "twilight", "is_valid": true},
+                "accel": {"tilt": 86, "vibration": 0},
+                "tmp": {"value": 21.3, "units": "C", "tC": 21.3, "tF": 70.3, "is_valid": true},
+                "bat": {"value": 62, "voltage": 5.52}, "charger": true}"#,
+        )
+        .unwrap();
+        assert_eq!(dw.sensor.unwrap().state.as_deref(), Some("open"));
+        assert_eq!(dw.lux.unwrap().valid_value(), Some(231.0));
+        assert_eq!(dw.accel.unwrap().tilt, Some(86.0));
+        assert_eq!(dw.charger, Some(true));
+
+        // Plus H&T and Plus Smoke
+        let plus: ShellyGen2Status = serde_json::from_str(
+            r#"{
+                "devicepower:0": {"id": 0, "battery": {"V": 5.62, "percent": 76},
+                    "external": {"present": false}},
+                "humidity:0": {"id": 0, "rh": 52.3},
+                "temperature:0": {"id": 0, "tC": 19.4, "tF": 66.9},
+                "smoke:0": {"id": 0, "alarm": false, "mute": false}
+            }"#,
+        )
+        .unwrap();
+        let Some(Component::DevicePower(power)) = plus.components.get("devicepower:0") else {
+            panic!("Expected devicepower:0 component");
+        };
+        assert_eq!(power.battery.as_ref().unwrap().voltage, Some(5.62));
+        assert!(!power.external.as_ref().unwrap().present);
+        assert!(matches!(
+            plus.components.get("humidity:0"),
+            Some(Component::Humidity(HumidityStatus { rh: Some(52.3), .. }))
+        ));
+        assert!(matches!(
+            plus.components.get("smoke:0"),
+            Some(Component::Smoke(AlarmStatus { alarm: false, .. }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_gen1_addon_status() {
+        let mock_server = MockServer::start().await;
+
+        // Shelly 1PM with the temperature add-on and three DS18B20 probes
+        let status_response = r#"{
+            "relays": [{"ison": true, "has_timer": false, "timer_started": 0,
+                "timer_duration": 0, "timer_remaining": 0, "overpower": false,
+                "source": "http"}],
+            "ext_sensors": {"temperature_unit": "C"},
+            "ext_temperature": {
+                "0": {"hwID": "28e3b79e0b000058", "tC": 58.4, "tF": 137.1},
+                "1": {"hwID": "2893f79f0b0000d2", "tC": 41.9, "tF": 107.4},
+                "2": {"hwID": "28a07a9f0b00004c", "tC": -18.6, "tF": -1.5}
+            },
+            "ext_humidity": {},
+            "ext_switch": {"0": {"input": 1}},
+            "uptime": 86400
+        }"#;
+
+        Mock::given(method("GET"))
+            .and(path("/status"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(status_response))
+            .mount(&mock_server)
+            .await;
+
+        let client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen1,
+        )
+        .unwrap();
+
+        let ShellyStatus::Gen1(status) = client.get_status().await.unwrap() else {
+            panic!("Expected Gen1 status");
+        };
+
+        let probes = status.ext_temperature.unwrap();
+        assert_eq!(probes.len(), 3);
+        assert_eq!(probes["2"].t_c, Some(-18.6));
+        assert_eq!(probes["0"].hw_id.as_deref(), Some("28e3b79e0b000058"));
+        assert!(status.ext_humidity.unwrap().is_empty());
+        assert_eq!(status.ext_switch.unwrap()["0"].input, 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_addon_peripherals() {
+        let mock_server = MockServer::start().await;
+
+        // Plus 1 with the Plus Add-on: DS18B20 probes, DHT22, voltmeter and a
+        // pulse counting input
+        let status_response = r#"{
+            "switch:0": {"id": 0, "source": "init", "output": false, "temperature": {"tC": 40.1, "tF": 104.2}},
+            "temperature:100": {"id": 100, "tC": 21.4, "tF": 70.5},
+            "temperature:101": {"id": 101, "tC": null, "tF": null, "errors": ["read"]},
+            "humidity:100": {"id": 100, "rh": 48.7},
+            "voltmeter:100": {"id": 100, "voltage": 4.12, "xvoltage": 41.2},
+            "input:100": {"id": 100, "counts": {"total": 1234, "xtotal": 1.234}, "freq": 0.5},
+            "illuminance:0": {"id": 0, "lux": 270, "illumination": "twilight"}
+        }"#;
+
+        let config_response = r#"{
+            "sys": {"device": {"name": "Boiler room"}},
+            "switch:0": {"id": 0, "name": null, "in_mode": "follow"},
+            "temperature:100": {"id": 100, "name": "Tank top", "report_thr_C": 0.5},
+            "input:100": {"id": 100, "name": "Water meter", "type": "count"}
+        }"#;
+
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetStatus"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(status_response))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetConfig"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(config_response))
+            .mount(&mock_server)
+            .await;
+
+        let client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen2,
+        )
+        .unwrap();
+
+        let ShellyStatus::Gen2(status) = client.get_status().await.unwrap() else {
+            panic!("Expected Gen2 status");
+        };
+
+        assert!(matches!(
+            &status.components["temperature:101"],
+            Component::Temperature(t) if t.t_c.is_none()
+        ));
+        assert!(matches!(
+            &status.components["voltmeter:100"],
+            Component::Voltmeter(v) if v.voltage == Some(4.12)
+        ));
+        assert!(matches!(
+            &status.components["input:100"],
+            Component::Input(i) if i.counts.as_ref().map(|c| c.total) == Some(1234.0)
+        ));
+        assert!(matches!(
+            &status.components["illuminance:0"],
+            Component::Illuminance(l) if l.lux == Some(270.0)
+        ));
+
+        let names = client.get_component_names().await.unwrap();
+        assert_eq!(names.len(), 2);
+        assert_eq!(names["temperature:100"], "Tank top");
+        assert_eq!(names["input:100"], "Water meter");
+    }
+
+    #[tokio::test]
+    async fn test_get_bthome_status() {
+        let mock_server = MockServer::start().await;
+
+        // Plus 1PM gateway with a paired BLU H&T
+        let status_response = r#"{
+            "switch:0": {"id": 0, "source": "init", "output": true},
+            "bthomedevice:200": {"id": 200, "rssi": -71, "battery": 98, "packet_id": 35,
+                "last_updated_ts": 1729150000, "paired": true, "rpc": false, "rsv": 0},
+            "bthomesensor:200": {"id": 200, "value": 21.8, "last_updated_ts": 1729150000},
+            "bthomesensor:201": {"id": 201, "value": 54, "last_updated_ts": 1729150000},
+            "bthomesensor:202": {"id": 202, "value": false, "last_updated_ts": 1729150000}
+        }"#;
+
+        let config_response = r#"{
+            "switch:0": {"id": 0, "name": null},
+            "bthomedevice:200": {"id": 200, "addr": "7c:c6:b6:61:e8:1a", "name": "Bedroom H&T", "key": null},
+            "bthomesensor:200": {"id": 200, "addr": "7c:c6:b6:61:e8:1a", "name": null, "obj_id": 69, "idx": 0},
+            "bthomesensor:201": {"id": 201, "addr": "7c:c6:b6:61:e8:1a", "name": null, "obj_id": 46, "idx": 0}
+        }"#;
+
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetStatus"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(status_response))
+            .mount(&mock_server)
+            .await;
+
+        // bthomesensor:202 has no config, but that must not trigger a refresh
+        // on every poll
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetConfig"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(config_response))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen2,
+        )
+        .unwrap();
+
+        let ShellyStatus::Gen2(status) = client.get_status().await.unwrap() else {
+            panic!("Expected Gen2 status");
+        };
+
+        assert!(matches!(
+            &status.components["bthomedevice:200"],
+            Component::BthomeDevice(d) if d.battery == Some(98.0) && d.rssi == Some(-71)
+        ));
+        let Component::BthomeSensor(window) = &status.components["bthomesensor:202"] else {
+            panic!("Expected BTHome sensor");
+        };
+        assert_eq!(window.numeric_value(), Some(0.0));
+
+        let device = &status.bthome_config["bthomedevice:200"];
+        assert_eq!(device.addr, "7c:c6:b6:61:e8:1a");
+        assert_eq!(device.name.as_deref(), Some("Bedroom H&T"));
+        let sensor = &status.bthome_config["bthomesensor:201"];
+        assert_eq!(sensor.obj_id, Some(46));
+        assert_eq!(bthome_object_name(46), "humidity");
+        assert!(!status.bthome_config.contains_key("bthomesensor:202"));
+
+        client.get_status().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bthome_config_refreshed_after_repairing() {
+        let mock_server = MockServer::start().await;
+
+        let paired = r#"{"bthomedevice:200": {"id": 200, "rssi": -71, "battery": 98}}"#;
+        let config = |addr: &str, name: &str| {
+            format!(r#"{{"bthomedevice:200": {{"id": 200, "addr": "{addr}", "name": "{name}"}}}}"#)
+        };
+
+        // The BLU device is removed, and another one paired under the same key
+        for (status, times) in [(paired, 1), ("{}", 1), (paired, 2)] {
+            Mock::given(method("GET"))
+                .and(path("/rpc/Shelly.GetStatus"))
+                .respond_with(ResponseTemplate::new(200).set_body_string(status))
+                .up_to_n_times(times)
+                .mount(&mock_server)
+                .await;
+        }
+        for config in [
+            config("7c:c6:b6:61:e8:1a", "Bedroom H&T"),
+            "{}".to_string(),
+            config("38:39:8f:70:b2:4c", "Kitchen H&T"),
+        ] {
+            Mock::given(method("GET"))
+                .and(path("/rpc/Shelly.GetConfig"))
+                .respond_with(ResponseTemplate::new(200).set_body_string(config))
+                .up_to_n_times(1)
+                .expect(1)
+                .mount(&mock_server)
+                .await;
+        }
+
+        let client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen2,
+        )
+        .unwrap();
+
+        let mut names = Vec::new();
+        for _ in 0..4 {
+            let ShellyStatus::Gen2(status) = client.get_status().await.unwrap() else {
+                panic!("Expected Gen2 status");
+            };
+            names.push(
+                status
+                    .bthome_config
+                    .get("bthomedevice:200")
+                    .map(|config| (config.addr.clone(), config.name.clone().unwrap())),
+            );
+        }
+
+        let kitchen = Some(("38:39:8f:70:b2:4c".to_string(), "Kitchen H&T".to_string()));
+        assert_eq!(
+            names,
+            [
+                Some(("7c:c6:b6:61:e8:1a".to_string(), "Bedroom H&T".to_string())),
+                None,
+                kitchen.clone(),
+                kitchen,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_trv_status() {
+        let mock_server = MockServer::start().await;
+
+        let status_response = r#"{
+            "thermostats": [{
+                "pos": 44.5,
+                "target_t": {"enabled": true, "value": 21.0, "value_op": 8.0, "units": "C"},
+                "tmp": {"value": 19.4, "units": "C", "is_valid": true},
+                "schedule": true,
+                "schedule_profile": 1,
+                "boost_minutes": 0,
+                "window_open": false
+            }],
+            "bat": {"value": 79, "voltage": 3.652},
+            "charger": false,
+            "uptime": 9000
+        }"#;
+
+        Mock::given(method("GET"))
+            .and(path("/status"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(status_response))
+            .mount(&mock_server)
+            .await;
+
+        let client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen1,
+        )
+        .unwrap();
+
+        let ShellyStatus::Gen1(status) = client.get_status().await.unwrap() else {
+            panic!("Expected Gen1 status");
+        };
+
+        let thermostats = status.thermostats.unwrap();
+        let thermostat = thermostats.get(0).unwrap();
+        assert_eq!(thermostat.pos, Some(44.5));
+        assert_eq!(thermostat.target_t.as_ref().unwrap().celsius(), Some(21.0));
+        assert_eq!(thermostat.tmp.as_ref().unwrap().celsius(), Some(19.4));
+        assert_eq!(thermostat.window_open, Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_get_motion_and_gas_status() {
+        let mock_server = MockServer::start().await;
+
+        let motion_response = r#"{
+            "sensor": {"motion": true, "vibration": false, "timestamp": 1729150000,
+                "active": true, "is_valid": true},
+            "lux": {"value": 87, "illumination": "twilight", "is_valid": true},
+            "bat": {"value": 93, "voltage": 4.11, "charging": false}
+        }"#;
+
+        let gas_response = r#"{
+            "gas_sensor": {"sensor_state": "normal", "self_test_state": "completed",
+                "alarm_state": "mild"},
+            "concentration": {"ppm": 182, "is_valid": true},
+            "valves": [{"state": "not_connected"}]
+        }"#;
+
+        Mock::given(method("GET"))
+            .and(path("/motion/status"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(motion_response))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/gas/status"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(gas_response))
+            .mount(&mock_server)
+            .await;
+
+        let motion = ShellyClient::new(
+            format!("{}/motion", mock_server.uri()),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen1,
+        )
+        .unwrap();
+
+        let ShellyStatus::Gen1(status) = motion.get_status().await.unwrap() else {
+            panic!("Expected Gen1 status");
+        };
+        let sensor = status.sensor.unwrap();
+        assert_eq!(sensor.motion, Some(true));
+        assert_eq!(sensor.vibration, Some(false));
+        assert_eq!(status.lux.unwrap().valid_value(), Some(87.0));
+
+        let gas = ShellyClient::new(
+            format!("{}/gas", mock_server.uri()),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen1,
+        )
+        .unwrap();
+
+        let ShellyStatus::Gen1(status) = gas.get_status().await.unwrap() else {
+            panic!("Expected Gen1 status");
+        };
+        let gas_sensor = status.gas_sensor.unwrap();
+        assert_eq!(gas_sensor.alarm_state.as_deref(), Some("mild"));
+        assert_eq!(gas_sensor.self_test_state.as_deref(), Some("completed"));
+        assert_eq!(status.concentration.unwrap().ppm, Some(182.0));
+    }
+
+    #[tokio::test]
+    async fn test_error_classification() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetStatus"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetDeviceInfo"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"name": 42}"#))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/status"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("{}")
+                    .set_delay(Duration::from_millis(500)),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen2,
+        )
+        .unwrap();
+
+        let error = client.get_status().await.unwrap_err();
+        assert!(matches!(
+            error,
+            ShellyError::Http(StatusCode::INTERNAL_SERVER_ERROR)
+        ));
+        assert_eq!(error.reason(), "http_status");
+
+        let error = client.get_device_info().await.unwrap_err();
+        assert_eq!(error.reason(), "parse");
+
+        let slow = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_millis(50),
+            None,
+            ShellyGeneration::Gen1,
+        )
+        .unwrap();
+        assert_eq!(slow.get_status().await.unwrap_err().reason(), "timeout");
+
+        // Take a free port and close it again, so that nothing listens on it
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let refused = ShellyClient::new(
+            format!("http://{address}"),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen1,
+        )
+        .unwrap();
+        assert_eq!(
+            refused.get_status().await.unwrap_err().reason(),
+            "connection_refused"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_partial_status_parsing() {
+        let mock_server = MockServer::start().await;
+
+        // `switch:1` lacks its output and `wifi` has a string rssi; both are
+        // dropped while the rest of the status survives
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetStatus"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{
+                    "sys": {"uptime": 120, "ram_free": 100000},
+                    "wifi": {"sta_ip": "192.168.1.50", "status": "got ip", "rssi": "weak"},
+                    "switch:0": {"id": 0, "output": true, "apower": 12.5},
+                    "switch:1": {"id": 1, "apower": "n/a"}
+                }"#,
+            ))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/gen1/status"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{
+                    "relays": [{"ison": true}, {"ison": "yes"}, {"ison": false}],
+                    "meters": [{"power": 4.2}],
+                    "wifi_sta": {"connected": true, "ssid": "Home"},
+                    "uptime": 300
+                }"#,
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen2,
+        )
+        .unwrap();
+
+        let ShellyStatus::Gen2(status) = client.get_status().await.unwrap() else {
+            panic!("Expected Gen2 status");
+        };
+        assert_eq!(status.sys.as_ref().unwrap().uptime, Some(120));
+        assert!(status.sys.as_ref().unwrap().mac.is_none());
+        assert!(status.wifi.is_none());
+        assert!(status.components.contains_key("switch:0"));
+        assert!(!status.components.contains_key("switch:1"));
+        assert_eq!(status.parse_errors, vec!["switch:1", "wifi"]);
+
+        let client = ShellyClient::new(
+            format!("{}/gen1", mock_server.uri()),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen1,
+        )
+        .unwrap();
+
+        let ShellyStatus::Gen1(status) = client.get_status().await.unwrap() else {
+            panic!("Expected Gen1 status");
+        };
+        // Only the malformed relay is lost, the others keep their channel
+        let relays = status.relays.as_ref().unwrap();
+        assert!(relays.get(0).unwrap().ison);
+        assert!(relays.get(1).is_none());
+        assert!(!relays.get(2).unwrap().ison);
+        assert_eq!(status.meters.as_ref().unwrap().get(0).unwrap().power, 4.2);
+        assert_eq!(status.wifi_sta.as_ref().unwrap().rssi, None);
+        assert_eq!(status.uptime, Some(300));
+        assert_eq!(status.parse_errors, vec!["relays[1]"]);
+    }
+
+    #[tokio::test]
+    async fn test_detect_generation() {
+        let mock_server = MockServer::start().await;
+
+        // Mock Gen2 device
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetDeviceInfo"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+
+        let generation =
+            ShellyClient::detect_generation(&mock_server.uri(), Duration::from_secs(5), None)
+                .await
+                .unwrap();
+
+        assert_eq!(generation, ShellyGeneration::Gen2);
     }
 
-    async fn get_gen2_status(&self) -> Result<ShellyStatus> {
-        let url = format!("{}/rpc/Shelly.GetStatus", self.base_url);
-        debug!("Fetching Gen2 status from: {}", url);
+    #[tokio::test]
+    async fn test_detect_gen1_generation() {
+        let mock_server = MockServer::start().await;
 
-        let mut request = self.client.get(&url);
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetDeviceInfo"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
 
-        if let Some((username, password)) = &self.auth {
-            request = request.basic_auth(username, Some(password));
-        }
+        // `/settings` is protected, `/shelly` never is
+        Mock::given(method("GET"))
+            .and(path("/settings"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&mock_server)
+            .await;
 
-        let response = request
-            .send()
-            .await
-            .map_err(|e| anyhow!("Failed to fetch Gen2 status: {}", e))?;
+        Mock::given(method("GET"))
+            .and(path("/shelly"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"type": "SHPLG-S", "mac": "C45BBE123456", "auth": true,
+                    "fw": "20230913-113421/v1.14.0-gcb84623", "num_outputs": 1,
+                    "num_meters": 1}"#,
+            ))
+            .mount(&mock_server)
+            .await;
 
-        if !response.status().is_success() {
-            return Err(anyhow!(
-                "Failed to fetch Gen2 status: HTTP {}",
-                response.status()
-            ));
-        }
+        let generation =
+            ShellyClient::detect_generation(&mock_server.uri(), Duration::from_secs(5), None)
+                .await
+                .unwrap();
+        assert_eq!(generation, ShellyGeneration::Gen1);
 
-        let status = response
-            .json::<ShellyGen2Status>()
-            .await
-            .map_err(|e| anyhow!("Failed to parse Gen2 status: {}", e))?;
+        let client =
+            ShellyClient::new(mock_server.uri(), Duration::from_secs(5), None, generation).unwrap();
+        let info = client.get_device_info().await.unwrap();
+        assert_eq!(info.model, "SHPLG-S");
+        assert_eq!(info.id, "");
 
-        debug!("Gen2 status fetched successfully");
-        Ok(ShellyStatus::Gen2(Box::new(status)))
+        assert_eq!(Gen1Family::from_model("SHPLG-S"), Some(Gen1Family::Relay));
+        assert_eq!(Gen1Family::from_model("SHDM-2"), Some(Gen1Family::Light));
+        assert_eq!(Gen1Family::from_model("SHEM-3"), Some(Gen1Family::Other));
+        assert_eq!(Gen1Family::from_model("Unknown"), None);
     }
 
-    async fn get_gen1_status(&self) -> Result<ShellyStatus> {
-        let url = format!("{}/status", self.base_url);
-        debug!("Fetching Gen1 status from: {}", url);
-
-        let mut request = self.client.get(&url);
-
-        if let Some((username, password)) = &self.auth {
-            request = request.basic_auth(username, Some(password));
-        }
+    #[tokio::test]
+    async fn test_detect_gen1_with_sparse_shelly() {
+        let mock_server = MockServer::start().await;
 
-        let response = request
-            .send()
-            .await
-            .map_err(|e| anyhow!("Failed to fetch Gen1 status: {}", e))?;
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetDeviceInfo"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
 
-        if !response.status().is_success() {
-            return Err(anyhow!(
-                "Failed to fetch Gen1 status: HTTP {}",
-                response.status()
-            ));
-        }
+        // Old firmware leaves out fields such as `auth` and `fw`
+        Mock::given(method("GET"))
+            .and(path("/shelly"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"type": "SHSW-1", "mac": "5CCF7F123456"}"#),
+            )
+            .mount(&mock_server)
+            .await;
 
-        let status = response
-            .json::<ShellyGen1Status>()
-            .await
-            .map_err(|e| anyhow!("Failed to parse Gen1 status: {}", e))?;
+        let generation =
+            ShellyClient::detect_generation(&mock_server.uri(), Duration::from_secs(5), None)
+                .await
+                .unwrap();
+        assert_eq!(generation, ShellyGeneration::Gen1);
 
-        debug!("Gen1 status fetched successfully");
-        Ok(ShellyStatus::Gen1(Box::new(status)))
+        let client =
+            ShellyClient::new(mock_server.uri(), Duration::from_secs(5), None, generation).unwrap();
+        let info = client.get_device_info().await.unwrap();
+        assert_eq!(info.model, "SHSW-1");
+        assert!(!info.auth_en);
     }
 
-    pub async fn discover_devices(_timeout: Duration) -> Result<Vec<String>> {
-        info!("Starting mDNS discovery for Shelly devices...");
-        let devices = Vec::new();
+    #[tokio::test]
+    async fn test_detect_gen2_from_shelly_when_rpc_unauthorized() {
+        let mock_server = MockServer::start().await;
 
-        // Note: mDNS discovery would be implemented here
-        // For now, we'll return an empty list and rely on manually configured devices
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetDeviceInfo"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&mock_server)
+            .await;
 
-        Ok(devices)
+        Mock::given(method("GET"))
+            .and(path("/shelly"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"id": "shellyplus1pm-a8032ab12345", "mac": "A8032AB12345", "model": "SNSW-001P16EU", "gen": 2, "auth_en": true}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let generation =
+            ShellyClient::detect_generation(&mock_server.uri(), Duration::from_secs(5), None)
+                .await
+                .unwrap();
+        assert_eq!(generation, ShellyGeneration::Gen2);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use wiremock::{
-        Mock, MockServer, ResponseTemplate,
-        matchers::{method, path},
-    };
+    #[tokio::test]
+    async fn test_detect_newer_generations() {
+        for (gen_field, expected) in [(3, ShellyGeneration::Gen3), (4, ShellyGeneration::Gen4)] {
+            let mock_server = MockServer::start().await;
+
+            Mock::given(method("GET"))
+                .and(path("/rpc/Shelly.GetDeviceInfo"))
+                .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+                    r#"{{"id": "shelly1g{gen_field}-aabbccddeeff", "gen": {gen_field}}}"#
+                )))
+                .mount(&mock_server)
+                .await;
+
+            let generation =
+                ShellyClient::detect_generation(&mock_server.uri(), Duration::from_secs(5), None)
+                    .await
+                    .unwrap();
+
+            assert_eq!(generation, expected);
+            assert!(generation.is_rpc());
+            assert_eq!(generation.label(), format!("gen{gen_field}"));
+        }
+    }
 
     #[tokio::test]
-    async fn test_get_device_info() {
+    async fn test_digest_authentication() {
         let mock_server = MockServer::start().await;
 
-        let device_info_response = r#"{
-            "name": "Test Shelly",
-            "id": "shelly1-123456",
-            "mac": "AA:BB:CC:DD:EE:FF",
-            "model": "SNSW-001X16EU",
-            "gen": 2,
-            "fw_id": "20230913-123456/v1.14.0",
-            "ver": "1.14.0",
-            "app": "S1",
-            "auth_en": false,
-            "auth_domain": null
-        }"#;
-
         Mock::given(method("GET"))
             .and(path("/rpc/Shelly.GetDeviceInfo"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(device_info_response))
+            .and(DigestMatcher {
+                nonce: "60dc59c6",
+                password: "secret",
+            })
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{
+                    "name": "Test Shelly",
+                    "id": "shellyplus1-a8032ab12345",
+                    "mac": "A8032AB12345",
+                    "model": "SNSW-001X16EU",
+                    "gen": 2,
+                    "fw_id": "20230913-123456/v1.14.0",
+                    "ver": "1.14.0",
+                    "app": "Plus1",
+                    "auth_en": true,
+                    "auth_domain": "shellyplus1-a8032ab12345"
+                }"#,
+            ))
+            .with_priority(1)
+            .mount(&mock_server)
+            .await;
+
+        // Only the first request should need the challenge; later ones reuse the nonce
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(401).insert_header("WWW-Authenticate", DIGEST_CHALLENGE),
+            )
+            .expect(1)
             .mount(&mock_server)
             .await;
 
         let client = ShellyClient::new(
             mock_server.uri(),
             Duration::from_secs(5),
-            None,
+            Some(("admin".to_string(), "secret".to_string())),
             ShellyGeneration::Gen2,
         )
         .unwrap();
 
         let info = client.get_device_info().await.unwrap();
-        assert_eq!(info.name, "Test Shelly");
-        assert_eq!(info.model, "SNSW-001X16EU");
-        assert_eq!(info.generation, 2);
+        assert_eq!(info.id, "shellyplus1-a8032ab12345");
+        assert!(info.auth_en);
+
+        client.get_device_info().await.unwrap();
     }
 
     #[tokio::test]
-    async fn test_get_status() {
+    async fn test_digest_stale_nonce_retry() {
         let mock_server = MockServer::start().await;
 
-        let status_response = r#"{
-            "switch:0": {
-                "id": 0,
-                "source": "manual",
-                "output": true,
-                "apower": 15.5,
-                "voltage": 230.1,
-                "current": 0.067,
-                "freq": 50.0,
-                "pf": 0.99,
-                "aenergy": {
-                    "total": 1234.567,
-                    "by_minute": [250.0, 251.0, 249.0],
-                    "minute_ts": 1234567890
-                },
-                "temperature": {
-                    "tC": 25.5,
-                    "tF": 77.9
-                }
-            },
-            "sys": {
-                "mac": "AA:BB:CC:DD:EE:FF",
-                "restart_required": false,
-                "time": "12:34:56",
-                "unixtime": 1234567890,
-                "uptime": 3600,
-                "ram_size": 262144,
-                "ram_free": 131072,
-                "fs_size": 524288,
-                "fs_free": 262144,
-                "cfg_rev": 10,
-                "available_updates": null
-            },
-            "wifi": {
-                "sta_ip": "192.168.1.100",
-                "status": "got ip",
-                "ssid": "TestNetwork",
-                "rssi": -65
-            }
-        }"#;
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetDeviceInfo"))
+            .and(DigestMatcher {
+                nonce: "60dc59c6",
+                password: "secret",
+            })
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{
+                    "name": "Test Shelly",
+                    "id": "shellyplus1-a8032ab12345",
+                    "mac": "A8032AB12345",
+                    "model": "SNSW-001X16EU",
+                    "gen": 2,
+                    "fw_id": "20230913-123456/v1.14.0",
+                    "ver": "1.14.0",
+                    "app": "Plus1",
+                    "auth_en": true,
+                    "auth_domain": "shellyplus1-a8032ab12345"
+                }"#,
+            ))
+            .with_priority(1)
+            .mount(&mock_server)
+            .await;
 
         Mock::given(method("GET"))
-            .and(path("/rpc/Shelly.GetStatus"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(status_response))
+            .respond_with(
+                ResponseTemplate::new(401).insert_header("WWW-Authenticate", DIGEST_CHALLENGE),
+            )
             .mount(&mock_server)
             .await;
 
         let client = ShellyClient::new(
             mock_server.uri(),
             Duration::from_secs(5),
-            None,
+            Some(("admin".to_string(), "wrong".to_string())),
             ShellyGeneration::Gen2,
         )
         .unwrap();
 
-        let status = client.get_status().await.unwrap();
-
-        match status {
-            ShellyStatus::Gen2(gen2_status) => {
-                assert!(gen2_status.switch_0.is_some());
-                let switch = gen2_status.switch_0.unwrap();
-                assert_eq!(switch.output, true);
-                assert_eq!(switch.apower, Some(15.5));
-                assert_eq!(switch.voltage, Some(230.1));
+        // Seed the client with an outdated nonce, as if the device had rebooted
+        *client.digest.lock().unwrap() = Some(DigestSession::new(
+            DigestChallenge::parse(
+                r#"Digest qop="auth", realm="shellyplus1-a8032ab12345", nonce="expired", algorithm=SHA-256"#,
+            )
+            .unwrap(),
+        ));
 
-                assert!(gen2_status.sys.is_some());
-                let sys = gen2_status.sys.unwrap();
-                assert_eq!(sys.uptime, 3600);
+        // A wrong password still fails after the single retry
+        let result = client.get_device_info().await;
+        assert!(result.unwrap_err().to_string().contains("HTTP 401"));
 
-                assert!(gen2_status.wifi.is_some());
-                let wifi = gen2_status.wifi.unwrap();
-                assert_eq!(wifi.sta_ip, Some("192.168.1.100".to_string()));
-                assert_eq!(wifi.rssi, Some(-65));
-            }
-            ShellyStatus::Gen1(_) => panic!("Expected Gen2 status"),
-        }
+        let client = ShellyClient {
+            auth: Some(("admin".to_string(), "secret".to_string())),
+            ..client
+        };
+        let info = client.get_device_info().await.unwrap();
+        assert_eq!(info.model, "SNSW-001X16EU");
     }
 
     #[tokio::test]
-    async fn test_authentication() {
+    async fn test_digest_stale_nonce_renewed() {
         let mock_server = MockServer::start().await;
 
+        // Only the fresh nonce is accepted, by the retry and later requests
         Mock::given(method("GET"))
             .and(path("/rpc/Shelly.GetDeviceInfo"))
-            .respond_with(ResponseTemplate::new(401))
+            .and(DigestMatcher {
+                nonce: "7f3a9c01",
+                password: "secret",
+            })
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{
+                    "name": "Test Shelly",
+                    "id": "shellyplus1-a8032ab12345",
+                    "mac": "A8032AB12345",
+                    "model": "SNSW-001X16EU",
+                    "gen": 2,
+                    "fw_id": "20230913-123456/v1.14.0",
+                    "ver": "1.14.0",
+                    "app": "Plus1",
+                    "auth_en": true,
+                    "auth_domain": "shellyplus1-a8032ab12345"
+                }"#,
+            ))
+            .with_priority(1)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        // The cached nonce expired: the device asks for the same credentials
+        // with a fresh nonce
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(401).insert_header(
+                "WWW-Authenticate",
+                r#"Digest qop="auth", realm="shellyplus1-a8032ab12345", nonce="7f3a9c01", algorithm=SHA-256, stale=true"#,
+            ))
+            .expect(1)
             .mount(&mock_server)
             .await;
 
         let client = ShellyClient::new(
             mock_server.uri(),
             Duration::from_secs(5),
-            None,
+            Some(("admin".to_string(), "secret".to_string())),
             ShellyGeneration::Gen2,
         )
         .unwrap();
+        *client.digest.lock().unwrap() = Some(DigestSession::new(
+            DigestChallenge::parse(DIGEST_CHALLENGE).unwrap(),
+        ));
 
-        let result = client.get_device_info().await;
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("HTTP 401"));
+        let info = client.get_device_info().await.unwrap();
+        assert_eq!(info.model, "SNSW-001X16EU");
+
+        // The fresh nonce is used up front from now on
+        client.get_device_info().await.unwrap();
     }
 
     #[tokio::test]
-    async fn test_get_gen1_status() {
+    async fn test_gen1_basic_authentication() {
         let mock_server = MockServer::start().await;
 
-        let status_response = r#"{
-            "relays": [{
-                "ison": true,
-                "has_timer": false,
-                "timer_started": 0,
-                "timer_duration": 0,
-                "timer_remaining": 0,
-                "source": "input"
-            }],
-            "meters": [{
-                "power": 23.45,
-                "is_valid": true,
-                "timestamp": 1234567890,
-                "counters": [1234.56, 0.0, 0.0],
-                "total": 1234.56
-            }],
-            "temperature": 25.5,
-            "overtemperature": false,
-            "wifi_sta": {
-                "connected": true,
-                "ssid": "TestNetwork",
-                "ip": "192.168.1.101",
-                "rssi": -60
-            },
-            "uptime": 7200
-        }"#;
-
         Mock::given(method("GET"))
             .and(path("/status"))
-            .respond_with(ResponseTemplate::new(200).set_body_string(status_response))
+            .and(header("authorization", "Basic YWRtaW46c2VjcmV0"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"uptime": 60}"#))
             .mount(&mock_server)
             .await;
 
         let client = ShellyClient::new(
             mock_server.uri(),
             Duration::from_secs(5),
-            None,
+            Some(("admin".to_string(), "secret".to_string())),
             ShellyGeneration::Gen1,
         )
         .unwrap();
 
-        let status = client.get_status().await.unwrap();
-
-        match status {
-            ShellyStatus::Gen1(gen1_status) => {
-                assert!(gen1_status.relays.is_some());
-                let relays = gen1_status.relays.unwrap();
-                assert_eq!(relays.len(), 1);
-                assert_eq!(relays[0].ison, true);
-
-                assert!(gen1_status.meters.is_some());
-                let meters = gen1_status.meters.unwrap();
-                assert_eq!(meters.len(), 1);
-                assert_eq!(meters[0].power, 23.45);
-                assert_eq!(meters[0].total, 1234.56);
-
-                assert_eq!(gen1_status.temperature, Some(25.5));
-                assert_eq!(gen1_status.uptime, Some(7200));
-
-                assert!(gen1_status.wifi_sta.is_some());
-                let wifi = gen1_status.wifi_sta.unwrap();
-                assert_eq!(wifi.connected, true);
-                assert_eq!(wifi.ip, Some("192.168.1.101".to_string()));
-                assert_eq!(wifi.rssi, -60);
-            }
+        match client.get_status().await.unwrap() {
+            ShellyStatus::Gen1(status) => assert_eq!(status.uptime, Some(60)),
             ShellyStatus::Gen2(_) => panic!("Expected Gen1 status"),
         }
     }
-
-    #[tokio::test]
-    async fn test_detect_generation() {
-        let mock_server = MockServer::start().await;
-
-        // Mock Gen2 device
-        Mock::given(method("GET"))
-            .and(path("/rpc/Shelly.GetDeviceInfo"))
-            .respond_with(ResponseTemplate::new(200))
-            .mount(&mock_server)
-            .await;
-
-        let generation =
-            ShellyClient::detect_generation(&mock_server.uri(), Duration::from_secs(5), None)
-                .await
-                .unwrap();
-
-        assert_eq!(generation, ShellyGeneration::Gen2);
-    }
 }
@@ -0,0 +1,293 @@
+//! Persistent WebSocket connection to the `/rpc` endpoint of a Gen2+ device.
+//!
+//! The device pushes `NotifyStatus` deltas and `NotifyEvent` input events
+//! over the connection once it has seen a request from us, so polls can be
+//! answered from memory and short button presses are no longer missed.
+
+use anyhow::{Result, anyhow, bail};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+use crate::rpc::{Incoming, PushedStatus, RpcChannel, RpcFrame};
+use crate::shelly::ComponentEvent;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How often the full status is requested again. Doubles as a keepalive:
+/// a connection that has not delivered anything for two intervals is dropped.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Status pushed over a WebSocket, either by a connection we keep open to
+/// the device or by one the device opened to us.
+#[derive(Debug)]
+pub struct WebSocketTransport {
+    state: Arc<Mutex<PushedStatus>>,
+    /// Task keeping an outbound connection alive, stopped on drop
+    task: Option<JoinHandle<()>>,
+}
+
+impl WebSocketTransport {
+    /// Connect to the device at `base_url` in the background, reconnecting
+    /// whenever the connection is lost.
+    pub fn connect(base_url: &str, auth: Option<(String, String)>, timeout: Duration) -> Self {
+        let url = format!("{}/rpc", base_url.replacen("http", "ws", 1));
+        let state = Arc::new(Mutex::new(PushedStatus::default()));
+        let task = tokio::spawn(run(url, auth, timeout, state.clone()));
+
+        Self {
+            state,
+            task: Some(task),
+        }
+    }
+
+    /// A transport fed by a connection the device opened to the exporter.
+    pub fn inbound() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(PushedStatus::default())),
+            task: None,
+        }
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        self.state.lock().unwrap().set_connected(connected);
+    }
+
+    /// Record a full `Shelly.GetStatus` result.
+    pub fn replace(&self, status: Value) {
+        self.state.lock().unwrap().replace(status);
+    }
+
+    pub fn apply(&self, method: &str, params: Value) {
+        self.state.lock().unwrap().apply(method, params);
+    }
+
+    /// The latest full status, or `None` while disconnected.
+    pub fn status(&self) -> Option<Value> {
+        self.state.lock().unwrap().status()
+    }
+
+    pub fn take_events(&self) -> Vec<ComponentEvent> {
+        self.state.lock().unwrap().take_events()
+    }
+}
+
+impl Drop for WebSocketTransport {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+/// Keep a connection open, reconnecting with exponential backoff.
+async fn run(
+    url: String,
+    auth: Option<(String, String)>,
+    timeout: Duration,
+    state: Arc<Mutex<PushedStatus>>,
+) {
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        let mut synced = false;
+        let result = session(&url, auth.clone(), timeout, &state, &mut synced).await;
+        state.lock().unwrap().set_connected(false);
+
+        if synced {
+            backoff = INITIAL_BACKOFF;
+        }
+        match result {
+            Ok(()) => info!("WebSocket to {} closed, reconnecting", url),
+            Err(e) => warn!(
+                "WebSocket to {} failed: {}, retrying in {:?}",
+                url, e, backoff
+            ),
+        }
+
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Run a single connection until it is closed or fails. `synced` is set once
+/// the device has delivered a full status.
+async fn session(
+    url: &str,
+    auth: Option<(String, String)>,
+    timeout: Duration,
+    state: &Mutex<PushedStatus>,
+    synced: &mut bool,
+) -> Result<()> {
+    let (mut socket, _) = tokio::time::timeout(timeout, connect_async(url))
+        .await
+        .map_err(|_| anyhow!("connection timed out"))??;
+    debug!("WebSocket connected to {}", url);
+
+    let mut channel = RpcChannel::new(auth);
+    let mut last_frame = Instant::now();
+    let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = refresh.tick() => {
+                if last_frame.elapsed() > REFRESH_INTERVAL * 2 {
+                    bail!("no frames received for {:?}", last_frame.elapsed());
+                }
+                let request = channel.request("Shelly.GetStatus");
+                socket.send(Message::text(request)).await?;
+            }
+            message = socket.next() => {
+                let text = match message {
+                    None | Some(Ok(Message::Close(_))) => return Ok(()),
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e.into()),
+                };
+                last_frame = Instant::now();
+
+                let frame: RpcFrame = match serde_json::from_str(&text) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        debug!("Ignoring malformed frame from {}: {}", url, e);
+                        continue;
+                    }
+                };
+
+                match channel.receive(frame)? {
+                    Incoming::Notification { method, params } => {
+                        state.lock().unwrap().apply(&method, params)
+                    }
+                    Incoming::Response { result, .. } => {
+                        let mut state = state.lock().unwrap();
+                        state.replace(result);
+                        state.set_connected(true);
+                        *synced = true;
+                    }
+                    Incoming::Retry(request) => socket.send(Message::text(request)).await?,
+                    Incoming::Ignored => {}
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shelly::{Component, ShellyClient, ShellyGeneration, ShellyStatus};
+    use serde_json::json;
+    use tokio::net::TcpListener;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Stand-in for a device: answers `Shelly.GetStatus`, then pushes a delta
+    /// and an input event.
+    async fn serve_device(listener: TcpListener) {
+        // Polls made before the WebSocket is up arrive here as plain HTTP
+        // requests, which fail the handshake and are dropped
+        let mut socket = loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            if let Ok(socket) = tokio_tungstenite::accept_async(stream).await {
+                break socket;
+            }
+        };
+
+        let Some(Ok(Message::Text(text))) = socket.next().await else {
+            panic!("expected a request");
+        };
+        let request: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(request["method"], "Shelly.GetStatus");
+        let src = request["src"].as_str().unwrap();
+
+        let frames = [
+            json!({"id": request["id"], "src": "shellyplus1pm-test", "dst": src, "result": {
+                "switch:0": {"id": 0, "source": "init", "output": false, "apower": 0.0},
+                "input:0": {"id": 0, "state": false}
+            }}),
+            json!({"src": "shellyplus1pm-test", "dst": src, "method": "NotifyStatus", "params": {
+                "ts": 1700000000.0, "switch:0": {"id": 0, "output": true, "apower": 42.5}
+            }}),
+            json!({"src": "shellyplus1pm-test", "dst": src, "method": "NotifyEvent", "params": {
+                "ts": 1700000001.0,
+                "events": [{"component": "input:0", "id": 0, "event": "single_push", "ts": 1700000001.0}]
+            }}),
+        ];
+        for frame in frames {
+            socket.send(Message::text(frame.to_string())).await.unwrap();
+        }
+
+        // Keep the connection open until the client goes away
+        while let Some(Ok(_)) = socket.next().await {}
+    }
+
+    #[tokio::test]
+    async fn test_websocket_status_and_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve_device(listener));
+
+        let mut client = ShellyClient::new(
+            base_url,
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen2,
+        )
+        .unwrap();
+        client.enable_websocket(Duration::from_secs(5));
+
+        let mut status = None;
+        for _ in 0..50 {
+            if let Ok(ShellyStatus::Gen2(gen2)) = client.get_status().await
+                && !gen2.events.is_empty()
+            {
+                status = Some(gen2);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let status = status.expect("no pushed status received");
+
+        let Some(Component::Switch(switch)) = status.components.get("switch:0") else {
+            panic!("expected switch:0");
+        };
+        assert!(switch.output);
+        assert_eq!(switch.apower, Some(42.5));
+        assert_eq!(status.events[0].component, "input:0");
+        assert_eq!(status.events[0].event, "single_push");
+    }
+
+    #[tokio::test]
+    async fn test_websocket_falls_back_to_http() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/rpc/Shelly.GetStatus"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "switch:0": {"id": 0, "source": "init", "output": true}
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let mut client = ShellyClient::new(
+            mock_server.uri(),
+            Duration::from_secs(5),
+            None,
+            ShellyGeneration::Gen2,
+        )
+        .unwrap();
+        client.enable_websocket(Duration::from_secs(5));
+
+        let ShellyStatus::Gen2(status) = client.get_status().await.unwrap() else {
+            panic!("expected Gen2 status");
+        };
+        assert!(status.components.contains_key("switch:0"));
+        assert!(status.events.is_empty());
+    }
+}